@@ -0,0 +1,576 @@
+use super::*;
+
+use chrono::{DateTime, NaiveDate, Utc, Weekday};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    ChannelId, CommandDataOptionValue, CommandInteraction, CommandOptionType, CreateCommand,
+    CreateCommandOption, CreateEmbed, CreateEmbedFooter, CreateInteractionResponse,
+    CreateInteractionResponseMessage, CreateMessage, Http, Message, MessageId, UserId,
+};
+use serenity::prelude::*;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(30);
+const MAX_PER_ANNOUNCEMENT: usize = 10;
+
+/// An achievement from `ACHIEVEMENTS`, one `name=trigger Title` per line.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Achievement {
+    pub name: String,
+    pub trigger: AchievementTrigger,
+    pub title: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AchievementTrigger {
+    Total(ReportColumn, usize), // `messages>=1000`, all-time total of a column
+    SeriesRecord,               // `series_record`, the longest series the server has seen
+    FirstOfWeek,                // `first_of_week`, first message after Monday 00:00
+    TopOfPeriod(ReportColumn),  // `top:files`, first in a closed period of the main schedule
+}
+
+impl FromStr for AchievementTrigger {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "series_record" => return Ok(AchievementTrigger::SeriesRecord),
+            "first_of_week" => return Ok(AchievementTrigger::FirstOfWeek),
+            _ => {}
+        }
+        if let Some(column) = s.strip_prefix("top:") {
+            return Ok(AchievementTrigger::TopOfPeriod(column.parse()?));
+        }
+        match s.split_once(">=") {
+            Some((column, threshold)) => {
+                let threshold = threshold
+                    .parse()
+                    .map_err(|_| format!("invalid threshold `{threshold}`"))?;
+                Ok(AchievementTrigger::Total(column.parse()?, threshold))
+            }
+            None => Err(format!("unknown achievement trigger `{s}`")),
+        }
+    }
+}
+
+impl Achievement {
+    pub fn parse(line: &str) -> Result<Achievement, String> {
+        let (name, rest) = line
+            .split_once('=')
+            .ok_or_else(|| format!("expected `name=trigger Title`, got `{line}`"))?;
+        let rest = rest.trim();
+        let (trigger, title) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+        let name = name.trim().to_string();
+        let title = match title.trim() {
+            "" => name.clone(),
+            title => title.to_string(),
+        };
+        Ok(Achievement {
+            name,
+            trigger: trigger.parse()?,
+            title,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UnlockedAchievement {
+    pub name: String,
+    pub unlocked_at: DateTime<Utc>,
+    pub count: usize, // times it was earned, only the first one is announced
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Announcement {
+    pub user_id: UserId,
+    pub name: String,
+}
+
+/// Earned achievements and what their triggers need across periods, saved with the
+/// statistics.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Achievements {
+    pub lifetime: MessageStat, // everything counted, for the all-time totals
+    pub seeded: bool,          // the lifetime stat includes the archive
+    #[serde(default)]
+    pub seeded_totals: HashSet<String>, // total achievements already unlocked silently
+    pub unlocked: HashMap<UserId, Vec<UnlockedAchievement>>,
+    pub best_series: usize,
+    pub best_series_start: Option<MessageId>, // first message of the record series
+    pub week: Option<NaiveDate>,              // Monday of the newest message
+    pub announcements: VecDeque<Announcement>, // waiting for the cooldown
+    pub last_announced: Option<DateTime<Utc>>,
+}
+
+impl Achievements {
+    /// Builds the lifetime stat from the periods of the main schedule the first time.
+    /// Totals already reached are unlocked silently, for achievements added later too, nobody
+    /// gets a flood of old news.
+    pub fn seed<'a>(
+        &mut self,
+        periods: impl Iterator<Item = &'a MessageStat>,
+        achievements: &[Achievement],
+    ) {
+        if !self.seeded {
+            let mut lifetime = MessageStat::default();
+            for stat in periods {
+                lifetime.merge(&stat.snapshot());
+            }
+            self.best_series = lifetime
+                .personal_record
+                .values()
+                .map(|record| record.counter)
+                .max()
+                .unwrap_or(0);
+            self.lifetime = lifetime;
+            self.seeded = true;
+            tracing::info!(
+                "Seeded achievements from {} users",
+                self.lifetime.users().len()
+            );
+        }
+
+        let now = Utc::now();
+        for achievement in achievements {
+            if let AchievementTrigger::Total(column, threshold) = achievement.trigger {
+                if !self.seeded_totals.insert(achievement.name.clone()) {
+                    continue;
+                }
+                for user_id in self.lifetime.users() {
+                    let earned = self.lifetime_value(column, user_id) >= threshold;
+                    if earned && !self.has(user_id, &achievement.name) {
+                        self.unlock(user_id, achievement, now, false);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn record_message(&mut self, msg: &Message, tz: Tz, conf: &Config) {
+        self.lifetime.record_message(msg, tz, &conf.streak_rules);
+        let user_id = msg.author.id;
+        let now = timestamp_to_utc(&msg.timestamp);
+        self.check_totals(user_id, conf, now);
+
+        let monday = now
+            .with_timezone(&tz)
+            .date_naive()
+            .week(Weekday::Mon)
+            .first_day();
+        // Late messages, from a backfill or a delayed event, don't take the week back
+        let new_week = self.week.is_some_and(|week| monday > week);
+        if self.week.is_none_or(|week| monday > week) {
+            self.week = Some(monday);
+        }
+
+        let running = self
+            .lifetime
+            .current_by_channel
+            .get(&msg.channel_id)
+            .filter(|streak| streak.user_id == user_id)
+            .map(|streak| (streak.counter, streak.first_message));
+        let new_record = match running {
+            Some((counter, first_message)) if counter > self.best_series.max(1) => {
+                self.best_series = counter;
+                // A record series keeps breaking its own record, that's one achievement
+                let new_series = self.best_series_start != first_message;
+                self.best_series_start = first_message;
+                new_series
+            }
+            _ => false,
+        };
+
+        for achievement in conf.achievements.iter() {
+            let earned = match achievement.trigger {
+                AchievementTrigger::FirstOfWeek => new_week,
+                AchievementTrigger::SeriesRecord => new_record,
+                _ => false,
+            };
+            if earned {
+                self.unlock(user_id, achievement, now, true);
+            }
+        }
+    }
+
+    pub fn record_reaction(
+        &mut self,
+        user_id: UserId,
+        author_id: UserId,
+        channel_id: ChannelId,
+        added: bool,
+        conf: &Config,
+    ) {
+        self.lifetime
+            .record_reaction(user_id, author_id, channel_id, added);
+        if added {
+            self.check_totals(user_id, conf, Utc::now());
+            self.check_totals(author_id, conf, Utc::now());
+        }
+    }
+
+    pub fn record_edit(&mut self, author_id: UserId, channel_id: ChannelId, conf: &Config) {
+        self.lifetime.record_edit(author_id, channel_id);
+        self.check_totals(author_id, conf, Utc::now());
+    }
+
+    pub fn record_deletion(&mut self, author_id: UserId, channel_id: ChannelId, conf: &Config) {
+        self.lifetime.record_deletion(author_id, channel_id);
+        self.check_totals(author_id, conf, Utc::now());
+    }
+
+    /// The top user of each `top:` column in the closed period earns it.
+    pub fn close_period(&mut self, stat: &MessageStat, conf: &Config) {
+        let now = Utc::now();
+        for achievement in conf.achievements.iter() {
+            if let AchievementTrigger::TopOfPeriod(column) = achievement.trigger {
+                if let Some((user_id, _)) = top_users(stat, column, 1).first() {
+                    self.unlock(*user_id, achievement, now, true);
+                }
+            }
+        }
+    }
+
+    /// All-time value of a column, the series column counts the running series too.
+    pub fn lifetime_value(&self, column: ReportColumn, user_id: UserId) -> usize {
+        let value = self.lifetime.value(column, &user_id);
+        match column {
+            ReportColumn::Series => self
+                .lifetime
+                .current_by_channel
+                .values()
+                .filter(|streak| streak.user_id == user_id)
+                .map(|streak| streak.counter)
+                .fold(value, usize::max),
+            _ => value,
+        }
+    }
+
+    fn check_totals(&mut self, user_id: UserId, conf: &Config, now: DateTime<Utc>) {
+        for achievement in conf.achievements.iter() {
+            if let AchievementTrigger::Total(column, threshold) = achievement.trigger {
+                let earned = self.lifetime_value(column, user_id) >= threshold;
+                if earned && !self.has(user_id, &achievement.name) {
+                    self.unlock(user_id, achievement, now, true);
+                }
+            }
+        }
+    }
+
+    pub fn has(&self, user_id: UserId, name: &str) -> bool {
+        self.unlocked
+            .get(&user_id)
+            .is_some_and(|unlocked| unlocked.iter().any(|a| a.name == name))
+    }
+
+    /// Earning it again only counts, the announcement is for the first time.
+    fn unlock(
+        &mut self,
+        user_id: UserId,
+        achievement: &Achievement,
+        now: DateTime<Utc>,
+        announce: bool,
+    ) {
+        let unlocked = self.unlocked.entry(user_id).or_default();
+        if let Some(earned) = unlocked.iter_mut().find(|a| a.name == achievement.name) {
+            earned.count += 1;
+            return;
+        }
+        unlocked.push(UnlockedAchievement {
+            name: achievement.name.clone(),
+            unlocked_at: now,
+            count: 1,
+        });
+        tracing::info!("User {} unlocked achievement {}", user_id, achievement.name);
+        if announce {
+            self.announcements.push_back(Announcement {
+                user_id,
+                name: achievement.name.clone(),
+            });
+        }
+    }
+
+    pub fn purge_user(&mut self, user_id: UserId) -> bool {
+        let mut purged = self.lifetime.purge_user(user_id);
+        purged |= self.unlocked.remove(&user_id).is_some();
+        let queued = self.announcements.len();
+        self.announcements.retain(|a| a.user_id != user_id);
+        purged || self.announcements.len() != queued
+    }
+
+    pub fn purge_channel(&mut self, channel_id: ChannelId) -> bool {
+        self.lifetime.purge_channel(channel_id)
+    }
+}
+
+/// Seeds the lifetime stat from the archive and the running period of the main schedule
+/// once, and the totals of newly configured achievements at every start.
+pub fn seed_achievements(stat: &mut Stat, conf: &Config) {
+    let main = &conf.report_schedules[0].name;
+    let archive = match stat.achievements.seeded {
+        true => vec![],
+        false => load_archive(&conf.archive_file, main),
+    };
+    let running = stat.periods.get(main).map(|period| &period.message_stat);
+    stat.achievements.seed(
+        archive
+            .iter()
+            .map(|period| &period.message_stat)
+            .chain(running),
+        &conf.achievements,
+    );
+}
+
+/// Posts the queued announcements to `ACHIEVEMENTS_CHANNEL_ID`, at most one message per
+/// `ACHIEVEMENTS_COOLDOWN_SECS`.
+pub fn achievement_announcer(http: Arc<Http>, stat: Arc<Mutex<Stat>>, conf: Config) {
+    if conf.achievements.is_empty() {
+        return;
+    }
+    tokio::spawn(async move {
+        let cooldown = chrono::Duration::seconds(conf.achievements_cooldown_secs as i64);
+        let mut interval = tokio::time::interval(ANNOUNCE_INTERVAL);
+        loop {
+            interval.tick().await;
+            let mut stat_guard = stat.lock().await;
+            let achievements = &mut stat_guard.achievements;
+            let cooling_down = achievements
+                .last_announced
+                .is_some_and(|last| Utc::now() - last < cooldown);
+            if cooling_down || achievements.announcements.is_empty() {
+                continue;
+            }
+            let count = achievements.announcements.len().min(MAX_PER_ANNOUNCEMENT);
+            let batch = achievements
+                .announcements
+                .drain(..count)
+                .collect::<Vec<Announcement>>();
+            drop(stat_guard);
+
+            let mut lines = vec![];
+            for announcement in batch.iter() {
+                let title = conf
+                    .achievements
+                    .iter()
+                    .find(|achievement| achievement.name == announcement.name)
+                    .map(|achievement| achievement.title.clone());
+                if let Some(title) = title {
+                    let name = get_user_name(&announcement.user_id, &http, &conf).await;
+                    lines.push(format!("🏆 {name} unlocked **{title}**"));
+                }
+            }
+            let sent = match lines.is_empty() {
+                true => Ok(()),
+                false => conf
+                    .achievements_channel_id
+                    .send_message(&http, CreateMessage::new().content(lines.join("\n")))
+                    .await
+                    .map(|_| ()),
+            };
+
+            let mut stat_guard = stat.lock().await;
+            let achievements = &mut stat_guard.achievements;
+            match sent {
+                Ok(()) => achievements.last_announced = Some(Utc::now()),
+                Err(e) => {
+                    tracing::error!("Error announcing achievements: {:?}", e);
+                    for announcement in batch.into_iter().rev() {
+                        achievements.announcements.push_front(announcement);
+                    }
+                }
+            }
+        }
+    });
+}
+
+pub fn achievements_command() -> CreateCommand {
+    CreateCommand::new("achievements")
+        .description("Earned achievements")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "The user, you by default",
+        ))
+}
+
+pub async fn run_achievements_command(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+) {
+    if conf.achievements.is_empty() {
+        respond_ephemeral(ctx, command, "No achievements are configured").await;
+        return;
+    }
+    let user_id = match command.data.options.first().map(|option| &option.value) {
+        Some(CommandDataOptionValue::User(user_id)) => *user_id,
+        _ => command.user.id,
+    };
+
+    let stat_guard = stat.lock().await;
+    let lines = achievement_lines(&stat_guard.achievements, conf, user_id);
+    let unlocked = stat_guard
+        .achievements
+        .unlocked
+        .get(&user_id)
+        .map(|unlocked| unlocked.len())
+        .unwrap_or(0);
+    drop(stat_guard);
+
+    let name = get_user_name(&user_id, &ctx.http, conf).await;
+    let embed = CreateEmbed::new()
+        .title(format!("Achievements of {name}"))
+        .description(lines.join("\n"))
+        .colour(0xf1c40f)
+        .footer(CreateEmbedFooter::new(format!(
+            "{unlocked} of {} unlocked",
+            conf.achievements.len()
+        )));
+    let response = CreateInteractionResponseMessage::new().embed(embed);
+    if let Err(why) = command
+        .create_response(&ctx.http, CreateInteractionResponse::Message(response))
+        .await
+    {
+        tracing::error!("Error responding to command: {why:?}");
+    }
+}
+
+/// Earned achievements with the date, the others locked with the progress of totals.
+fn achievement_lines(achievements: &Achievements, conf: &Config, user_id: UserId) -> Vec<String> {
+    let unlocked = achievements
+        .unlocked
+        .get(&user_id)
+        .map(|unlocked| unlocked.as_slice())
+        .unwrap_or_default();
+    conf.achievements
+        .iter()
+        .map(
+            |achievement| match unlocked.iter().find(|a| a.name == achievement.name) {
+                Some(earned) => {
+                    let times = match earned.count > 1 {
+                        true => format!(" ×{}", earned.count),
+                        false => String::new(),
+                    };
+                    format!(
+                        "🏆 **{}**{times} — <t:{}:d>",
+                        achievement.title,
+                        earned.unlocked_at.timestamp()
+                    )
+                }
+                None => match achievement.trigger {
+                    AchievementTrigger::Total(column, threshold) => format!(
+                        "🔒 {} — {}/{threshold}",
+                        achievement.title,
+                        achievements.lifetime_value(column, user_id).min(threshold)
+                    ),
+                    _ => format!("🔒 {}", achievement.title),
+                },
+            },
+        )
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        let achievements = [
+            "hundred=messages>=3 Three messages",
+            "record=series_record Series champion",
+            "monday=first_of_week Early bird",
+            "archivist=top:files",
+        ];
+        Config {
+            achievements: achievements
+                .iter()
+                .map(|line| Achievement::parse(line).unwrap())
+                .collect(),
+            ..Config::default()
+        }
+    }
+
+    fn message(id: u64, author: u64, time: &str) -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(id);
+        msg.author.id = UserId::new(author);
+        msg.timestamp = serenity::all::Timestamp::parse(time).unwrap();
+        msg
+    }
+
+    #[test]
+    fn test_parse_achievement() {
+        let conf = config();
+        assert_eq!(
+            conf.achievements[0].trigger,
+            AchievementTrigger::Total(ReportColumn::Messages, 3)
+        );
+        assert_eq!(conf.achievements[0].title, "Three messages");
+        assert_eq!(
+            conf.achievements[3].trigger,
+            AchievementTrigger::TopOfPeriod(ReportColumn::Files)
+        );
+        assert_eq!(conf.achievements[3].title, "archivist");
+        assert!(Achievement::parse("x=likes>=5").is_err());
+        assert!(Achievement::parse("x=messages>=many").is_err());
+        assert!(Achievement::parse("no trigger").is_err());
+    }
+
+    #[test]
+    fn test_unlock_and_announce_once() {
+        let conf = config();
+        let mut achievements = Achievements::default();
+        let (alice, bob) = (UserId::new(1), UserId::new(2));
+        for msg in [
+            // Wednesday and Thursday
+            message(1, 1, "2025-03-05T10:00:00Z"),
+            message(2, 1, "2025-03-06T10:00:00Z"),
+            message(3, 1, "2025-03-06T10:01:00Z"),
+            message(4, 1, "2025-03-06T10:02:00Z"),
+            // Next Monday
+            message(5, 2, "2025-03-10T08:00:00Z"),
+            // A late message of the week before doesn't start the week again
+            message(6, 1, "2025-03-06T11:00:00Z"),
+            message(7, 1, "2025-03-10T09:00:00Z"),
+        ] {
+            achievements.record_message(&msg, chrono_tz::UTC, &conf);
+        }
+
+        assert!(achievements.has(alice, "hundred"));
+        assert!(achievements.has(alice, "record"));
+        assert!(achievements.has(bob, "monday"));
+        assert!(!achievements.has(alice, "monday"));
+        assert!(!achievements.has(bob, "hundred"));
+        let announced = achievements
+            .announcements
+            .iter()
+            .map(|a| (a.user_id, a.name.as_str()))
+            .collect::<Vec<(UserId, &str)>>();
+        assert_eq!(
+            announced,
+            [(alice, "record"), (alice, "hundred"), (bob, "monday")]
+        );
+        assert_eq!(achievements.best_series, 4);
+
+        let lines = achievement_lines(&achievements, &conf, bob);
+        assert_eq!(lines[0], "🔒 Three messages — 1/3");
+
+        // A total achievement added later is unlocked without an announcement
+        let mut conf = conf;
+        conf.achievements
+            .push(Achievement::parse("two=messages>=2 Two messages").unwrap());
+        achievements.seeded = true;
+        achievements.seed(std::iter::empty(), &conf.achievements);
+        assert!(achievements.has(alice, "two"));
+        assert!(!achievements.has(bob, "two"));
+        assert_eq!(achievements.announcements.len(), 3);
+
+        assert!(achievements.purge_user(alice));
+        assert!(!achievements.has(alice, "hundred"));
+        assert_eq!(achievements.announcements.len(), 1);
+    }
+}
@@ -0,0 +1,565 @@
+use super::*;
+
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    CacheHttp, ChannelId, CommandDataOption, CommandDataOptionValue, CommandInteraction,
+    CommandOptionType, CreateCommand, CreateCommandOption, CreateInteractionResponseFollowup,
+    EditInteractionResponse, UserId,
+};
+use serenity::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::path::Path;
+
+/// Held while the archive file is read and rewritten or appended to, so a purge and a new
+/// period never overwrite each other.
+static ARCHIVE_LOCK: Mutex<()> = Mutex::const_new(());
+
+const ARCHIVE_USAGE: &str = "Usage: discord-bot archive <command> [--schedule NAME] [--file PATH]
+
+Commands:
+  list                 Archived periods
+  show [N | DATE]      Report of the period N periods ago (1 is the last one) or containing DATE
+  user <ID | NAME>     Statistics of a user across periods
+  totals               All-time totals per user";
+
+/// A closed period as it was reported, with the names the users had at the time so the
+/// archive is readable without Discord.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ArchivedPeriod {
+    pub schedule: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub names: HashMap<UserId, String>,
+    #[serde(default)]
+    pub channel_names: HashMap<ChannelId, String>,
+    pub message_stat: MessageStat,
+    pub table: Option<String>,
+}
+
+impl ArchivedPeriod {
+    pub async fn new(
+        schedule: &Schedule,
+        report: &PeriodReport,
+        cache_http: impl CacheHttp,
+        conf: &Config,
+    ) -> ArchivedPeriod {
+        let stat = &report.message_stat;
+        let mut names = HashMap::new();
+        for user_id in stat.users() {
+            names.insert(user_id, get_user_name(&user_id, &cache_http, conf).await);
+        }
+        let mut channel_names = HashMap::new();
+        let channels = stat
+            .personal_record
+            .values()
+            .map(|record| record.channel_id)
+            .collect::<BTreeSet<ChannelId>>();
+        for channel_id in channels {
+            let name = get_channel_name(&channel_id, &cache_http).await;
+            channel_names.insert(channel_id, name);
+        }
+        ArchivedPeriod {
+            schedule: schedule.name.clone(),
+            start: report.start,
+            end: report.end,
+            names,
+            channel_names,
+            message_stat: stat.clone(),
+            table: report.table.clone(),
+        }
+    }
+
+    pub fn name(&self, user_id: &UserId) -> String {
+        self.names
+            .get(user_id)
+            .cloned()
+            .unwrap_or_else(|| user_id.to_string())
+    }
+}
+
+/// Names as they were at the time of the period.
+impl NameResolver for ArchivedPeriod {
+    async fn user_name(&self, user_id: UserId) -> String {
+        self.name(&user_id)
+    }
+
+    async fn channel_name(&self, channel_id: ChannelId) -> String {
+        self.channel_names
+            .get(&channel_id)
+            .cloned()
+            .unwrap_or_else(|| channel_id.to_string())
+    }
+}
+
+/// Removes the users and channels from every archived period, the tables of the changed
+/// periods are rendered again without them.
+pub async fn purge_archive(
+    conf: &Config,
+    users: &BTreeSet<UserId>,
+    channels: &BTreeSet<ChannelId>,
+) {
+    let _archive = ARCHIVE_LOCK.lock().await;
+    let mut periods = read_json_lines::<ArchivedPeriod>(&conf.archive_file);
+    let mut changed = 0;
+    for period in periods.iter_mut() {
+        let mut purged = false;
+        for user_id in users {
+            purged |= period.message_stat.purge_user(*user_id);
+            purged |= period.names.remove(user_id).is_some();
+        }
+        for channel_id in channels {
+            purged |= period.message_stat.purge_channel(*channel_id);
+        }
+        if purged {
+            period.table =
+                format_report_table(&period.message_stat, None, &*period, &conf.report_layout)
+                    .await;
+            changed += 1;
+        }
+    }
+    if changed == 0 {
+        return;
+    }
+
+    let mut bytes = vec![];
+    for period in periods.iter() {
+        match serde_json::to_vec(period) {
+            Ok(line) => bytes.extend(line),
+            Err(e) => {
+                tracing::error!("Error serializing archived period: {}", e);
+                return;
+            }
+        }
+        bytes.push(b'\n');
+    }
+    match write_atomic(Path::new(&conf.archive_file), &bytes) {
+        Ok(()) => tracing::info!("Purged {} archived periods", changed),
+        Err(e) => tracing::error!("Error rewriting {}: {}", conf.archive_file, e),
+    }
+}
+
+/// Appends the period unless it is archived already, a report repeated after a crash
+/// must not count twice.
+pub async fn archive_period(conf: &Config, period: &ArchivedPeriod) {
+    let _archive = ARCHIVE_LOCK.lock().await;
+    let exists = read_json_lines::<ArchivedPeriod>(&conf.archive_file)
+        .iter()
+        .any(|archived| {
+            archived.schedule == period.schedule
+                && archived.start == period.start
+                && archived.end == period.end
+        });
+    if exists {
+        tracing::info!(
+            "Period {} {} is archived already",
+            period.schedule,
+            period.start
+        );
+        return;
+    }
+    if let Err(e) = append_json_line(&conf.archive_file, period) {
+        tracing::error!("Error archiving period: {}", e);
+    }
+}
+
+/// Archived periods of one schedule, oldest first.
+pub fn load_archive(archive_file: &str, schedule: &str) -> Vec<ArchivedPeriod> {
+    let mut periods = read_json_lines::<ArchivedPeriod>(archive_file)
+        .into_iter()
+        .filter(|period| period.schedule == schedule)
+        .collect::<Vec<ArchivedPeriod>>();
+    periods.sort_by_key(|period| period.start);
+    periods
+}
+
+/// The last period that ended by `start`, the one before a period starting then.
+pub fn previous_period(
+    periods: &[ArchivedPeriod],
+    start: DateTime<Utc>,
+) -> Option<&ArchivedPeriod> {
+    periods.iter().rev().find(|period| period.end <= start)
+}
+
+/// `1` is the last closed period, a date picks the period containing it.
+pub fn find_period<'a>(
+    periods: &'a [ArchivedPeriod],
+    selector: &str,
+    tz: Tz,
+) -> Option<&'a ArchivedPeriod> {
+    if let Ok(ago) = selector.parse::<usize>() {
+        return periods.len().checked_sub(ago).map(|index| &periods[index]);
+    }
+    let date = NaiveDate::parse_from_str(selector, "%Y-%m-%d").ok()?;
+    let time = tz
+        .from_local_datetime(&date.and_hms_opt(12, 0, 0)?)
+        .earliest()?;
+    periods
+        .iter()
+        .find(|period| period.start <= time && time < period.end)
+}
+
+pub fn format_archived_period(period: &ArchivedPeriod, tz: Tz) -> String {
+    let range = format_period(period.start, period.end, tz);
+    match &period.table {
+        Some(table) => format!("{range}\n{table}"),
+        None => format!("{range}\nNo data"),
+    }
+}
+
+pub fn format_period_list(periods: &[ArchivedPeriod], tz: Tz) -> String {
+    let rows = periods
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(ago, period)| {
+            vec![
+                (ago + 1).to_string(),
+                format_period(period.start, period.end, tz),
+                period
+                    .message_stat
+                    .messages_count
+                    .values()
+                    .sum::<usize>()
+                    .to_string(),
+                period.message_stat.messages_count.len().to_string(),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+    format_table(&["#", "Period", "Messages", "Users"], &rows)
+}
+
+pub fn format_user_history(periods: &[ArchivedPeriod], user_id: UserId, tz: Tz) -> String {
+    let rows = periods
+        .iter()
+        .filter_map(|period| {
+            let stat = &period.message_stat;
+            let messages = stat.messages_count.get(&user_id).copied().unwrap_or(0);
+            let streak = stat.personal_record.get(&user_id).map(|r| r.counter);
+            if messages == 0 && streak.is_none() {
+                return None;
+            }
+            Some(vec![
+                format_period(period.start, period.end, tz),
+                messages.to_string(),
+                streak.unwrap_or(0).to_string(),
+                stat.attachments_count
+                    .get(&user_id)
+                    .copied()
+                    .unwrap_or(0)
+                    .to_string(),
+            ])
+        })
+        .collect::<Vec<Vec<String>>>();
+    format_table(&["Period", "Messages", "Max series", "Files"], &rows)
+}
+
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct UserTotals {
+    pub name: String,
+    pub messages: usize,
+    pub attachments: usize,
+    pub best_streak: usize,
+    pub periods: usize,
+}
+
+/// All-time totals per user, most messages first. The latest known name wins.
+pub fn user_totals(periods: &[ArchivedPeriod]) -> Vec<UserTotals> {
+    let mut totals: HashMap<UserId, UserTotals> = HashMap::new();
+    for period in periods {
+        let stat = &period.message_stat;
+        for (user_id, messages) in stat.messages_count.iter() {
+            let entry = totals.entry(*user_id).or_default();
+            entry.name = period.name(user_id);
+            entry.messages += messages;
+            entry.periods += 1;
+            entry.attachments += stat.attachments_count.get(user_id).copied().unwrap_or(0);
+        }
+        for (user_id, record) in stat.personal_record.iter() {
+            let entry = totals.entry(*user_id).or_default();
+            entry.name = period.name(user_id);
+            entry.best_streak = entry.best_streak.max(record.counter);
+        }
+    }
+
+    let mut totals = totals.into_values().collect::<Vec<UserTotals>>();
+    totals.sort_by(|a, b| b.messages.cmp(&a.messages).then(a.name.cmp(&b.name)));
+    totals
+}
+
+pub fn format_user_totals(totals: &[UserTotals]) -> String {
+    let rows = totals
+        .iter()
+        .map(|total| {
+            vec![
+                total.name.clone(),
+                total.messages.to_string(),
+                total.best_streak.to_string(),
+                total.attachments.to_string(),
+                total.periods.to_string(),
+            ]
+        })
+        .collect::<Vec<Vec<String>>>();
+    format_table(
+        &["User", "Messages", "Max series", "Files", "Periods"],
+        &rows,
+    )
+}
+
+/// Matches a user ID or, case insensitive, a name the user had in any period.
+fn find_user(periods: &[ArchivedPeriod], query: &str) -> Option<UserId> {
+    if let Ok(id) = query.parse::<u64>() {
+        return Some(UserId::new(id));
+    }
+    let query = query.to_lowercase();
+    periods.iter().rev().find_map(|period| {
+        period
+            .names
+            .iter()
+            .find(|(_, name)| name.to_lowercase() == query)
+            .map(|(user_id, _)| *user_id)
+    })
+}
+
+pub fn archive_command() -> CreateCommand {
+    let schedule =
+        || CreateCommandOption::new(CommandOptionType::String, "schedule", "Report schedule");
+    CreateCommand::new("archive")
+        .description("Statistics of past periods")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "period", "A past report")
+                .add_sub_option(
+                    CreateCommandOption::new(
+                        CommandOptionType::Integer,
+                        "ago",
+                        "Periods ago, 1 is the last one",
+                    )
+                    .min_int_value(1),
+                )
+                .add_sub_option(CreateCommandOption::new(
+                    CommandOptionType::String,
+                    "date",
+                    "A date in the period, YYYY-MM-DD",
+                ))
+                .add_sub_option(schedule()),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "user",
+                "A user's statistics across periods",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::User, "user", "The user")
+                    .required(true),
+            )
+            .add_sub_option(schedule()),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "totals", "All-time totals")
+                .add_sub_option(schedule()),
+        )
+}
+
+pub async fn run_archive_command(ctx: &Context, command: &CommandInteraction, conf: &Config) {
+    let (subcommand, options) = match command.data.options.first() {
+        Some(CommandDataOption {
+            name,
+            value: CommandDataOptionValue::SubCommand(options),
+            ..
+        }) => (name.as_str(), options.as_slice()),
+        _ => return respond_ephemeral(ctx, command, "Unknown subcommand").await,
+    };
+    command.defer(&ctx.http).await.ok();
+    let string_option = |name: &str| {
+        options.iter().find_map(|option| match &option.value {
+            CommandDataOptionValue::String(value) if option.name == name => Some(value.clone()),
+            _ => None,
+        })
+    };
+    let schedule = string_option("schedule").unwrap_or(conf.report_schedules[0].name.clone());
+    let periods = load_archive(&conf.archive_file, &schedule);
+    let tz = conf.report_timezone();
+
+    let content = match subcommand {
+        "period" => {
+            let ago = options.iter().find_map(|option| match option.value {
+                CommandDataOptionValue::Integer(ago) if option.name == "ago" => {
+                    Some(ago.to_string())
+                }
+                _ => None,
+            });
+            let selector = string_option("date").or(ago).unwrap_or("1".to_string());
+            find_period(&periods, &selector, tz).map(|period| format_archived_period(period, tz))
+        }
+        "user" => {
+            let user_id = options.iter().find_map(|option| match option.value {
+                CommandDataOptionValue::User(user_id) => Some(user_id),
+                _ => None,
+            });
+            user_id.map(|user_id| format_user_history(&periods, user_id, tz))
+        }
+        "totals" => match periods.is_empty() {
+            true => None,
+            false => Some(format_user_totals(&user_totals(&periods))),
+        },
+        _ => None,
+    };
+
+    let parts = match content {
+        Some(content) => split_message(&content, 1900)
+            .into_iter()
+            .map(|part| format!("```\n{part}\n```"))
+            .collect::<Vec<String>>(),
+        None => vec![format!("Nothing archived for {schedule}")],
+    };
+    let mut parts = parts.into_iter();
+    if let Some(first) = parts.next() {
+        let builder = EditInteractionResponse::new().content(first);
+        if let Err(why) = command.edit_response(&ctx.http, builder).await {
+            tracing::error!("Error responding to command: {why:?}");
+        }
+    }
+    for part in parts {
+        let builder = CreateInteractionResponseFollowup::new().content(part);
+        if let Err(why) = command.create_followup(&ctx.http, builder).await {
+            tracing::error!("Error responding to command: {why:?}");
+        }
+    }
+}
+
+/// `discord-bot archive ...`, reads the archive file only.
+pub fn run_archive_cli(args: &[String]) -> i32 {
+    let args = CliArgs::parse(args, &[]);
+    let conf = init_offline_config();
+    let schedule = args
+        .value("schedule")
+        .unwrap_or(&conf.report_schedules[0].name);
+    let file = args.value("file").unwrap_or(&conf.archive_file);
+    let periods = load_archive(file, schedule);
+    let tz = conf.report_timezone();
+
+    let output = match args.positional.first().map(String::as_str) {
+        Some("list") => Some(format_period_list(&periods, tz)),
+        Some("show") => {
+            let selector = args.positional.get(1).map(String::as_str).unwrap_or("1");
+            find_period(&periods, selector, tz).map(|period| format_archived_period(period, tz))
+        }
+        Some("user") => args
+            .positional
+            .get(1)
+            .and_then(|query| find_user(&periods, query))
+            .map(|user_id| format_user_history(&periods, user_id, tz)),
+        Some("totals") => Some(format_user_totals(&user_totals(&periods))),
+        _ => {
+            eprintln!("{ARCHIVE_USAGE}");
+            return 2;
+        }
+    };
+
+    match output {
+        Some(output) => {
+            println!("{output}");
+            0
+        }
+        None => {
+            eprintln!("Not found in the {schedule} archive");
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn period(start: &str, end: &str, messages: &[(u64, usize)]) -> ArchivedPeriod {
+        let mut message_stat = MessageStat::default();
+        let mut names = HashMap::new();
+        for (user, count) in messages {
+            message_stat
+                .messages_count
+                .insert(UserId::new(*user), *count);
+            names.insert(UserId::new(*user), format!("user{user}"));
+        }
+        ArchivedPeriod {
+            schedule: "weekly".to_string(),
+            start: start.parse().unwrap(),
+            end: end.parse().unwrap(),
+            names,
+            channel_names: HashMap::new(),
+            message_stat,
+            table: Some("table".to_string()),
+        }
+    }
+
+    fn periods() -> Vec<ArchivedPeriod> {
+        vec![
+            period(
+                "2025-03-02T00:00:00Z",
+                "2025-03-09T00:00:00Z",
+                &[(1, 5), (2, 3)],
+            ),
+            period("2025-03-09T00:00:00Z", "2025-03-16T00:00:00Z", &[(2, 4)]),
+        ]
+    }
+
+    #[tokio::test]
+    async fn test_purge_archive() {
+        let dir = std::env::temp_dir().join(format!("archive-{}", uuid::Uuid::new_v4()));
+        let conf = Config {
+            archive_file: dir.join("archive.jsonl").to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        for period in periods() {
+            archive_period(&conf, &period).await;
+        }
+
+        purge_archive(&conf, &BTreeSet::from([UserId::new(2)]), &BTreeSet::new()).await;
+        let purged = load_archive(&conf.archive_file, "weekly");
+        assert_eq!(purged.len(), periods().len());
+        for period in purged.iter() {
+            assert!(!period.message_stat.users().contains(&UserId::new(2)));
+            assert!(!period.names.contains_key(&UserId::new(2)));
+            assert!(!period.table.as_deref().unwrap_or("").contains("user2"));
+        }
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_find_period() {
+        let periods = periods();
+        let start = |p: Option<&ArchivedPeriod>| p.map(|p| p.start.to_rfc3339());
+        let last = find_period(&periods, "1", Tz::UTC);
+        assert_eq!(start(last).as_deref(), Some("2025-03-09T00:00:00+00:00"));
+        let by_date = find_period(&periods, "2025-03-05", Tz::UTC);
+        assert_eq!(start(by_date).as_deref(), Some("2025-03-02T00:00:00+00:00"));
+        assert!(find_period(&periods, "3", Tz::UTC).is_none());
+        assert!(find_period(&periods, "2025-04-01", Tz::UTC).is_none());
+    }
+
+    #[test]
+    fn test_user_totals() {
+        let totals = user_totals(&periods());
+        assert_eq!(totals[0].name, "user2");
+        assert_eq!((totals[0].messages, totals[0].periods), (7, 2));
+        assert_eq!((totals[1].messages, totals[1].periods), (5, 1));
+        assert_eq!(find_user(&periods(), "USER1"), Some(UserId::new(1)));
+    }
+
+    #[tokio::test]
+    async fn test_archive_skips_duplicates() {
+        let dir = std::env::temp_dir().join(format!("archive-{}", Uuid::new_v4()));
+        let conf = Config {
+            archive_file: dir.join("archive.jsonl").to_string_lossy().to_string(),
+            ..Config::default()
+        };
+        for period in periods().iter().chain(periods().iter()) {
+            archive_period(&conf, period).await;
+        }
+        assert_eq!(load_archive(&conf.archive_file, "weekly").len(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
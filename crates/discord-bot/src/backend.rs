@@ -0,0 +1,340 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use ollama_rs::error::OllamaError;
+use ollama_rs::generation::chat::request::ChatMessageRequest;
+use ollama_rs::generation::chat::{ChatMessage, ChatMessageResponse};
+use ollama_rs::Ollama;
+use serenity::all::{
+    ChannelId, CommandInteraction, CreateCommand, EditInteractionResponse, Permissions,
+};
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BackendError {
+    Timeout,
+    ModelMissing(String),
+    ConnectionRefused,
+    Other(String),
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BackendError::Timeout => write!(f, "request timed out"),
+            BackendError::ModelMissing(model) => write!(f, "model {model} is not pulled"),
+            BackendError::ConnectionRefused => write!(f, "connection refused"),
+            BackendError::Other(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl BackendError {
+    pub fn from_ollama(err: OllamaError, model: &str) -> BackendError {
+        match err {
+            OllamaError::ReqwestError(e) if e.is_timeout() => BackendError::Timeout,
+            OllamaError::ReqwestError(e) if e.is_connect() => BackendError::ConnectionRefused,
+            OllamaError::Other(text) if text.contains("not found") => {
+                BackendError::ModelMissing(model.to_string())
+            }
+            e => BackendError::Other(format!("{e}: {e:?}")),
+        }
+    }
+}
+
+/// Latest known state of the Ollama server, refreshed by `backend_monitor`.
+#[derive(Debug, Clone, Default)]
+pub struct BackendStatus {
+    pub last_check: Option<DateTime<Utc>>,
+    pub reachable: bool,
+    pub available_models: Vec<String>,
+    pub warmed_model: Option<String>,
+    pub last_used_model: Option<String>,
+    pub last_error: Option<BackendError>,
+}
+
+impl BackendStatus {
+    /// Configured models in fallback order, the ones known to be missing go last.
+    pub fn models_to_try(&self, conf: &Config) -> Vec<String> {
+        let (mut available, missing): (Vec<String>, Vec<String>) = conf
+            .model_names
+            .iter()
+            .cloned()
+            .partition(|model| !self.reachable || self.is_available(model));
+        available.extend(missing);
+        available
+    }
+
+    pub fn is_available(&self, model: &str) -> bool {
+        self.available_models
+            .iter()
+            .any(|m| m == model || m.strip_suffix(":latest") == Some(model))
+    }
+
+    pub fn report(&self, conf: &Config) -> String {
+        let last_check = match self.last_check {
+            Some(time) => time.format("%Y-%m-%d %H:%M:%S UTC").to_string(),
+            None => "never".to_string(),
+        };
+        let mut lines = vec![
+            format!("Ollama: {}:{}", conf.ollama_host, conf.ollama_port),
+            format!(
+                "Status: {}",
+                match self.reachable {
+                    true => "reachable",
+                    false => "unreachable",
+                }
+            ),
+            format!("Last check: {last_check}"),
+            "Configured models:".to_string(),
+        ];
+        for model in conf.model_names.iter() {
+            let mark = match self.is_available(model) {
+                true => "available",
+                false => "missing",
+            };
+            lines.push(format!("- {model} ({mark})"));
+        }
+        lines.push(format!(
+            "Warmed up: {}",
+            self.warmed_model.as_deref().unwrap_or("-")
+        ));
+        lines.push(format!(
+            "Last used: {}",
+            self.last_used_model.as_deref().unwrap_or("-")
+        ));
+        if let Some(err) = &self.last_error {
+            lines.push(format!("Last error: {err}"));
+        }
+        lines.join("\n")
+    }
+}
+
+/// Everything the bot keeps about its LLM conversations.
+pub struct Llm {
+    pub status: Mutex<BackendStatus>,
+    pub feedback: Mutex<Feedback>,
+    pub transcripts: Mutex<TranscriptWriter>,
+    pub summarize_cooldowns: Mutex<HashMap<ChannelId, Instant>>,
+}
+
+impl Llm {
+    pub fn new(conf: &Config) -> Llm {
+        Llm {
+            status: Mutex::new(BackendStatus::default()),
+            feedback: Mutex::new(Feedback::load(conf)),
+            transcripts: Mutex::new(TranscriptWriter::new(conf)),
+            summarize_cooldowns: Mutex::new(HashMap::new()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ChatReply {
+    pub content: String,
+    pub model: String,
+}
+
+pub fn ollama_client(conf: &Config) -> Ollama {
+    Ollama::new(conf.ollama_host.clone(), conf.ollama_port)
+}
+
+/// Sends a single request to one model, bounded by `OLLAMA_TIMEOUT_SECS`.
+pub async fn send_chat(
+    conf: &Config,
+    model: &str,
+    messages: Vec<ChatMessage>,
+) -> Result<ChatMessageResponse, BackendError> {
+    let mut request = ChatMessageRequest::new(model.to_string(), messages);
+    if let Some(options) = conf.ollama_options.clone() {
+        request = request.options(options);
+    }
+    let timeout = Duration::from_secs(conf.ollama_timeout_secs);
+    match tokio::time::timeout(timeout, ollama_client(conf).send_chat_messages(request)).await {
+        Ok(Ok(response)) => Ok(response),
+        Ok(Err(e)) => Err(BackendError::from_ollama(e, model)),
+        Err(_) => Err(BackendError::Timeout),
+    }
+}
+
+/// Sends the chat to the first configured model that answers, falling back through
+/// `FALLBACK_MODELS` on failure. Every attempt is written to the transcript.
+pub async fn chat(
+    conf: &Config,
+    llm: &Llm,
+    purpose: &str,
+    messages: Vec<ChatMessage>,
+) -> Result<ChatReply, BackendError> {
+    let models = llm.status.lock().await.models_to_try(conf);
+    let mut last_error = BackendError::Other("No models configured".to_string());
+
+    for model in models {
+        let started = Instant::now();
+        let result = send_chat(conf, &model, messages.clone()).await;
+        let record = TranscriptRecord::new(
+            purpose,
+            &model,
+            &messages,
+            conf.ollama_options.clone(),
+            &result,
+            started.elapsed(),
+        );
+        llm.transcripts.lock().await.write(&record);
+
+        let mut status = llm.status.lock().await;
+        match result {
+            Ok(response) => {
+                status.last_used_model = Some(model.clone());
+                return Ok(ChatReply {
+                    content: response.message.content,
+                    model,
+                });
+            }
+            Err(err) => {
+                tracing::error!("Ollama error with model {model}: {err}");
+                status.last_error = Some(err.clone());
+                if err == BackendError::ConnectionRefused {
+                    // Other models live on the same server
+                    status.reachable = false;
+                    return Err(err);
+                }
+                last_error = err;
+            }
+        }
+    }
+
+    Err(last_error)
+}
+
+/// Lists the models on the server and warms up the preferred available one.
+pub async fn probe_backend(conf: &Config, llm: &Llm) {
+    let ollama = ollama_client(conf);
+    let timeout = Duration::from_secs(conf.ollama_timeout_secs);
+
+    let listed = match tokio::time::timeout(timeout, ollama.list_local_models()).await {
+        Ok(Ok(models)) => Ok(models.into_iter().map(|m| m.name).collect::<Vec<String>>()),
+        Ok(Err(e)) => Err(BackendError::from_ollama(e, "")),
+        Err(_) => Err(BackendError::Timeout),
+    };
+
+    let mut status = llm.status.lock().await;
+    status.last_check = Some(Utc::now());
+    let available_models = match listed {
+        Ok(models) => models,
+        Err(err) => {
+            tracing::warn!("Ollama is unavailable: {err}");
+            status.reachable = false;
+            status.warmed_model = None;
+            status.last_error = Some(err);
+            return;
+        }
+    };
+    status.reachable = true;
+    status.available_models = available_models;
+
+    let missing = conf
+        .model_names
+        .iter()
+        .filter(|model| !status.is_available(model))
+        .cloned()
+        .collect::<Vec<String>>();
+    if !missing.is_empty() {
+        tracing::warn!("Configured models are not pulled: {}", missing.join(", "));
+    }
+
+    let preferred = match status.models_to_try(conf).into_iter().next() {
+        Some(model) if status.is_available(&model) => model,
+        _ => {
+            status.last_error = Some(BackendError::ModelMissing(conf.model_names.join(", ")));
+            return;
+        }
+    };
+    drop(status);
+
+    // A request without messages only loads the model into memory, repeating it on every
+    // probe also keeps the model from being unloaded between conversations
+    tracing::info!("Warming up model {preferred}");
+    let request = ChatMessageRequest::new(preferred.clone(), vec![]);
+    let result = tokio::time::timeout(timeout, ollama.send_chat_messages(request)).await;
+
+    let mut status = llm.status.lock().await;
+    match result {
+        Ok(Ok(_)) => {
+            status.warmed_model = Some(preferred);
+        }
+        Ok(Err(e)) => {
+            let err = BackendError::from_ollama(e, &preferred);
+            tracing::warn!("Error warming up model {preferred}: {err}");
+            status.last_error = Some(err);
+        }
+        Err(_) => {
+            tracing::warn!("Timeout warming up model {preferred}");
+            status.last_error = Some(BackendError::Timeout);
+        }
+    }
+}
+
+pub fn backend_monitor(conf: Config, llm: Arc<Llm>) {
+    tokio::spawn(async move {
+        let interval = Duration::from_secs(conf.ollama_health_interval_secs);
+        loop {
+            probe_backend(&conf, &llm).await;
+            tokio::time::sleep(interval).await;
+        }
+    });
+}
+
+pub fn backend_command() -> CreateCommand {
+    CreateCommand::new("backend")
+        .description("Show the LLM backend status")
+        .default_member_permissions(Permissions::ADMINISTRATOR)
+}
+
+pub async fn run_backend_command(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    llm: &Llm,
+) {
+    // Refresh first so the admin sees the current state, not the one from the last tick
+    command.defer_ephemeral(&ctx.http).await.ok();
+    probe_backend(conf, llm).await;
+    let report = llm.status.lock().await.report(conf);
+    let builder = EditInteractionResponse::new().content(format!("```\n{report}\n```"));
+    if let Err(why) = command.edit_response(&ctx.http, builder).await {
+        tracing::error!("Error responding to command: {why:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_config(models: &[&str]) -> Config {
+        Config {
+            model_names: models.iter().map(|m| m.to_string()).collect(),
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn test_models_to_try_puts_missing_last() {
+        let conf = test_config(&["big", "medium", "small"]);
+        let status = BackendStatus {
+            reachable: true,
+            available_models: vec!["small:latest".to_string(), "medium".to_string()],
+            ..BackendStatus::default()
+        };
+        assert_eq!(status.models_to_try(&conf), ["medium", "small", "big"]);
+    }
+
+    #[test]
+    fn test_models_to_try_keeps_order_when_unreachable() {
+        let conf = test_config(&["big", "small"]);
+        let status = BackendStatus::default();
+        assert_eq!(status.models_to_try(&conf), ["big", "small"]);
+    }
+}
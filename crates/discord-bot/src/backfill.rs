@@ -0,0 +1,674 @@
+use super::*;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    ChannelId, ChannelType, CommandDataOptionValue, CommandInteraction, CommandOptionType,
+    CreateCommand, CreateCommandOption, CreateMessage, EditInteractionResponse, GetMessages,
+    GuildChannel, Http, LightMethod, Message, MessageId, Permissions, Request, Route, ThreadsData,
+    UserId,
+};
+use serenity::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::time::Duration;
+
+const DISCORD_EPOCH_MS: i64 = 1_420_070_400_000;
+const PAGE_SIZE: u8 = 100;
+
+/// Held for the whole backfill, a second one is refused. Dropped even if the backfill panics.
+static BACKFILL_LOCK: Mutex<()> = Mutex::const_new(());
+
+/// A time range, the end included.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interval {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+}
+
+/// When messages were already counted: live while the bot was connected, or by a backfill
+/// of the channel. A backfill skips these so running it again doesn't count twice.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Coverage {
+    pub initialized: bool,
+    pub live: Vec<Interval>,
+    pub channels: HashMap<ChannelId, Vec<Interval>>,
+}
+
+impl Coverage {
+    /// Statistics from before the coverage was kept are taken as counted live from the start
+    /// of the running periods, a backfill can't tell what they contain.
+    pub fn init(&mut self, stat_periods: &HashMap<String, StatPeriod>, now: DateTime<Utc>) {
+        if self.initialized {
+            return;
+        }
+        let start = stat_periods
+            .values()
+            .map(|period| period.collection_start)
+            .min();
+        if let Some(start) = start.filter(|start| *start < now) {
+            add_interval(&mut self.live, Interval { start, end: now });
+        }
+        self.initialized = true;
+    }
+
+    /// The bot is connected and receives messages from now on.
+    pub fn start_session(&mut self, now: DateTime<Utc>) {
+        add_interval(
+            &mut self.live,
+            Interval {
+                start: now,
+                end: now,
+            },
+        );
+    }
+
+    /// A message was counted live, the session lasts at least until it.
+    pub fn extend_session(&mut self, at: DateTime<Utc>) {
+        if let Some(session) = self.live.last_mut().filter(|session| session.start <= at) {
+            session.end = session.end.max(at);
+        }
+    }
+
+    pub fn covers(&self, channel_id: ChannelId, at: DateTime<Utc>) -> bool {
+        let within = |intervals: &Vec<Interval>| {
+            intervals
+                .iter()
+                .any(|interval| interval.start <= at && at <= interval.end)
+        };
+        within(&self.live) || self.channels.get(&channel_id).is_some_and(within)
+    }
+
+    pub fn add_channel(&mut self, channel_id: ChannelId, interval: Interval) {
+        add_interval(self.channels.entry(channel_id).or_default(), interval);
+    }
+
+    /// Forgets what ended before the running periods, nothing is added there anymore.
+    pub fn prune(&mut self, before: DateTime<Utc>) {
+        self.live.retain(|interval| interval.end >= before);
+        for intervals in self.channels.values_mut() {
+            intervals.retain(|interval| interval.end >= before);
+        }
+        self.channels.retain(|_, intervals| !intervals.is_empty());
+    }
+}
+
+/// Adds the interval keeping the list sorted and merging the overlapping ones.
+fn add_interval(intervals: &mut Vec<Interval>, interval: Interval) {
+    intervals.push(interval);
+    intervals.sort_by_key(|interval| interval.start);
+    let mut merged: Vec<Interval> = Vec::with_capacity(intervals.len());
+    for interval in intervals.drain(..) {
+        match merged.last_mut() {
+            Some(last) if interval.start <= last.end => last.end = last.end.max(interval.end),
+            _ => merged.push(interval),
+        }
+    }
+    *intervals = merged;
+}
+
+/// A running period as it was when the backfill started, with the backfilled messages
+/// counted apart so the live series are left alone.
+#[derive(Debug)]
+pub struct BackfillTarget {
+    pub schedule: Schedule,
+    pub collection_start: DateTime<Utc>,
+    pub collect_until: DateTime<Utc>,
+    pub stat: MessageStat,
+    pub earliest: Option<DateTime<Utc>>,
+}
+
+impl BackfillTarget {
+    /// In the period, or before its start but still after the boundary it started from,
+    /// which happens when the bot joined in the middle of a period.
+    fn contains(&self, at: DateTime<Utc>, tz: Tz) -> bool {
+        at < self.collect_until
+            && (at >= self.collection_start
+                || self.schedule.next_after(at, tz) == self.collect_until)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct BackfillCounts {
+    pub counted: usize,
+    pub already_counted: usize,
+    pub excluded: usize,
+}
+
+/// Feeds the messages of one channel in timestamp order into the periods they belong to.
+pub fn backfill_messages(
+    targets: &mut [BackfillTarget],
+    mut messages: Vec<Message>,
+    coverage: &Coverage,
+    opted_out: &BTreeSet<UserId>,
+    conf: &Config,
+) -> BackfillCounts {
+    messages.sort_by_key(|msg| (msg.timestamp.unix_timestamp(), msg.id));
+    let mut counts = BackfillCounts::default();
+    for msg in messages.iter() {
+        let at = timestamp_to_utc(&msg.timestamp);
+        if conf.stat_filter.excludes_author(msg) || opted_out.contains(&msg.author.id) {
+            counts.excluded += 1;
+            continue;
+        }
+        if coverage.covers(msg.channel_id, at) {
+            counts.already_counted += 1;
+            continue;
+        }
+        let tz = conf.guild_timezone(msg.guild_id);
+        let mut counted = false;
+        for target in targets
+            .iter_mut()
+            .filter(|target| target.contains(at, conf.report_timezone()))
+        {
+            target.stat.record_message(msg, tz, &conf.streak_rules);
+            target.earliest = Some(target.earliest.map_or(at, |earliest| earliest.min(at)));
+            counted = true;
+        }
+        match counted {
+            true => counts.counted += 1,
+            false => counts.excluded += 1,
+        }
+    }
+    counts
+}
+
+/// Adds the backfilled counts to the running periods and marks the channels as covered.
+/// Nothing is added if a period closed meanwhile, the backfill has to run again.
+///
+/// Backfilled messages award no achievements and break no hall of fame records, those only
+/// follow live messages. The lifetime totals include them, a total they reach unlocks with
+/// the user's next live message.
+pub fn apply_backfill(
+    stat: &mut Stat,
+    targets: Vec<BackfillTarget>,
+    channels: &[ChannelId],
+    range: Interval,
+    conf: &Config,
+) -> Result<(), String> {
+    for target in targets.iter() {
+        let period = stat.periods.get(&target.schedule.name);
+        if period.map(|period| period.collect_until) != Some(target.collect_until) {
+            return Err(format!(
+                "the {} period closed during the backfill",
+                target.schedule.name
+            ));
+        }
+    }
+    let main = conf.report_schedules.first().map(|main| &main.name);
+    for target in targets {
+        let backfilled = target.stat.snapshot();
+        if let Some(period) = stat.periods.get_mut(&target.schedule.name) {
+            period.message_stat.merge(&backfilled);
+            if let Some(earliest) = target.earliest {
+                period.collection_start = period.collection_start.min(earliest);
+            }
+        }
+        if main == Some(&target.schedule.name) {
+            stat.achievements.lifetime.merge(&backfilled);
+        }
+    }
+    for channel_id in channels {
+        stat.coverage.add_channel(*channel_id, range);
+    }
+    let oldest = stat
+        .periods
+        .values()
+        .map(|period| period.collection_start)
+        .min();
+    if let Some(oldest) = oldest {
+        stat.coverage.prune(oldest);
+    }
+    Ok(())
+}
+
+/// The first ID Discord could give a message sent at `at`.
+fn snowflake_at(at: DateTime<Utc>) -> MessageId {
+    let ms = (at.timestamp_millis() - DISCORD_EPOCH_MS).max(1) as u64;
+    MessageId::new(ms << 22)
+}
+
+/// Pages through the channel from `range.start` on, oldest first, waiting between pages.
+async fn fetch_history(
+    http: &Http,
+    channel_id: ChannelId,
+    range: Interval,
+    page_delay: Duration,
+) -> serenity::Result<Vec<Message>> {
+    let mut messages = vec![];
+    let mut after = snowflake_at(range.start);
+    loop {
+        let request = GetMessages::new().after(after).limit(PAGE_SIZE);
+        let mut page = channel_id.messages(http, request).await?;
+        page.sort_by_key(|msg| msg.id);
+        let full = page.len() == PAGE_SIZE as usize;
+        let last = match page.last() {
+            Some(last) => last.id,
+            None => break,
+        };
+        let past_end = page
+            .last()
+            .is_some_and(|msg| timestamp_to_utc(&msg.timestamp) > range.end);
+        messages.extend(
+            page.into_iter()
+                .filter(|msg| timestamp_to_utc(&msg.timestamp) <= range.end),
+        );
+        if !full || past_end {
+            break;
+        }
+        after = last;
+        tokio::time::sleep(page_delay).await;
+    }
+    Ok(messages)
+}
+
+/// Public threads of the channel archived since `since`, newest first. Serenity sends
+/// `before` as a number where Discord expects a timestamp, so the request is made here.
+async fn archived_threads(
+    http: &Http,
+    channel_id: ChannelId,
+    since: DateTime<Utc>,
+    page_delay: Duration,
+) -> serenity::Result<Vec<GuildChannel>> {
+    let archived_at = |thread: &GuildChannel| {
+        let metadata = thread.thread_metadata.as_ref();
+        metadata
+            .and_then(|metadata| metadata.archive_timestamp)
+            .map(|at| timestamp_to_utc(&at))
+    };
+    let mut threads = vec![];
+    let mut before: Option<DateTime<Utc>> = None;
+    loop {
+        let route = Route::ChannelArchivedPublicThreads { channel_id };
+        let params = before.map(|before| vec![("before", before.to_rfc3339())]);
+        let request = Request::new(route, LightMethod::Get).params(params);
+        let page: ThreadsData = http.fire(request).await?;
+        let oldest = page.threads.iter().filter_map(archived_at).min();
+        threads.extend(
+            page.threads
+                .into_iter()
+                .filter(|thread| archived_at(thread).is_none_or(|at| at >= since)),
+        );
+        // A thread archived before the start has no messages to backfill
+        match oldest {
+            Some(oldest) if page.has_more && oldest >= since => before = Some(oldest),
+            _ => break,
+        }
+        tokio::time::sleep(page_delay).await;
+    }
+    Ok(threads)
+}
+
+/// Text channels with their active threads and the threads archived since `since`, those
+/// the filters allow.
+async fn backfill_channels(
+    http: &Http,
+    conf: &Config,
+    channels: &Mutex<ChannelCache>,
+    since: DateTime<Utc>,
+) -> serenity::Result<Vec<(ChannelId, String)>> {
+    let mut found = vec![];
+    let page_delay = Duration::from_millis(conf.backfill_page_delay_ms);
+    let guild_channels = conf.guild_id.channels(http).await?;
+    for channel in guild_channels.values() {
+        if matches!(channel.kind, ChannelType::Text | ChannelType::News) {
+            found.push((channel.id, channel.name.clone()));
+        }
+        if !matches!(
+            channel.kind,
+            ChannelType::Text | ChannelType::News | ChannelType::Forum
+        ) {
+            continue;
+        }
+        match archived_threads(http, channel.id, since, page_delay).await {
+            Ok(threads) => found.extend(
+                threads
+                    .into_iter()
+                    .map(|thread| (thread.id, thread.name.clone())),
+            ),
+            Err(e) => tracing::warn!(
+                "Error listing the archived threads of {}: {:?}",
+                channel.id,
+                e
+            ),
+        }
+    }
+    for thread in conf.guild_id.get_active_threads(http).await?.threads {
+        found.push((thread.id, thread.name.clone()));
+    }
+    let mut allowed = vec![];
+    for (channel_id, name) in found {
+        let guild_id = Some(conf.guild_id);
+        if stat_channel_allowed(http, conf, channels, channel_id, guild_id).await {
+            allowed.push((channel_id, name));
+        }
+    }
+    // A thread archived while the list is fetched shows up twice
+    allowed.sort();
+    allowed.dedup_by_key(|(channel_id, _)| *channel_id);
+    Ok(allowed)
+}
+
+/// `YYYY-MM-DD` or `YYYY-MM-DD HH:MM` in the report timezone.
+fn parse_local_time(text: &str, tz: Tz) -> Option<DateTime<Utc>> {
+    let text = text.trim();
+    let naive = NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M")
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(text, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })?;
+    tz.from_local_datetime(&naive)
+        .earliest()
+        .map(|local| local.with_timezone(&Utc))
+}
+
+pub fn backfill_command() -> CreateCommand {
+    let time = |name: &str, description: &str| {
+        CreateCommandOption::new(CommandOptionType::String, name, description)
+    };
+    CreateCommand::new("backfill")
+        .description("Count the messages sent while the bot was away")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .add_option(time("from", "Start, YYYY-MM-DD [HH:MM]").required(true))
+        .add_option(time("to", "End, YYYY-MM-DD [HH:MM], now by default"))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::Channel,
+            "channel",
+            "Only this channel",
+        ))
+}
+
+pub async fn run_backfill_command(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+    channels: &Mutex<ChannelCache>,
+) {
+    let option = |name: &str| {
+        command
+            .data
+            .options
+            .iter()
+            .find(|option| option.name == name)
+            .map(|option| &option.value)
+    };
+    let tz = conf.report_timezone();
+    let now = Utc::now();
+    let time = |name: &str| match option(name) {
+        Some(CommandDataOptionValue::String(text)) => Some(parse_local_time(text, tz)),
+        _ => None,
+    };
+    let range = match (time("from"), time("to")) {
+        (Some(Some(start)), None) => Interval { start, end: now },
+        (Some(Some(start)), Some(Some(end))) => Interval {
+            start,
+            end: end.min(now),
+        },
+        _ => {
+            respond_ephemeral(ctx, command, "Expected times as YYYY-MM-DD [HH:MM]").await;
+            return;
+        }
+    };
+    if range.start >= range.end {
+        respond_ephemeral(ctx, command, "The start has to be before the end").await;
+        return;
+    }
+    if let Some(CommandDataOptionValue::Channel(channel_id)) = option("channel") {
+        let allowed =
+            stat_channel_allowed(&ctx.http, conf, channels, *channel_id, command.guild_id).await;
+        if !allowed {
+            respond_ephemeral(ctx, command, "This channel is left out of the statistics").await;
+            return;
+        }
+    }
+
+    let _running = match BACKFILL_LOCK.try_lock() {
+        Ok(running) => running,
+        Err(_) => {
+            respond_ephemeral(ctx, command, "A backfill is already running").await;
+            return;
+        }
+    };
+
+    command.defer(&ctx.http).await.ok();
+    let summary = match option("channel") {
+        Some(CommandDataOptionValue::Channel(channel_id)) => {
+            let name = get_channel_name(channel_id, &ctx.http).await;
+            let targets = vec![(*channel_id, name)];
+            run_backfill(ctx, command, conf, stat, channels, targets, range).await
+        }
+        _ => match backfill_channels(&ctx.http, conf, channels, range.start).await {
+            Ok(targets) => run_backfill(ctx, command, conf, stat, channels, targets, range).await,
+            Err(e) => format!("Error listing the channels: {e}"),
+        },
+    };
+
+    tracing::info!("Backfill: {}", summary);
+    let builder = EditInteractionResponse::new().content(&summary);
+    if command.edit_response(&ctx.http, builder).await.is_err() {
+        // The interaction expires after 15 minutes, a long backfill outlives it
+        let message = CreateMessage::new().content(summary);
+        if let Err(e) = command.channel_id.send_message(&ctx.http, message).await {
+            tracing::error!("Error sending the backfill summary: {:?}", e);
+        }
+    }
+}
+
+async fn run_backfill(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+    channel_cache: &Mutex<ChannelCache>,
+    channels: Vec<(ChannelId, String)>,
+    range: Interval,
+) -> String {
+    let stat_guard = stat.lock().await;
+    let mut targets = conf
+        .report_schedules
+        .iter()
+        .filter_map(|schedule| {
+            let period = stat_guard.periods.get(&schedule.name)?;
+            Some(BackfillTarget {
+                schedule: schedule.clone(),
+                collection_start: period.collection_start,
+                collect_until: period.collect_until,
+                stat: MessageStat::default(),
+                earliest: None,
+            })
+        })
+        .collect::<Vec<BackfillTarget>>();
+    let opted_out = stat_guard.opted_out.clone();
+    drop(stat_guard);
+
+    let page_delay = Duration::from_millis(conf.backfill_page_delay_ms);
+    let mut total = BackfillCounts::default();
+    let mut done = vec![];
+    for (index, (channel_id, name)) in channels.iter().enumerate() {
+        let progress = format!(
+            "Backfilling #{name} ({}/{}), {} messages counted so far",
+            index + 1,
+            channels.len(),
+            total.counted
+        );
+        let builder = EditInteractionResponse::new().content(progress);
+        command.edit_response(&ctx.http, builder).await.ok();
+
+        let messages = match fetch_history(&ctx.http, *channel_id, range, page_delay).await {
+            Ok(messages) => messages,
+            Err(e) => {
+                tracing::warn!("Error reading the history of {}: {:?}", channel_id, e);
+                continue;
+            }
+        };
+        let parent_id = ChannelCache::thread_parent(channel_cache, &ctx.http, *channel_id).await;
+        if let Some(parent_id) = parent_id {
+            for target in targets.iter_mut() {
+                target.stat.thread_parents.insert(*channel_id, parent_id);
+            }
+        }
+        // The coverage is read fresh, a live session may have started meanwhile
+        let stat_guard = stat.lock().await;
+        let counts = backfill_messages(
+            &mut targets,
+            messages,
+            &stat_guard.coverage,
+            &opted_out,
+            conf,
+        );
+        drop(stat_guard);
+        total.counted += counts.counted;
+        total.already_counted += counts.already_counted;
+        total.excluded += counts.excluded;
+        done.push(*channel_id);
+    }
+
+    let mut stat_guard = stat.lock().await;
+    if let Err(e) = apply_backfill(&mut stat_guard, targets, &done, range, conf) {
+        return format!("Backfill discarded, {e}. Run it again");
+    }
+    save_stat(stat_guard, conf).await;
+    let period = format_period(range.start, range.end, conf.report_timezone());
+    format!(
+        "Backfilled {} of {} channels from {period}: {} messages counted, \
+         {} already counted, {} left out",
+        done.len(),
+        channels.len(),
+        total.counted,
+        total.already_counted,
+        total.excluded
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn message(id: u64, author: u64, time: &str) -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(id);
+        msg.author.id = UserId::new(author);
+        msg.channel_id = ChannelId::new(5);
+        msg.timestamp = serenity::all::Timestamp::parse(time).unwrap();
+        msg
+    }
+
+    #[test]
+    fn test_coverage_intervals() {
+        let mut coverage = Coverage::default();
+        coverage.start_session(at("2025-03-05T10:00:00Z"));
+        coverage.extend_session(at("2025-03-05T12:00:00Z"));
+        coverage.add_channel(
+            ChannelId::new(5),
+            Interval {
+                start: at("2025-03-04T00:00:00Z"),
+                end: at("2025-03-05T11:00:00Z"),
+            },
+        );
+        let channel = ChannelId::new(5);
+        assert!(coverage.covers(channel, at("2025-03-05T11:30:00Z")));
+        assert!(coverage.covers(channel, at("2025-03-04T01:00:00Z")));
+        assert!(!coverage.covers(ChannelId::new(6), at("2025-03-04T01:00:00Z")));
+        assert!(!coverage.covers(channel, at("2025-03-05T12:00:01Z")));
+
+        // Overlapping ranges merge
+        coverage.add_channel(
+            channel,
+            Interval {
+                start: at("2025-03-03T00:00:00Z"),
+                end: at("2025-03-04T12:00:00Z"),
+            },
+        );
+        assert_eq!(coverage.channels[&channel].len(), 1);
+        coverage.prune(at("2025-03-05T11:30:00Z"));
+        assert!(coverage.channels.is_empty());
+        assert_eq!(coverage.live.len(), 1);
+    }
+
+    #[test]
+    fn test_backfill_twice_counts_once() {
+        let conf = Config {
+            report_schedules: vec![Schedule::parse("weekly=weekly mon 00:00").unwrap()],
+            ..Config::default()
+        };
+        let mut stat = Stat::default();
+        // The bot joined on Wednesday and was online until Thursday noon
+        stat.periods.insert(
+            "weekly".to_string(),
+            StatPeriod {
+                collection_start: at("2025-03-05T00:00:00Z"),
+                collect_until: at("2025-03-10T00:00:00Z"),
+                ..StatPeriod::default()
+            },
+        );
+        stat.coverage.start_session(at("2025-03-05T00:00:00Z"));
+        stat.record_message(&message(10, 1, "2025-03-06T12:00:00Z"), &conf);
+
+        let history = || {
+            vec![
+                // Last week
+                message(1, 1, "2025-03-02T10:00:00Z"),
+                // Monday and Tuesday, before the bot joined
+                message(2, 1, "2025-03-03T10:00:00Z"),
+                message(3, 2, "2025-03-04T10:00:00Z"),
+                // Counted live
+                message(10, 1, "2025-03-06T12:00:00Z"),
+                // While the bot was away
+                message(11, 2, "2025-03-07T09:00:00Z"),
+                message(12, 2, "2025-03-07T09:05:00Z"),
+            ]
+        };
+        let range = Interval {
+            start: at("2025-03-01T00:00:00Z"),
+            end: at("2025-03-08T00:00:00Z"),
+        };
+        let backfill = |stat: &mut Stat| {
+            let period = &stat.periods["weekly"];
+            let mut targets = vec![BackfillTarget {
+                schedule: conf.report_schedules[0].clone(),
+                collection_start: period.collection_start,
+                collect_until: period.collect_until,
+                stat: MessageStat::default(),
+                earliest: None,
+            }];
+            let opted_out = BTreeSet::new();
+            let counts =
+                backfill_messages(&mut targets, history(), &stat.coverage, &opted_out, &conf);
+            apply_backfill(stat, targets, &[ChannelId::new(5)], range, &conf).unwrap();
+            counts
+        };
+
+        let first = backfill(&mut stat);
+        assert_eq!(
+            first,
+            BackfillCounts {
+                counted: 4,
+                already_counted: 1,
+                excluded: 1,
+            }
+        );
+        let period = &stat.periods["weekly"];
+        assert_eq!(period.message_stat.messages_count[&UserId::new(1)], 2);
+        assert_eq!(period.message_stat.messages_count[&UserId::new(2)], 3);
+        assert_eq!(
+            period.message_stat.personal_record[&UserId::new(2)].counter,
+            3
+        );
+        assert_eq!(period.collection_start, at("2025-03-03T10:00:00Z"));
+
+        let second = backfill(&mut stat);
+        assert_eq!(second.counted, 0);
+        assert_eq!(second.already_counted, 6);
+        assert_eq!(
+            stat.periods["weekly"].message_stat.messages_count[&UserId::new(2)],
+            3
+        );
+    }
+}
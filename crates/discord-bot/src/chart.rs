@@ -0,0 +1,474 @@
+use super::*;
+
+use ab_glyph::{point, Font, FontRef, GlyphId, PxScale, ScaleFont};
+use serenity::all::CacheHttp;
+use std::str::FromStr;
+use tiny_skia::{
+    Color, FilterQuality, Paint, Pixmap, PixmapPaint, PremultipliedColorU8, Rect, Transform,
+};
+use twemoji_assets::png::PngTwemojiAsset;
+use unicode_segmentation::UnicodeSegmentation;
+
+static FONT_REGULAR: &[u8] = include_bytes!("../assets/fonts/DejaVuSans.ttf");
+static FONT_BOLD: &[u8] = include_bytes!("../assets/fonts/DejaVuSans-Bold.ttf");
+
+const WIDTH: u32 = 900;
+const PADDING: f32 = 20.0;
+const TITLE_SIZE: f32 = 24.0;
+const TEXT_SIZE: f32 = 17.0;
+const ROW_HEIGHT: f32 = 28.0;
+const SECTION_GAP: f32 = 24.0;
+const LABEL_WIDTH: f32 = 220.0;
+const VALUE_WIDTH: f32 = 180.0;
+const CELL_HEIGHT: f32 = 24.0;
+const MAX_BARS: usize = 15;
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+// Discord dark theme
+const BACKGROUND: (u8, u8, u8) = (0x31, 0x33, 0x38);
+const PANEL: (u8, u8, u8) = (0x2b, 0x2d, 0x31);
+const TEXT: (u8, u8, u8) = (0xdb, 0xde, 0xe1);
+const MUTED: (u8, u8, u8) = (0x94, 0x9b, 0xa4);
+const BLURPLE: (u8, u8, u8) = (0x58, 0x65, 0xf2);
+const GREEN: (u8, u8, u8) = (0x57, 0xf2, 0x87);
+
+#[derive(Clone, Copy)]
+struct TextStyle {
+    size: f32,
+    bold: bool,
+    rgb: (u8, u8, u8),
+}
+
+const TITLE_STYLE: TextStyle = TextStyle {
+    size: TITLE_SIZE,
+    bold: true,
+    rgb: TEXT,
+};
+const HEADING_STYLE: TextStyle = TextStyle {
+    size: TEXT_SIZE,
+    bold: true,
+    rgb: TEXT,
+};
+const LABEL_STYLE: TextStyle = TextStyle {
+    size: TEXT_SIZE,
+    bold: false,
+    rgb: TEXT,
+};
+const NOTE_STYLE: TextStyle = TextStyle {
+    size: TEXT_SIZE,
+    bold: false,
+    rgb: MUTED,
+};
+const AXIS_STYLE: TextStyle = TextStyle {
+    size: TEXT_SIZE * 0.8,
+    bold: false,
+    rgb: MUTED,
+};
+
+/// Whether the report message gets a chart image, `REPORT_IMAGE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReportImage {
+    #[default]
+    Off,
+    Alongside, // chart and table
+    Instead,   // chart only, the table is kept if rendering fails
+}
+
+impl FromStr for ReportImage {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.trim() {
+            "off" => ReportImage::Off,
+            "alongside" => ReportImage::Alongside,
+            "instead" => ReportImage::Instead,
+            other => return Err(format!("unknown report image mode `{other}`")),
+        })
+    }
+}
+
+/// One bar of a chart section: user name, length and a note after the value.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChartBar {
+    pub label: String,
+    pub value: usize,
+    pub note: String,
+}
+
+/// Everything the chart shows, with the names already resolved.
+#[derive(Debug, Clone, Default)]
+pub struct ChartData {
+    pub title: String,
+    pub subtitle: String,
+    pub messages: Vec<ChartBar>,
+    pub streaks: Vec<ChartBar>,
+    pub activity: [[usize; 24]; 7],
+}
+
+impl ChartData {
+    pub async fn from_stat(
+        title: String,
+        subtitle: String,
+        message_stat: &MessageStat,
+        cache_http: impl CacheHttp,
+        conf: &Config,
+    ) -> ChartData {
+        let mut messages = vec![];
+        for (user_id, count) in message_stat.messages_count.iter() {
+            messages.push(ChartBar {
+                label: get_user_name(user_id, &cache_http, conf).await,
+                value: *count,
+                note: String::new(),
+            });
+        }
+        let mut streaks = vec![];
+        for (user_id, record) in message_stat.personal_record.iter() {
+            streaks.push(ChartBar {
+                label: get_user_name(user_id, &cache_http, conf).await,
+                value: record.counter,
+                note: format!(
+                    "in {}",
+                    get_channel_name(&record.channel_id, &cache_http).await
+                ),
+            });
+        }
+
+        ChartData {
+            title,
+            subtitle,
+            messages: top_bars(messages),
+            streaks: top_bars(streaks),
+            activity: message_stat.activity,
+        }
+    }
+}
+
+/// Largest first, ties by name, at most `MAX_BARS`.
+fn top_bars(mut bars: Vec<ChartBar>) -> Vec<ChartBar> {
+    bars.retain(|bar| bar.value > 0);
+    bars.sort_by(|a, b| b.value.cmp(&a.value).then_with(|| a.label.cmp(&b.label)));
+    bars.truncate(MAX_BARS);
+    bars
+}
+
+/// Renders the messages and streak bar charts and the weekday/hour heatmap as a PNG.
+/// Sections without data are left out.
+pub fn render_report_chart(data: &ChartData) -> eyre::Result<Vec<u8>> {
+    let bars_height = |bars: &[ChartBar]| match bars.is_empty() {
+        true => 0.0,
+        false => ROW_HEIGHT * (bars.len() + 1) as f32 + SECTION_GAP,
+    };
+    let has_activity = data.activity.iter().flatten().any(|count| *count > 0);
+    let heatmap_height = match has_activity {
+        true => ROW_HEIGHT * 2.0 + CELL_HEIGHT * 7.0 + SECTION_GAP,
+        false => 0.0,
+    };
+    let height = PADDING * 2.0
+        + TITLE_SIZE * 1.5
+        + ROW_HEIGHT
+        + SECTION_GAP
+        + bars_height(&data.messages)
+        + bars_height(&data.streaks)
+        + heatmap_height;
+
+    let mut canvas = Canvas::new(WIDTH, height.ceil() as u32)?;
+    let mut y = PADDING;
+    canvas.text(&data.title, PADDING, y, TITLE_STYLE, canvas.max_text());
+    y += TITLE_SIZE * 1.5;
+    canvas.text(&data.subtitle, PADDING, y, NOTE_STYLE, canvas.max_text());
+    y += ROW_HEIGHT + SECTION_GAP;
+
+    if !data.messages.is_empty() {
+        y = canvas.bar_section("Messages", &data.messages, BLURPLE, y);
+    }
+    if !data.streaks.is_empty() {
+        y = canvas.bar_section("Max series", &data.streaks, GREEN, y);
+    }
+    if has_activity {
+        canvas.heatmap("Activity", &data.activity, y);
+    }
+
+    Ok(canvas.pixmap.encode_png()?)
+}
+
+/// A piece of laid out text: a font glyph or a Twemoji image, with its x offset.
+enum TextPiece {
+    Glyph(GlyphId, f32),
+    Emoji(&'static [u8], f32),
+}
+
+struct Canvas {
+    pixmap: Pixmap,
+    regular: FontRef<'static>,
+    bold: FontRef<'static>,
+}
+
+impl Canvas {
+    fn new(width: u32, height: u32) -> eyre::Result<Canvas> {
+        let mut pixmap = Pixmap::new(width, height)
+            .ok_or_else(|| eyre::eyre!("invalid chart size {width}x{height}"))?;
+        pixmap.fill(color(BACKGROUND));
+        Ok(Canvas {
+            pixmap,
+            regular: FontRef::try_from_slice(FONT_REGULAR)?,
+            bold: FontRef::try_from_slice(FONT_BOLD)?,
+        })
+    }
+
+    fn max_text(&self) -> f32 {
+        self.pixmap.width() as f32 - PADDING * 2.0
+    }
+
+    fn bar_section(&mut self, title: &str, bars: &[ChartBar], rgb: (u8, u8, u8), y: f32) -> f32 {
+        self.text(title, PADDING, y, HEADING_STYLE, self.max_text());
+        let mut y = y + ROW_HEIGHT;
+
+        let max_value = bars.iter().map(|bar| bar.value).max().unwrap_or(1).max(1);
+        let bar_x = PADDING + LABEL_WIDTH;
+        let bar_space = self.pixmap.width() as f32 - bar_x - VALUE_WIDTH - PADDING;
+        for bar in bars {
+            self.text(&bar.label, PADDING, y, LABEL_STYLE, LABEL_WIDTH - 10.0);
+            let length = (bar_space * bar.value as f32 / max_value as f32).max(2.0);
+            self.rect(bar_x, y + 4.0, length, ROW_HEIGHT - 10.0, rgb);
+            let value = format!("{} {}", bar.value, bar.note);
+            let value_x = bar_x + length + 8.0;
+            let value_width = self.pixmap.width() as f32 - PADDING - value_x;
+            self.text(value.trim(), value_x, y, NOTE_STYLE, value_width);
+            y += ROW_HEIGHT;
+        }
+        y + SECTION_GAP
+    }
+
+    fn heatmap(&mut self, title: &str, activity: &[[usize; 24]; 7], y: f32) {
+        self.text(title, PADDING, y, HEADING_STYLE, self.max_text());
+        let label_width = 50.0;
+        let cell_width = (self.pixmap.width() as f32 - PADDING * 2.0 - label_width) / 24.0;
+        let left = PADDING + label_width;
+        let mut y = y + ROW_HEIGHT;
+
+        for hour in (0..24).step_by(3) {
+            let x = left + cell_width * hour as f32;
+            self.text(&format!("{hour:02}"), x, y, AXIS_STYLE, cell_width * 3.0);
+        }
+        y += ROW_HEIGHT;
+
+        let max_count = activity.iter().flatten().copied().max().unwrap_or(1).max(1);
+        for (weekday, hours) in activity.iter().enumerate() {
+            self.text(WEEKDAYS[weekday], PADDING, y, AXIS_STYLE, label_width);
+            for (hour, count) in hours.iter().enumerate() {
+                let share = *count as f32 / max_count as f32;
+                let rgb = match *count {
+                    0 => PANEL,
+                    _ => mix(PANEL, BLURPLE, 0.2 + 0.8 * share),
+                };
+                let x = left + cell_width * hour as f32;
+                self.rect(x + 1.0, y + 1.0, cell_width - 2.0, CELL_HEIGHT - 2.0, rgb);
+            }
+            y += CELL_HEIGHT;
+        }
+    }
+
+    fn rect(&mut self, x: f32, y: f32, width: f32, height: f32, rgb: (u8, u8, u8)) {
+        if let Some(rect) = Rect::from_xywh(x, y, width, height) {
+            let mut paint = Paint::default();
+            paint.set_color(color(rgb));
+            self.pixmap
+                .fill_rect(rect, &paint, Transform::identity(), None);
+        }
+    }
+
+    /// Draws a single line with its top at `y`, cut with "…" to fit `max_width`.
+    fn text(&mut self, text: &str, x: f32, y: f32, style: TextStyle, max_width: f32) {
+        let (size, rgb) = (style.size, style.rgb);
+        let font = match style.bold {
+            true => self.bold.clone(),
+            false => self.regular.clone(),
+        };
+        let (pieces, _) = fit_text(&font, text, size, max_width);
+        let scaled = font.as_scaled(PxScale::from(size));
+        let baseline = y + scaled.ascent();
+
+        for piece in pieces {
+            match piece {
+                TextPiece::Glyph(id, offset) => {
+                    let glyph = id.with_scale_and_position(size, point(x + offset, baseline));
+                    if let Some(outlined) = font.outline_glyph(glyph) {
+                        let bounds = outlined.px_bounds();
+                        outlined.draw(|gx, gy, coverage| {
+                            let px = bounds.min.x as i32 + gx as i32;
+                            let py = bounds.min.y as i32 + gy as i32;
+                            self.blend(px, py, rgb, coverage);
+                        });
+                    }
+                }
+                TextPiece::Emoji(png, offset) => match Pixmap::decode_png(png) {
+                    Ok(image) => {
+                        let scale = size / image.width() as f32;
+                        let top = baseline - size * 0.85;
+                        let transform =
+                            Transform::from_row(scale, 0.0, 0.0, scale, x + offset, top);
+                        let paint = PixmapPaint {
+                            quality: FilterQuality::Bicubic,
+                            ..PixmapPaint::default()
+                        };
+                        self.pixmap
+                            .draw_pixmap(0, 0, image.as_ref(), &paint, transform, None);
+                    }
+                    Err(e) => tracing::warn!("Error decoding emoji image: {}", e),
+                },
+            }
+        }
+    }
+
+    /// Blends a text color over the opaque background.
+    fn blend(&mut self, x: i32, y: i32, rgb: (u8, u8, u8), coverage: f32) {
+        let (width, height) = (self.pixmap.width() as i32, self.pixmap.height() as i32);
+        if x < 0 || y < 0 || x >= width || y >= height {
+            return;
+        }
+        let pixel = &mut self.pixmap.pixels_mut()[(y * width + x) as usize];
+        let alpha = coverage.clamp(0.0, 1.0);
+        let mixed = mix((pixel.red(), pixel.green(), pixel.blue()), rgb, alpha);
+        if let Some(color) = PremultipliedColorU8::from_rgba(mixed.0, mixed.1, mixed.2, 255) {
+            *pixel = color;
+        }
+    }
+}
+
+/// Lays the text out on one line, replacing the tail with "…" when it is wider than
+/// `max_width`. Returns the pieces and the resulting width.
+fn fit_text(font: &FontRef, text: &str, size: f32, max_width: f32) -> (Vec<TextPiece>, f32) {
+    let graphemes = text.graphemes(true).collect::<Vec<&str>>();
+    let (pieces, width) = layout_text(font, &graphemes, size);
+    if width <= max_width {
+        return (pieces, width);
+    }
+    for keep in (0..graphemes.len()).rev() {
+        let mut shortened = graphemes[..keep].to_vec();
+        shortened.push("…");
+        let (pieces, width) = layout_text(font, &shortened, size);
+        if width <= max_width {
+            return (pieces, width);
+        }
+    }
+    (vec![], 0.0)
+}
+
+fn layout_text(font: &FontRef, graphemes: &[&str], size: f32) -> (Vec<TextPiece>, f32) {
+    let scaled = font.as_scaled(PxScale::from(size));
+    let mut pieces = vec![];
+    let mut x = 0.0;
+    let mut previous: Option<GlyphId> = None;
+
+    for grapheme in graphemes {
+        if let Some(png) = emoji_image(grapheme) {
+            pieces.push(TextPiece::Emoji(png, x));
+            x += size * 1.1;
+            previous = None;
+            continue;
+        }
+        for c in grapheme.chars() {
+            let id = font.glyph_id(c);
+            if let Some(previous) = previous {
+                x += scaled.kern(previous, id);
+            }
+            pieces.push(TextPiece::Glyph(id, x));
+            x += scaled.h_advance(id);
+            previous = Some(id);
+        }
+    }
+
+    (pieces, x)
+}
+
+/// Twemoji image of the grapheme, also without the emoji presentation selector.
+fn emoji_image(grapheme: &str) -> Option<&'static [u8]> {
+    if grapheme.is_ascii() {
+        return None;
+    }
+    let asset = PngTwemojiAsset::from_emoji(grapheme)
+        .or_else(|| PngTwemojiAsset::from_emoji(&grapheme.replace('\u{fe0f}', "")))?;
+    Some(asset)
+}
+
+fn color(rgb: (u8, u8, u8)) -> Color {
+    Color::from_rgba8(rgb.0, rgb.1, rgb.2, 255)
+}
+
+fn mix(from: (u8, u8, u8), to: (u8, u8, u8), share: f32) -> (u8, u8, u8) {
+    let channel = |a: u8, b: u8| (a as f32 + (b as f32 - a as f32) * share).round() as u8;
+    (
+        channel(from.0, to.0),
+        channel(from.1, to.1),
+        channel(from.2, to.2),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bar(label: &str, value: usize) -> ChartBar {
+        ChartBar {
+            label: label.to_string(),
+            value,
+            note: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_render_report_chart() {
+        let mut activity = [[0; 24]; 7];
+        activity[0][10] = 5;
+        activity[6][23] = 1;
+        let data = ChartData {
+            title: "Итоги недели 🏆".to_string(),
+            subtitle: "2025-03-03 00:00 - 2025-03-10 00:00 (UTC)".to_string(),
+            messages: vec![bar("Ахиллес сын Пелея", 120), bar("Bob 🐈‍⬛", 7)],
+            streaks: vec![ChartBar {
+                note: "in general".to_string(),
+                ..bar("Алиса ❤️", 12)
+            }],
+            activity,
+        };
+
+        let png = render_report_chart(&data).unwrap();
+        let image = Pixmap::decode_png(&png).unwrap();
+        assert_eq!(image.width(), WIDTH);
+        let background = color(BACKGROUND).premultiply().to_color_u8().demultiply();
+        let drawn = image
+            .pixels()
+            .iter()
+            .filter(|pixel| pixel.demultiply() != background)
+            .count();
+        assert!(drawn > 1000);
+
+        let empty = render_report_chart(&ChartData::default()).unwrap();
+        let empty = Pixmap::decode_png(&empty).unwrap();
+        assert!(empty.height() < image.height());
+    }
+
+    #[test]
+    fn test_fit_text_and_emoji() {
+        let font = FontRef::try_from_slice(FONT_REGULAR).unwrap();
+        let (_, full) = fit_text(&font, "Ахиллес сын Пелея", TEXT_SIZE, 1000.0);
+        let (pieces, cut) = fit_text(&font, "Ахиллес сын Пелея", TEXT_SIZE, 60.0);
+        assert!(cut <= 60.0 && cut < full);
+        assert!(!pieces.is_empty());
+
+        assert!(emoji_image("🐈‍⬛").is_some());
+        assert!(emoji_image("❤️").is_some());
+        assert!(emoji_image("Я").is_none());
+        assert!(emoji_image("a").is_none());
+    }
+
+    #[test]
+    fn test_top_bars() {
+        let bars = top_bars(vec![bar("b", 3), bar("a", 3), bar("c", 0), bar("d", 9)]);
+        let labels = bars
+            .iter()
+            .map(|bar| bar.label.as_str())
+            .collect::<Vec<&str>>();
+        assert_eq!(labels, ["d", "a", "b"]);
+    }
+}
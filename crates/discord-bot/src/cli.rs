@@ -0,0 +1,92 @@
+use super::*;
+
+use std::collections::HashMap;
+
+const USAGE: &str = "Usage: discord-bot [command] [args]
+
+Without arguments the bot is started, any other argument than a command below is refused.
+
+Commands:
+  archive   Browse the archived statistics periods
+  export    Export the statistics as CSV or JSON
+  replay    Replay LLM transcripts against a backend and diff the answers, the regression
+            tool for prompt and model changes. It ships inside the bot binary so that it
+            uses the same backend code and config.";
+
+/// Runs an offline command when the binary is started with arguments, `None` when there
+/// are none and the bot has to start. Returns the process exit code.
+pub async fn run_cli(args: &[String]) -> Option<i32> {
+    let command = args.first()?;
+    let code = match command.as_str() {
+        "archive" => run_archive_cli(&args[1..]),
+        "export" => run_export_cli(&args[1..]).await,
+        "replay" => run_replay(&args[1..]).await,
+        "help" | "--help" | "-h" => {
+            println!("{USAGE}");
+            0
+        }
+        // A mistyped flag must not look like a bot that started
+        command => {
+            eprintln!("Unknown command `{command}`, the bot was not started\n\n{USAGE}");
+            2
+        }
+    };
+    Some(code)
+}
+
+/// `--name value` options, `--flag` switches and positional arguments.
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    pub positional: Vec<String>,
+    values: HashMap<String, String>,
+    flags: Vec<String>,
+}
+
+impl CliArgs {
+    pub fn parse(args: &[String], flags: &[&str]) -> CliArgs {
+        let mut result = CliArgs::default();
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let name = match arg.strip_prefix("--") {
+                Some(name) => name,
+                None => {
+                    result.positional.push(arg.clone());
+                    continue;
+                }
+            };
+            if flags.contains(&name) {
+                result.flags.push(name.to_string());
+                continue;
+            }
+            match args.next() {
+                Some(value) => {
+                    result.values.insert(name.to_string(), value.clone());
+                }
+                None => eprintln!("Missing value for --{name}"),
+            }
+        }
+        result
+    }
+
+    pub fn value(&self, name: &str) -> Option<&str> {
+        self.values.get(name).map(String::as_str)
+    }
+
+    pub fn flag(&self, name: &str) -> bool {
+        self.flags.iter().any(|flag| flag == name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_args() {
+        let args = ["--mock", "a.jsonl", "--model", "qwen", "b.jsonl"].map(String::from);
+        let args = CliArgs::parse(&args, &["mock"]);
+        assert!(args.flag("mock"));
+        assert_eq!(args.value("model"), Some("qwen"));
+        assert_eq!(args.positional, ["a.jsonl", "b.jsonl"]);
+    }
+}
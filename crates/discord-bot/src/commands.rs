@@ -0,0 +1,47 @@
+use super::*;
+
+use serenity::all::{CommandInteraction, CreateCommand};
+use serenity::prelude::*;
+
+fn commands() -> Vec<CreateCommand> {
+    vec![
+        achievements_command(),
+        archive_command(),
+        backend_command(),
+        backfill_command(),
+        emoji_command(),
+        export_command(),
+        feedback_command(),
+        karma_command(),
+        stats_command(),
+        summarize_command(),
+    ]
+}
+
+pub async fn register_commands(ctx: &Context, conf: &Config) {
+    match conf.guild_id.set_commands(&ctx.http, commands()).await {
+        Ok(commands) => tracing::info!("Registered {} commands", commands.len()),
+        Err(why) => tracing::error!("Error registering commands: {why:?}"),
+    }
+}
+
+pub async fn handle_command(ctx: &Context, command: &CommandInteraction, handler: &Handler) {
+    let conf = handler.config.lock().await.clone();
+    tracing::info!("Command /{} from {}", command.data.name, command.user.id);
+
+    match command.data.name.as_str() {
+        "achievements" => run_achievements_command(ctx, command, &conf, &handler.stat).await,
+        "archive" => run_archive_command(ctx, command, &conf).await,
+        "backend" => run_backend_command(ctx, command, &conf, &handler.llm).await,
+        "backfill" => {
+            run_backfill_command(ctx, command, &conf, &handler.stat, &handler.channels).await
+        }
+        "emoji" => run_emoji_command(ctx, command, &conf, &handler.stat).await,
+        "export" => run_export_command(ctx, command, &conf, &handler.stat).await,
+        "feedback" => run_feedback_command(ctx, command, &conf).await,
+        "karma" => run_karma_command(ctx, command, &conf, &handler.stat).await,
+        "stats" => run_stats_command(ctx, command, &conf, &handler.stat).await,
+        "summarize" => run_summarize_command(ctx, command, &conf, &handler.llm).await,
+        name => tracing::warn!("Unknown command: {name}"),
+    }
+}
@@ -1,16 +1,99 @@
 use super::*;
 
-use serenity::all::{GuildId, UserId};
+use chrono_tz::Tz;
+use ollama_rs::generation::options::GenerationOptions;
+use serenity::all::{ChannelId, GuildId, ReactionType, UserId};
+use std::collections::HashMap;
+use std::str::FromStr;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default)]
 pub struct Config {
     pub token: String,
     pub override_user_id: UserId,
     pub override_user_name: String,
     pub guild_id: GuildId,
+    pub thread_mode: bool,
+    pub thread_auto_archive_minutes: u16,
+    pub thread_context_messages: u8,
+    pub ollama_host: String,
+    pub ollama_port: u16,
+    pub model_names: Vec<String>, // main model first, then fallbacks
+    pub ollama_options: Option<GenerationOptions>,
+    pub ollama_timeout_secs: u64,
+    pub ollama_health_interval_secs: u64,
+    pub error_ollama_error: String,
+    pub error_ollama_timeout: String,
+    pub error_ollama_model_missing: String,
+    pub error_ollama_connection_refused: String,
+    pub persona_name: String,
+    pub replies_file: String,
+    pub feedback_file: String,
+    pub feedback_track_days: i64,
+    pub transcript_file: String,
+    pub transcript_max_bytes: u64,
+    pub transcript_max_files: usize,
+    pub summarize_default_count: usize,
+    pub summarize_max_messages: usize,
+    pub summarize_chunk_chars: usize,
+    pub summarize_cooldown_secs: u64,
+    pub summarize_prompt: String,
+    pub summarize_reduce_prompt: String,
+    pub stat_file: String,
+    pub stat_backups: usize,
+    pub stat_autosave_secs: u64,
+    pub report_channel_id: ChannelId,
+    pub report_late_note: String,
+    pub report_layout: ReportLayout,
+    pub report_channels_top: usize, // channel leaderboard rows, 0 leaves it out
+    pub report_emoji_top: usize,    // top emoji lines, 0 leaves them out
+    pub stat_filter: StatFilter,
+    pub streak_rules: StreakRules,
+    pub achievements: Vec<Achievement>,
+    pub achievements_channel_id: ChannelId,
+    pub achievements_cooldown_secs: u64,
+    pub records_channel_id: ChannelId,
+    pub backfill_page_delay_ms: u64,
+    pub karma_rules: KarmaRules,
+    pub report_image: ReportImage,
+    pub archive_file: String,
+    pub report_schedules: Vec<Schedule>,
+    pub stat_timezone: Tz,
+    pub stat_timezones: HashMap<GuildId, Tz>,
+}
+
+impl Config {
+    /// Picks the reply text for a failed LLM request.
+    pub fn backend_error_message(&self, err: &BackendError) -> String {
+        match err {
+            BackendError::Timeout => self.error_ollama_timeout.clone(),
+            BackendError::ModelMissing(_) => self.error_ollama_model_missing.clone(),
+            BackendError::ConnectionRefused => self.error_ollama_connection_refused.clone(),
+            BackendError::Other(_) => self.error_ollama_error.clone(),
+        }
+    }
+
+    /// Timezone of the guild for day and hour boundaries, `STAT_TIMEZONE` if not overridden.
+    pub fn guild_timezone(&self, guild_id: Option<GuildId>) -> Tz {
+        guild_id
+            .and_then(|guild_id| self.stat_timezones.get(&guild_id))
+            .copied()
+            .unwrap_or(self.stat_timezone)
+    }
+
+    /// Timezone of the report periods, the one of the main guild.
+    pub fn report_timezone(&self) -> Tz {
+        self.guild_timezone(Some(self.guild_id))
+    }
 }
 
 pub fn init_config() -> Config {
+    // The offline tools can take the model from their arguments, the bot can't
+    let offline = init_offline_config();
+    if offline.model_names.is_empty() {
+        panic!("Expected a model name in the environment MODEL_NAME");
+    }
+    let report_channel_id = env_or("REPORT_CHANNEL_ID", ChannelId::new(1245807370341191812));
+
     Config {
         token: env::var("DISCORD_TOKEN")
             .expect("Expected a token in the environment DISCORD_TOKEN"),
@@ -24,5 +107,266 @@ pub fn init_config() -> Config {
             .expect("Expected an ID in the environment GUILD_ID")
             .parse()
             .expect("Expected a valid ID in the environment GUILD_ID"),
+        thread_mode: env_or("THREAD_MODE", false),
+        thread_auto_archive_minutes: init_thread_auto_archive_minutes(),
+        thread_context_messages: env_or("THREAD_CONTEXT_MESSAGES", 20),
+        persona_name: env_or("PERSONA_NAME", "default".to_string()),
+        replies_file: env_or("REPLIES_FILE", "stat/llm-replies.jsonl".to_string()),
+        feedback_file: env_or("FEEDBACK_FILE", "stat/feedback.jsonl".to_string()),
+        feedback_track_days: env_or("FEEDBACK_TRACK_DAYS", 30),
+        summarize_default_count: env_or("SUMMARIZE_DEFAULT_COUNT", 100),
+        summarize_max_messages: env_or("SUMMARIZE_MAX_MESSAGES", 1000),
+        summarize_chunk_chars: env_or("SUMMARIZE_CHUNK_CHARS", 6000),
+        summarize_cooldown_secs: env_or("SUMMARIZE_COOLDOWN_SECS", 120),
+        summarize_prompt: env_or(
+            "SUMMARIZE_PROMPT",
+            "Summarize the chat log below as a short bullet-point list of the main topics, \
+             decisions and open questions. Mention who said what when it matters."
+                .to_string(),
+        ),
+        summarize_reduce_prompt: env_or(
+            "SUMMARIZE_REDUCE_PROMPT",
+            "Below are bullet-point summaries of consecutive parts of one chat. Merge them \
+             into one short bullet-point list without repetitions."
+                .to_string(),
+        ),
+        stat_file: env_or("STAT_FILE", "stat/stat.json".to_string()),
+        stat_backups: env_or("STAT_BACKUPS", 5),
+        stat_autosave_secs: env_or("STAT_AUTOSAVE_SECS", 300),
+        report_channel_id,
+        report_late_note: env_or(
+            "REPORT_LATE_NOTE",
+            "Late report, the bot was offline when the period ended".to_string(),
+        ),
+        report_image: env_or("REPORT_IMAGE", ReportImage::Off),
+        stat_filter: init_stat_filter(),
+        achievements: init_achievements(),
+        achievements_channel_id: env_or("ACHIEVEMENTS_CHANNEL_ID", report_channel_id),
+        achievements_cooldown_secs: env_or("ACHIEVEMENTS_COOLDOWN_SECS", 600),
+        records_channel_id: env_or("RECORDS_CHANNEL_ID", report_channel_id),
+        backfill_page_delay_ms: env_or("BACKFILL_PAGE_DELAY_MS", 1000),
+        karma_rules: init_karma_rules(),
+        ..offline
+    }
+}
+
+/// `name=spec` lines, the first schedule also takes over a legacy `stat.json`.
+fn init_report_schedules() -> Vec<Schedule> {
+    let mut lines = env_list("REPORT_SCHEDULES");
+    if lines.is_empty() {
+        lines.push("weekly=weekly sun 00:00".to_string());
+    }
+    let schedules = lines
+        .iter()
+        .map(|line| {
+            Schedule::parse(line).unwrap_or_else(|e| {
+                panic!("Expected a valid schedule in the environment REPORT_SCHEDULES: {e}")
+            })
+        })
+        .collect::<Vec<Schedule>>();
+    for (index, schedule) in schedules.iter().enumerate() {
+        if schedules[..index].iter().any(|s| s.name == schedule.name) {
+            panic!("Duplicate schedule {} in REPORT_SCHEDULES", schedule.name);
+        }
+    }
+    schedules
+}
+
+/// `REPORT_COLUMNS` comma separated, `REPORT_SORT` as `column [asc|desc]`, `REPORT_TOP` rows
+/// before the others row and `REPORT_MIN_MESSAGES`. The classic table by default.
+fn init_report_layout() -> ReportLayout {
+    let columns = env_or("REPORT_COLUMNS", "messages,series,files".to_string())
+        .split(',')
+        .map(|column| {
+            column.parse().unwrap_or_else(|e| {
+                panic!("Expected valid columns in the environment REPORT_COLUMNS: {e}")
+            })
+        })
+        .collect();
+    let (sort_by, descending) =
+        ReportLayout::parse_sort(&env_or("REPORT_SORT", "messages desc".to_string()))
+            .unwrap_or_else(|e| {
+                panic!("Expected a valid order in the environment REPORT_SORT: {e}")
+            });
+    ReportLayout {
+        columns,
+        sort_by,
+        descending,
+        top: env_or("REPORT_TOP", 0),
+        min_messages: env_or("REPORT_MIN_MESSAGES", 1),
+    }
+}
+
+/// Discord only archives threads after one of these durations.
+fn init_thread_auto_archive_minutes() -> u16 {
+    let minutes = env_or("THREAD_AUTO_ARCHIVE_MINUTES", 60);
+    match [60, 1440, 4320, 10080].contains(&minutes) {
+        true => minutes,
+        false => panic!(
+            "Expected 60, 1440, 4320 or 10080 in the environment THREAD_AUTO_ARCHIVE_MINUTES"
+        ),
+    }
+}
+
+/// `name=trigger Title` lines, none turns the achievements off.
+fn init_achievements() -> Vec<Achievement> {
+    let achievements = env_list("ACHIEVEMENTS")
+        .iter()
+        .map(|line| {
+            Achievement::parse(line).unwrap_or_else(|e| {
+                panic!("Expected valid achievements in the environment ACHIEVEMENTS: {e}")
+            })
+        })
+        .collect::<Vec<Achievement>>();
+    for (index, achievement) in achievements.iter().enumerate() {
+        if achievements[..index]
+            .iter()
+            .any(|a| a.name == achievement.name)
+        {
+            panic!("Duplicate achievement {} in ACHIEVEMENTS", achievement.name);
+        }
     }
+    achievements
+}
+
+/// `KARMA_WORDS` are newline separated, none gives English and Russian thanks.
+/// `KARMA_EMOJI` is a reaction, `<:name:id>` for a custom one.
+fn init_karma_rules() -> KarmaRules {
+    let mut words = env_list("KARMA_WORDS");
+    if words.is_empty() {
+        words = ["thanks", "thank you", "thx", "спасибо", "спс", "благодарю"]
+            .map(String::from)
+            .to_vec();
+    }
+    KarmaRules {
+        words: words.iter().map(|word| word.to_lowercase()).collect(),
+        emoji: env::var("KARMA_EMOJI")
+            .ok()
+            .filter(|emoji| !emoji.trim().is_empty())
+            .map(|emoji| reaction_emoji(&ReactionType::Unicode(emoji.trim().to_string()))),
+        cooldown: chrono::Duration::minutes(env_or("KARMA_COOLDOWN_MINUTES", 60)),
+    }
+}
+
+/// `STREAK_GAP_MINUTES` of silence break a series, `STREAK_BURST_MINUTES` is the window of
+/// the burst metric and `STREAK_REPORT_TOP` the longest series listed. 0 turns each off.
+fn init_streak_rules() -> StreakRules {
+    let minutes = |name: &str, default: i64| {
+        Some(env_or(name, default))
+            .filter(|minutes| *minutes > 0)
+            .map(chrono::Duration::minutes)
+    };
+    StreakRules {
+        gap: minutes("STREAK_GAP_MINUTES", 60),
+        burst_window: minutes("STREAK_BURST_MINUTES", 0),
+        report_top: env_or("STREAK_REPORT_TOP", 3),
+    }
+}
+
+/// `STAT_IGNORE_*` switches and newline separated channel and category ID lists, a thread
+/// follows the rules of its channel.
+fn init_stat_filter() -> StatFilter {
+    let ids = |name: &str| {
+        env_list(name)
+            .iter()
+            .map(|id| {
+                id.parse()
+                    .unwrap_or_else(|_| panic!("Expected channel IDs in the environment {name}"))
+            })
+            .collect()
+    };
+    StatFilter {
+        ignore_bots: env_or("STAT_IGNORE_BOTS", true),
+        ignore_webhooks: env_or("STAT_IGNORE_WEBHOOKS", true),
+        ignore_dms: env_or("STAT_IGNORE_DMS", true),
+        allow_channels: ids("STAT_ALLOW_CHANNELS"),
+        deny_channels: ids("STAT_DENY_CHANNELS"),
+        allow_categories: ids("STAT_ALLOW_CATEGORIES"),
+        deny_categories: ids("STAT_DENY_CATEGORIES"),
+    }
+}
+
+/// `guild_id=Area/City` lines.
+fn init_stat_timezones() -> HashMap<GuildId, Tz> {
+    env_list("STAT_TIMEZONES")
+        .iter()
+        .map(|line| {
+            let parsed = line.split_once('=').and_then(|(guild_id, tz)| {
+                Some((guild_id.trim().parse().ok()?, tz.trim().parse().ok()?))
+            });
+            parsed.unwrap_or_else(|| {
+                panic!(
+                    "Expected `guild_id=timezone` in the environment STAT_TIMEZONES, got `{line}`"
+                )
+            })
+        })
+        .collect()
+}
+
+/// The LLM backend and statistics part of the config, enough for the offline tools which
+/// don't talk to Discord.
+pub fn init_offline_config() -> Config {
+    let error_ollama_error = env_or("ERROR_OLLAMA_ERROR", "ERROR_OLLAMA_ERROR".to_string());
+    let mut model_names = env_list("MODEL_NAME");
+    model_names.extend(env_list("FALLBACK_MODELS"));
+    let ollama_options = env::var("OLLAMA_OPTIONS")
+        .ok()
+        .filter(|options| !options.trim().is_empty())
+        .map(|options| {
+            serde_json::from_str(&options)
+                .expect("Expected valid JSON options in the environment OLLAMA_OPTIONS")
+        });
+
+    Config {
+        ollama_host: env_or("OLLAMA_HOST", "http://localhost".to_string()),
+        ollama_port: env_or("OLLAMA_PORT", 11434),
+        model_names,
+        ollama_options,
+        ollama_timeout_secs: env_or("OLLAMA_TIMEOUT_SECS", 180),
+        ollama_health_interval_secs: env_or("OLLAMA_HEALTH_INTERVAL_SECS", 300),
+        error_ollama_timeout: env_or("ERROR_OLLAMA_TIMEOUT", error_ollama_error.clone()),
+        error_ollama_model_missing: env_or(
+            "ERROR_OLLAMA_MODEL_MISSING",
+            error_ollama_error.clone(),
+        ),
+        error_ollama_connection_refused: env_or(
+            "ERROR_OLLAMA_CONNECTION_REFUSED",
+            error_ollama_error.clone(),
+        ),
+        error_ollama_error,
+        transcript_file: env_or("TRANSCRIPT_FILE", "stat/transcripts.jsonl".to_string()),
+        transcript_max_bytes: env_or("TRANSCRIPT_MAX_BYTES", 10 * 1024 * 1024),
+        transcript_max_files: env_or("TRANSCRIPT_MAX_FILES", 5),
+        guild_id: env_or("GUILD_ID", GuildId::default()),
+        report_schedules: init_report_schedules(),
+        stat_timezone: env_or("STAT_TIMEZONE", Tz::UTC),
+        stat_timezones: init_stat_timezones(),
+        archive_file: env_or("ARCHIVE_FILE", "stat/archive.jsonl".to_string()),
+        report_layout: init_report_layout(),
+        report_channels_top: env_or("REPORT_CHANNELS_TOP", 0),
+        report_emoji_top: env_or("REPORT_EMOJI_TOP", 5),
+        streak_rules: init_streak_rules(),
+        ..Config::default()
+    }
+}
+
+/// Reads an optional variable from the environment, falling back to `default` when it is
+/// missing or empty (`run.sh` exports empty values for absent `.config` files).
+pub fn env_or<T: FromStr>(name: &str, default: T) -> T {
+    match env::var(name) {
+        Ok(value) if !value.trim().is_empty() => value.trim().parse().unwrap_or_else(|_| {
+            panic!("Expected a valid value in the environment {name}");
+        }),
+        _ => default,
+    }
+}
+
+/// Reads an optional newline separated list from the environment.
+pub fn env_list(name: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_default()
+        .split('\n')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
 }
@@ -0,0 +1,370 @@
+use super::*;
+
+use regex::Regex;
+use serenity::all::{
+    CommandDataOption, CommandDataOptionValue, CommandInteraction, CommandOptionType,
+    CreateCommand, CreateCommandOption, CreateEmbed, CreateEmbedFooter, EditInteractionResponse,
+    EmojiId, ReactionType, UserId,
+};
+use serenity::prelude::*;
+use std::collections::{HashMap, HashSet};
+use std::sync::LazyLock;
+use twemoji_assets::png::PngTwemojiAsset;
+use unicode_segmentation::UnicodeSegmentation;
+
+const EMBED_COLOUR: u32 = 0x5865f2;
+
+static CUSTOM_EMOJI: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"<a?:\w+:\d+>").unwrap());
+
+/// Custom emoji as written in the message, `<:name:id>` or `<a:name:id>`, then the Unicode
+/// ones, in order of appearance within each kind.
+pub fn message_emoji(content: &str) -> Vec<String> {
+    let mut emoji = vec![];
+    if content.contains('<') {
+        emoji.extend(
+            CUSTOM_EMOJI
+                .find_iter(content)
+                .map(|m| m.as_str().to_string()),
+        );
+    }
+    emoji.extend(
+        content
+            .graphemes(true)
+            .filter(|grapheme| !grapheme.is_ascii())
+            .filter_map(unicode_emoji_key),
+    );
+    emoji
+}
+
+/// Twemoji knows every Unicode emoji. They are counted without the presentation selector
+/// where possible, messages and reactions don't always agree on it.
+fn unicode_emoji_key(grapheme: &str) -> Option<String> {
+    let plain = grapheme.replace('\u{fe0f}', "");
+    if PngTwemojiAsset::from_emoji(&plain).is_some() {
+        return Some(plain);
+    }
+    PngTwemojiAsset::from_emoji(grapheme).map(|_| grapheme.to_string())
+}
+
+/// The reaction's emoji in the form [`message_emoji`] gives.
+pub fn reaction_emoji(emoji: &ReactionType) -> String {
+    match emoji {
+        ReactionType::Unicode(emoji) => unicode_emoji_key(emoji).unwrap_or(emoji.clone()),
+        emoji => emoji.to_string(),
+    }
+}
+
+/// The id of a custom emoji key, `None` for Unicode emoji.
+pub fn custom_emoji_id(emoji: &str) -> Option<EmojiId> {
+    let id = emoji.strip_suffix('>')?.rsplit(':').next()?;
+    id.parse().ok()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmojiUsage {
+    pub emoji: String,
+    pub messages: usize,
+    pub reactions: usize,
+}
+
+impl EmojiUsage {
+    pub fn total(&self) -> usize {
+        self.messages + self.reactions
+    }
+}
+
+/// How many times `user` used it, or everyone when `None`.
+fn user_count(users: &HashMap<UserId, usize>, user: Option<UserId>) -> usize {
+    match user {
+        Some(user_id) => users.get(&user_id).copied().unwrap_or(0),
+        None => users.values().sum(),
+    }
+}
+
+/// Every emoji used in messages or reactions, the most used first. With `user` only theirs.
+pub fn emoji_usage(stat: &MessageStat, user: Option<UserId>) -> Vec<EmojiUsage> {
+    let mut usage = HashMap::<&String, EmojiUsage>::new();
+    for (emoji, users) in stat.emoji_used.iter() {
+        let entry = usage.entry(emoji).or_insert(EmojiUsage {
+            emoji: emoji.clone(),
+            messages: 0,
+            reactions: 0,
+        });
+        entry.messages += user_count(users, user);
+    }
+    for (emoji, users) in stat.emoji_reacted.iter() {
+        let entry = usage.entry(emoji).or_insert(EmojiUsage {
+            emoji: emoji.clone(),
+            messages: 0,
+            reactions: 0,
+        });
+        entry.reactions += user_count(users, user);
+    }
+    let mut usage = usage
+        .into_values()
+        .filter(|usage| usage.total() > 0)
+        .collect::<Vec<EmojiUsage>>();
+    usage.sort_by(|a, b| b.total().cmp(&a.total()).then(a.emoji.cmp(&b.emoji)));
+    usage
+}
+
+/// The most used emoji and stickers of the period, of one user or of everyone. `None` when
+/// there are none or `top` is 0.
+pub fn format_top_emoji(stat: &MessageStat, top: usize, user: Option<UserId>) -> Option<String> {
+    let mut lines = vec![];
+    let usage = emoji_usage(stat, user);
+    if !usage.is_empty() && top > 0 {
+        lines.push("Top emoji:".to_string());
+        for (index, usage) in usage.iter().take(top).enumerate() {
+            let mut line = format!("{}. {} — {}", index + 1, usage.emoji, usage.total());
+            if usage.reactions > 0 {
+                line.push_str(&format!(", {} as reactions", usage.reactions));
+            }
+            lines.push(line);
+        }
+    }
+
+    let mut stickers = stat
+        .stickers_used
+        .iter()
+        .map(|(id, users)| (id, user_count(users, user)))
+        .filter(|(_, count)| *count > 0)
+        .collect::<Vec<_>>();
+    stickers.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)));
+    stickers.truncate(top);
+    if !stickers.is_empty() {
+        let stickers = stickers
+            .iter()
+            .map(|(id, count)| {
+                let name = stat.sticker_names.get(id).map_or("?", String::as_str);
+                format!("{name} ({count})")
+            })
+            .collect::<Vec<String>>();
+        lines.push(format!("Top stickers: {}", stickers.join(", ")));
+    }
+    match lines.is_empty() {
+        true => None,
+        false => Some(lines.join("\n")),
+    }
+}
+
+pub fn emoji_command() -> CreateCommand {
+    CreateCommand::new("emoji")
+        .description("Emoji usage")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "top",
+                "The most used emoji and stickers of the current period",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "n", "How many emoji")
+                    .min_int_value(1)
+                    .max_int_value(25),
+            )
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::User,
+                "user",
+                "Only the emoji of this user",
+            ))
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::String,
+                "schedule",
+                "Report schedule",
+            )),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "unused",
+            "Custom emoji of the server nobody used in the kept statistics",
+        ))
+}
+
+pub async fn run_emoji_command(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+) {
+    let (subcommand, options) = match command.data.options.first() {
+        Some(CommandDataOption {
+            name,
+            value: CommandDataOptionValue::SubCommand(options),
+            ..
+        }) => (name.as_str(), options.as_slice()),
+        _ => return respond_ephemeral(ctx, command, "Unknown subcommand").await,
+    };
+    command.defer(&ctx.http).await.ok();
+    let option = |name: &str| {
+        options
+            .iter()
+            .find(|option| option.name == name)
+            .map(|option| &option.value)
+    };
+    let schedule = match option("schedule") {
+        Some(CommandDataOptionValue::String(schedule)) => schedule.clone(),
+        _ => conf.report_schedules[0].name.clone(),
+    };
+
+    let embed = match subcommand {
+        "top" => {
+            let count = match option("n") {
+                Some(CommandDataOptionValue::Integer(n)) => *n as usize,
+                _ => 10,
+            };
+            let user = match option("user") {
+                Some(CommandDataOptionValue::User(user_id)) => Some(*user_id),
+                _ => None,
+            };
+            let snapshot = stat
+                .lock()
+                .await
+                .periods
+                .get(&schedule)
+                .map(|period| period.message_stat.snapshot());
+            let description = snapshot
+                .and_then(|snapshot| format_top_emoji(&snapshot, count, user))
+                .unwrap_or("No emoji in this period yet".to_string());
+            let description = match user {
+                Some(user_id) => format!("<@{user_id}>\n{description}"),
+                None => description,
+            };
+            CreateEmbed::new()
+                .title("Top emoji")
+                .description(description)
+                .footer(CreateEmbedFooter::new(format!("Schedule: {schedule}")))
+        }
+        "unused" => unused_embed(ctx, conf, stat, &schedule).await,
+        _ => CreateEmbed::new().description("Unknown subcommand"),
+    };
+
+    let builder = EditInteractionResponse::new().embed(embed.colour(EMBED_COLOUR));
+    if let Err(why) = command.edit_response(&ctx.http, builder).await {
+        tracing::error!("Cannot respond to slash command: {why}");
+    }
+}
+
+/// Looks through the running period and the archive of the schedule, the longer the archive
+/// the more an "unused" emoji means.
+async fn unused_embed(
+    ctx: &Context,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+    schedule: &str,
+) -> CreateEmbed {
+    let embed = CreateEmbed::new().title("Unused custom emoji");
+    let guild_emoji = match conf.guild_id.emojis(&ctx.http).await {
+        Ok(emoji) => emoji,
+        Err(e) => {
+            tracing::warn!("Error listing the emoji: {:?}", e);
+            return embed.description("Could not list the server's emoji");
+        }
+    };
+
+    let archive = read_json_lines::<ArchivedPeriod>(&conf.archive_file);
+    let stat_guard = stat.lock().await;
+    let running = stat_guard.periods.get(schedule);
+    let since = archive
+        .iter()
+        .filter(|period| period.schedule == schedule)
+        .map(|period| period.start)
+        .chain(running.map(|period| period.collection_start))
+        .min();
+    let mut used = HashSet::new();
+    for message_stat in archive
+        .iter()
+        .filter(|period| period.schedule == schedule)
+        .map(|period| &period.message_stat)
+        .chain(running.map(|period| &period.message_stat))
+    {
+        used.extend(used_custom_emoji(message_stat));
+    }
+    drop(stat_guard);
+
+    let mut unused = guild_emoji
+        .iter()
+        .filter(|emoji| !used.contains(&emoji.id))
+        .collect::<Vec<_>>();
+    if unused.is_empty() {
+        return embed.description("Every custom emoji was used");
+    }
+    unused.sort_by(|a, b| a.name.cmp(&b.name));
+    let mut description = match since {
+        Some(since) => format!("Not used since <t:{}:D>:\n", since.timestamp()),
+        None => String::new(),
+    };
+    for emoji in unused {
+        // Embed descriptions are limited to 4096 characters
+        let entry = format!("{emoji} `:{}:`\n", emoji.name);
+        if description.len() + entry.len() > 4000 {
+            description.push('…');
+            break;
+        }
+        description.push_str(&entry);
+    }
+    embed.description(description)
+}
+
+fn used_custom_emoji(stat: &MessageStat) -> HashSet<EmojiId> {
+    stat.emoji_used
+        .iter()
+        .chain(stat.emoji_reacted.iter())
+        .filter(|(_, users)| users.values().any(|count| *count > 0))
+        .filter_map(|(emoji, _)| custom_emoji_id(emoji))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::{StickerId, UserId};
+
+    #[test]
+    fn test_message_emoji() {
+        assert_eq!(
+            message_emoji("hi <:pepe:123> 😂😂 <a:dance:456> ❤️ <@789>"),
+            vec!["<:pepe:123>", "<a:dance:456>", "😂", "😂", "❤"]
+        );
+        let heart = ReactionType::Unicode("❤️".to_string());
+        assert_eq!(reaction_emoji(&heart), "❤");
+        assert!(message_emoji("no emoji here, ünïcode either").is_empty());
+        assert_eq!(custom_emoji_id("<a:dance:456>"), Some(EmojiId::new(456)));
+        assert_eq!(custom_emoji_id("😂"), None);
+    }
+
+    #[test]
+    fn test_top_emoji() {
+        let user = UserId::new;
+        let stat = MessageStat {
+            emoji_used: HashMap::from([
+                (
+                    "😂".to_string(),
+                    HashMap::from([(user(1), 3), (user(2), 1)]),
+                ),
+                ("<:pepe:123>".to_string(), HashMap::from([(user(1), 2)])),
+            ]),
+            emoji_reacted: HashMap::from([(
+                "<:pepe:123>".to_string(),
+                HashMap::from([(user(2), 4)]),
+            )]),
+            stickers_used: HashMap::from([(StickerId::new(5), HashMap::from([(user(1), 2)]))]),
+            sticker_names: HashMap::from([(StickerId::new(5), "Wave".to_string())]),
+            ..MessageStat::default()
+        };
+        assert_eq!(
+            format_top_emoji(&stat, 5, None).unwrap(),
+            "Top emoji:\n\
+             1. <:pepe:123> — 6, 4 as reactions\n\
+             2. 😂 — 4\n\
+             Top stickers: Wave (2)"
+        );
+        assert_eq!(
+            format_top_emoji(&stat, 5, Some(user(2))).unwrap(),
+            "Top emoji:\n\
+             1. <:pepe:123> — 4, 4 as reactions\n\
+             2. 😂 — 1"
+        );
+        assert_eq!(format_top_emoji(&stat, 0, None), None);
+        assert_eq!(used_custom_emoji(&stat), HashSet::from([EmojiId::new(123)]));
+    }
+}
@@ -0,0 +1,486 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serenity::all::{
+    ChannelId, CommandDataOption, CommandDataOptionValue, CommandInteraction, CommandOptionType,
+    CreateAttachment, CreateCommand, CreateCommandOption, EditInteractionResponse, Permissions,
+    UserId,
+};
+use serenity::prelude::*;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+const EXPORT_USAGE: &str = "Usage: discord-bot export [--format csv|json] [--schedule NAME] \
+[--periods N] [--out PATH]
+
+Exports the running period and the archived ones of the schedule, per user and per channel,
+with names and raw IDs. --periods keeps the last N archived periods, --out writes to a file
+instead of the standard output.";
+
+/// Discord's upload limit without server boosts.
+const UPLOAD_LIMIT: usize = 10 * 1024 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    Csv,
+    Json,
+}
+
+impl ExportFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Json => "json",
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_lowercase().as_str() {
+            "csv" => Ok(ExportFormat::Csv),
+            "json" => Ok(ExportFormat::Json),
+            other => Err(format!("unknown export format `{other}`")),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportPeriod {
+    pub schedule: String,
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub current: bool, // still running, the values are a snapshot
+    pub users: Vec<ExportUser>,
+    pub channels: Vec<ExportChannel>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportUser {
+    pub user_id: UserId,
+    pub name: String,
+    #[serde(flatten)]
+    pub values: BTreeMap<&'static str, usize>, // by report column name
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportChannel {
+    pub channel_id: ChannelId,
+    pub name: String,
+    pub messages: usize,
+    pub users: Vec<ExportChannelUser>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExportChannelUser {
+    pub user_id: UserId,
+    pub name: String,
+    pub messages: usize,
+}
+
+/// The latest name the archive knows, for the running period when Discord is not at hand.
+pub struct ArchiveNames<'a>(pub &'a [ArchivedPeriod]);
+
+impl NameResolver for ArchiveNames<'_> {
+    async fn user_name(&self, user_id: UserId) -> String {
+        self.0
+            .iter()
+            .rev()
+            .find_map(|period| period.names.get(&user_id).cloned())
+            .unwrap_or_else(|| user_id.to_string())
+    }
+
+    async fn channel_name(&self, channel_id: ChannelId) -> String {
+        self.0
+            .iter()
+            .rev()
+            .find_map(|period| period.channel_names.get(&channel_id).cloned())
+            .unwrap_or_else(|| channel_id.to_string())
+    }
+}
+
+pub async fn export_period(
+    schedule: &str,
+    (start, end): (DateTime<Utc>, DateTime<Utc>),
+    current: bool,
+    stat: &MessageStat,
+    names: &impl NameResolver,
+) -> ExportPeriod {
+    let mut users = vec![];
+    for user_id in stat.users() {
+        let values = ReportColumn::ALL
+            .into_iter()
+            .map(|column| (column.name(), stat.value(column, &user_id)))
+            .collect();
+        users.push(ExportUser {
+            user_id,
+            name: names.user_name(user_id).await,
+            values,
+        });
+    }
+
+    let mut channels = vec![];
+    let channel_messages = stat.channel_messages.iter().collect::<BTreeMap<_, _>>();
+    for (channel_id, counts) in channel_messages {
+        let mut channel_users = vec![];
+        for (user_id, messages) in counts.iter().collect::<BTreeMap<_, _>>() {
+            channel_users.push(ExportChannelUser {
+                user_id: *user_id,
+                name: names.user_name(*user_id).await,
+                messages: *messages,
+            });
+        }
+        channels.push(ExportChannel {
+            channel_id: *channel_id,
+            name: names.channel_name(*channel_id).await,
+            messages: counts.values().sum(),
+            users: channel_users,
+        });
+    }
+
+    ExportPeriod {
+        schedule: schedule.to_string(),
+        start,
+        end,
+        current,
+        users,
+        channels,
+    }
+}
+
+/// The last `last` archived periods of the schedule, oldest first, and the running one.
+/// Archived periods use the names they were reported with.
+pub async fn export_schedule(
+    schedule: &str,
+    archive: &[ArchivedPeriod],
+    last: Option<usize>,
+    running: Option<&StatPeriod>,
+    names: &impl NameResolver,
+) -> Vec<ExportPeriod> {
+    let skip = archive.len().saturating_sub(last.unwrap_or(archive.len()));
+    let mut periods = vec![];
+    for period in archive.iter().skip(skip) {
+        let span = (period.start, period.end);
+        let stat = &period.message_stat;
+        periods.push(export_period(schedule, span, false, stat, period).await);
+    }
+    if let Some(running) = running {
+        let span = (running.collection_start, running.collect_until);
+        let snapshot = running.message_stat.snapshot();
+        periods.push(export_period(schedule, span, true, &snapshot, names).await);
+    }
+    periods
+}
+
+pub fn format_export(periods: &[ExportPeriod], format: ExportFormat) -> String {
+    match format {
+        ExportFormat::Csv => to_csv(periods),
+        ExportFormat::Json => serde_json::to_string_pretty(periods).unwrap_or_default(),
+    }
+}
+
+/// One row per user and period, then one per channel, user and period. Channel rows only
+/// have the messages.
+fn to_csv(periods: &[ExportPeriod]) -> String {
+    let mut header = [
+        "scope",
+        "schedule",
+        "period_start",
+        "period_end",
+        "current",
+        "user_id",
+        "user_name",
+        "channel_id",
+        "channel_name",
+    ]
+    .map(String::from)
+    .to_vec();
+    header.extend(
+        ReportColumn::ALL
+            .iter()
+            .map(|column| column.name().to_string()),
+    );
+    let mut lines = vec![csv_row(&header)];
+
+    for period in periods {
+        let prefix = |scope: &str| {
+            vec![
+                scope.to_string(),
+                period.schedule.clone(),
+                period.start.to_rfc3339(),
+                period.end.to_rfc3339(),
+                period.current.to_string(),
+            ]
+        };
+        for user in period.users.iter() {
+            let mut row = prefix("user");
+            row.extend([
+                user.user_id.to_string(),
+                user.name.clone(),
+                String::new(),
+                String::new(),
+            ]);
+            row.extend(ReportColumn::ALL.iter().map(|column| {
+                user.values
+                    .get(column.name())
+                    .copied()
+                    .unwrap_or(0)
+                    .to_string()
+            }));
+            lines.push(csv_row(&row));
+        }
+        for channel in period.channels.iter() {
+            for user in channel.users.iter() {
+                let mut row = prefix("channel");
+                row.extend([
+                    user.user_id.to_string(),
+                    user.name.clone(),
+                    channel.channel_id.to_string(),
+                    channel.name.clone(),
+                ]);
+                row.extend(ReportColumn::ALL.iter().map(|column| match column {
+                    ReportColumn::Messages => user.messages.to_string(),
+                    _ => String::new(),
+                }));
+                lines.push(csv_row(&row));
+            }
+        }
+    }
+    lines.join("\n") + "\n"
+}
+
+/// Quotes the fields with separators, quotes or line breaks, names can have any of them.
+/// A name that a spreadsheet would take for a formula is kept as text.
+fn csv_row(fields: &[String]) -> String {
+    fields
+        .iter()
+        .map(|field| match field.starts_with(['=', '+', '-', '@']) {
+            true => format!("'{field}"),
+            false => field.clone(),
+        })
+        .map(|field| match field.contains([',', '"', '\n', '\r']) {
+            true => format!("\"{}\"", field.replace('"', "\"\"")),
+            false => field,
+        })
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+pub fn export_command() -> CreateCommand {
+    CreateCommand::new("export")
+        .description("Export the statistics as a file")
+        .default_member_permissions(Permissions::MANAGE_GUILD)
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::String, "format", "File format")
+                .add_string_choice("CSV", "csv")
+                .add_string_choice("JSON", "json"),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "schedule",
+            "Report schedule",
+        ))
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "periods",
+                "Archived periods to include, all by default",
+            )
+            .min_int_value(0),
+        )
+}
+
+pub async fn run_export_command(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+) {
+    command.defer(&ctx.http).await.ok();
+
+    let option = |name: &str| {
+        command
+            .data
+            .options
+            .iter()
+            .find(|option| option.name == name)
+            .map(|option: &CommandDataOption| &option.value)
+    };
+    let format = match option("format") {
+        Some(CommandDataOptionValue::String(format)) => format.parse().unwrap_or(ExportFormat::Csv),
+        _ => ExportFormat::Csv,
+    };
+    let schedule = match option("schedule") {
+        Some(CommandDataOptionValue::String(schedule)) => schedule.clone(),
+        _ => conf.report_schedules[0].name.clone(),
+    };
+    let last = match option("periods") {
+        Some(CommandDataOptionValue::Integer(periods)) => Some(*periods as usize),
+        _ => None,
+    };
+
+    let archive = load_archive(&conf.archive_file, &schedule);
+    let running = stat.lock().await.periods.get(&schedule).cloned();
+    let names = DiscordNames {
+        cache_http: &ctx.http,
+        conf,
+    };
+    let periods = export_schedule(&schedule, &archive, last, running.as_ref(), &names).await;
+
+    let builder = match periods.is_empty() {
+        true => EditInteractionResponse::new().content(format!("No statistics for {schedule}")),
+        false => {
+            let content = format_export(&periods, format);
+            let file_name = format!("stats-{schedule}.{}", format.extension());
+            match content.len() > UPLOAD_LIMIT {
+                true => EditInteractionResponse::new().content(format!(
+                    "The export of {} periods is {} MB, over Discord's upload limit. \
+                     Use `periods` to export fewer",
+                    periods.len(),
+                    content.len().div_ceil(1024 * 1024)
+                )),
+                false => EditInteractionResponse::new()
+                    .content(format!("{} periods of {schedule}", periods.len()))
+                    .new_attachment(CreateAttachment::bytes(content.into_bytes(), file_name)),
+            }
+        }
+    };
+    if let Err(why) = command.edit_response(&ctx.http, builder).await {
+        tracing::error!("Error responding to command: {why:?}");
+    }
+}
+
+/// `discord-bot export ...`, reads the statistics and archive files only.
+pub async fn run_export_cli(args: &[String]) -> i32 {
+    let args = CliArgs::parse(args, &[]);
+    if !args.positional.is_empty() {
+        eprintln!("{EXPORT_USAGE}");
+        return 2;
+    }
+    let conf = init_offline_config();
+    let format = match args
+        .value("format")
+        .unwrap_or("csv")
+        .parse::<ExportFormat>()
+    {
+        Ok(format) => format,
+        Err(e) => {
+            eprintln!("{e}\n\n{EXPORT_USAGE}");
+            return 2;
+        }
+    };
+    let last = match args.value("periods").map(str::parse::<usize>) {
+        Some(Ok(last)) => Some(last),
+        Some(Err(_)) => {
+            eprintln!("--periods must be a number\n\n{EXPORT_USAGE}");
+            return 2;
+        }
+        None => None,
+    };
+    let main = &conf.report_schedules[0].name;
+    let schedule = args.value("schedule").unwrap_or(main);
+
+    let stat = match Stat::load_from_file(&conf.stat_file, conf.stat_backups, main) {
+        Ok(stat) => stat,
+        Err(e) => {
+            eprintln!("Error loading statistics from {}: {e}", conf.stat_file);
+            return 1;
+        }
+    };
+    let archive = load_archive(&conf.archive_file, schedule);
+    let running = stat.periods.get(schedule);
+    let names = ArchiveNames(&archive);
+    let periods = export_schedule(schedule, &archive, last, running, &names).await;
+    let content = format_export(&periods, format);
+
+    match args.value("out") {
+        Some(path) => match std::fs::write(path, content) {
+            Ok(()) => {
+                println!("Exported {} periods of {schedule} to {path}", periods.len());
+                0
+            }
+            Err(e) => {
+                eprintln!("Error writing {path}: {e}");
+                1
+            }
+        },
+        None => {
+            print!("{content}");
+            0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn archived() -> ArchivedPeriod {
+        let (alice, bob) = (UserId::new(1), UserId::new(2));
+        let channel = ChannelId::new(10);
+        ArchivedPeriod {
+            schedule: "weekly".to_string(),
+            start: "2025-03-03T00:00:00Z".parse().unwrap(),
+            end: "2025-03-10T00:00:00Z".parse().unwrap(),
+            names: HashMap::from([
+                (alice, "Алиса, \"admin\"".to_string()),
+                (bob, "bob".to_string()),
+            ]),
+            channel_names: HashMap::from([(channel, "general".to_string())]),
+            message_stat: MessageStat {
+                messages_count: HashMap::from([(alice, 3), (bob, 1)]),
+                attachments_count: HashMap::from([(bob, 2)]),
+                channel_messages: HashMap::from([(channel, HashMap::from([(alice, 3), (bob, 1)]))]),
+                ..MessageStat::default()
+            },
+            table: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_csv_and_json() {
+        let archive = [archived()];
+        let running = StatPeriod {
+            collection_start: archive[0].end,
+            collect_until: "2025-03-17T00:00:00Z".parse().unwrap(),
+            message_stat: MessageStat {
+                messages_count: HashMap::from([(UserId::new(2), 5)]),
+                ..MessageStat::default()
+            },
+            ..StatPeriod::default()
+        };
+        let names = ArchiveNames(&archive);
+        let periods = export_schedule("weekly", &archive, None, Some(&running), &names).await;
+        assert_eq!(periods.len(), 2);
+        assert!(periods[1].current);
+        // The running period takes the names from the archive
+        assert_eq!(periods[1].users[0].name, "bob");
+
+        let csv = format_export(&periods, ExportFormat::Csv);
+        let lines = csv.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 1 + 2 + 2 + 1);
+        assert!(lines[0].starts_with("scope,schedule,period_start,period_end,current,user_id"));
+        assert!(lines[1].starts_with(
+            "user,weekly,2025-03-03T00:00:00+00:00,2025-03-10T00:00:00+00:00,false,1,\
+             \"Алиса, \"\"admin\"\"\",,,3,0,0,"
+        ));
+        assert!(lines[3].starts_with("channel,weekly,"));
+        assert!(lines[3].contains(",10,general,3,,"));
+        let row = ["=1+1", "@bob, hi", "42"].map(String::from);
+        assert_eq!(csv_row(&row), "'=1+1,\"'@bob, hi\",42");
+
+        let json = format_export(&periods, ExportFormat::Json);
+        let value = serde_json::from_str::<serde_json::Value>(&json).unwrap();
+        assert_eq!(value[0]["users"][1]["files"], 2);
+        assert_eq!(value[0]["users"][1]["user_id"], "2");
+        assert_eq!(value[0]["channels"][0]["name"], "general");
+        assert_eq!(value[0]["channels"][0]["messages"], 4);
+
+        let last = export_schedule("weekly", &archive, Some(0), None, &names).await;
+        assert!(last.is_empty());
+    }
+}
@@ -0,0 +1,359 @@
+use super::*;
+
+use chrono::{DateTime, Datelike, Utc};
+use ollama_rs::generation::chat::ChatMessage;
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    ChannelId, CommandDataOptionValue, CommandInteraction, CommandOptionType, CreateCommand,
+    CreateCommandOption, EditInteractionResponse, MessageId, Reaction, ReactionType, UserId,
+};
+use serenity::prelude::*;
+use std::collections::{BTreeMap, HashMap};
+
+/// A bot reply produced by the LLM, together with the context it was generated from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrackedReply {
+    pub message_id: MessageId,
+    pub channel_id: ChannelId,
+    pub created_at: DateTime<Utc>,
+    pub persona: String,
+    pub model: String,
+    pub prompt: Vec<ChatMessage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Vote {
+    Up,
+    Down,
+}
+
+impl Vote {
+    pub fn from_reaction(emoji: &ReactionType) -> Option<Vote> {
+        match emoji {
+            // Skin tone modifiers follow the base emoji
+            ReactionType::Unicode(e) if e.starts_with('👍') => Some(Vote::Up),
+            ReactionType::Unicode(e) if e.starts_with('👎') => Some(Vote::Down),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum VoteAction {
+    Add,
+    Remove,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FeedbackEvent {
+    pub timestamp: DateTime<Utc>,
+    pub message_id: MessageId,
+    pub user_id: UserId,
+    pub vote: Vote,
+    pub action: VoteAction,
+    pub persona: String,
+    pub model: String,
+}
+
+#[derive(Debug, Default)]
+pub struct Feedback {
+    replies: HashMap<MessageId, TrackedReply>,
+    pruned_at: DateTime<Utc>,
+}
+
+impl Feedback {
+    /// Restores the replies that are recent enough to still collect reactions.
+    pub fn load(conf: &Config) -> Feedback {
+        let replies = read_json_lines::<TrackedReply>(&conf.replies_file)
+            .into_iter()
+            .map(|reply| (reply.message_id, reply))
+            .collect::<HashMap<MessageId, TrackedReply>>();
+        let mut feedback = Feedback {
+            replies,
+            pruned_at: DateTime::default(),
+        };
+        feedback.prune(conf, Utc::now());
+        tracing::info!(
+            "Tracking reactions on {} bot replies",
+            feedback.replies.len()
+        );
+        feedback
+    }
+
+    pub fn track(&mut self, conf: &Config, reply: TrackedReply) {
+        if let Err(e) = append_json_line(&conf.replies_file, &reply) {
+            tracing::error!("Error saving tracked reply: {}", e);
+        }
+        self.replies.insert(reply.message_id, reply);
+        let now = Utc::now();
+        if now - self.pruned_at >= chrono::Duration::days(1) {
+            self.prune(conf, now);
+        }
+    }
+
+    /// Forgets the replies too old to collect reactions and rewrites the file without them,
+    /// their prompts aren't needed anymore.
+    fn prune(&mut self, conf: &Config, now: DateTime<Utc>) {
+        self.pruned_at = now;
+        let since = now - chrono::Duration::days(conf.feedback_track_days);
+        let count = self.replies.len();
+        self.replies.retain(|_, reply| reply.created_at >= since);
+        if self.replies.len() == count {
+            return;
+        }
+
+        let mut replies = self.replies.values().collect::<Vec<&TrackedReply>>();
+        replies.sort_by_key(|reply| reply.created_at);
+        let mut bytes = vec![];
+        for reply in replies {
+            match serde_json::to_vec(reply) {
+                Ok(line) => bytes.extend(line),
+                Err(e) => {
+                    tracing::error!("Error serializing tracked reply: {}", e);
+                    return;
+                }
+            }
+            bytes.push(b'\n');
+        }
+        if let Err(e) = write_atomic(std::path::Path::new(&conf.replies_file), &bytes) {
+            tracing::error!("Error pruning tracked replies: {}", e);
+        }
+    }
+
+    pub fn record_reaction(
+        &self,
+        conf: &Config,
+        reaction: &Reaction,
+        action: VoteAction,
+        self_id: UserId,
+    ) {
+        let reply = match self.replies.get(&reaction.message_id) {
+            Some(reply) => reply,
+            None => return,
+        };
+        let vote = match Vote::from_reaction(&reaction.emoji) {
+            Some(vote) => vote,
+            None => return,
+        };
+        let user_id = match reaction.user_id {
+            Some(user_id) if user_id != self_id => user_id,
+            _ => return,
+        };
+
+        let event = FeedbackEvent {
+            timestamp: Utc::now(),
+            message_id: reaction.message_id,
+            user_id,
+            vote,
+            action,
+            persona: reply.persona.clone(),
+            model: reply.model.clone(),
+        };
+        tracing::info!(
+            "Feedback {:?} {:?} on {}",
+            event.action,
+            vote,
+            event.message_id
+        );
+        if let Err(e) = append_json_line(&conf.feedback_file, &event) {
+            tracing::error!("Error saving feedback: {}", e);
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Satisfaction {
+    pub up: usize,
+    pub down: usize,
+}
+
+impl Satisfaction {
+    pub fn percent(&self) -> usize {
+        match self.up + self.down {
+            0 => 0,
+            total => self.up * 100 / total,
+        }
+    }
+}
+
+/// Week start (Monday) -> (persona, model) -> votes. Only the latest action of a user on a
+/// reply counts, so a removed reaction is not a vote.
+pub fn satisfaction_by_week(
+    events: &[FeedbackEvent],
+) -> BTreeMap<chrono::NaiveDate, BTreeMap<(String, String), Satisfaction>> {
+    let mut latest: HashMap<(MessageId, UserId, Vote), &FeedbackEvent> = HashMap::new();
+    for event in events {
+        latest.insert((event.message_id, event.user_id, event.vote), event);
+    }
+
+    let mut result: BTreeMap<_, BTreeMap<_, Satisfaction>> = BTreeMap::new();
+    for event in latest.values() {
+        if event.action == VoteAction::Remove {
+            continue;
+        }
+        let date = event.timestamp.date_naive();
+        let week = date - chrono::Duration::days(date.weekday().num_days_from_monday() as i64);
+        let entry = result
+            .entry(week)
+            .or_default()
+            .entry((event.persona.clone(), event.model.clone()))
+            .or_default();
+        match event.vote {
+            Vote::Up => entry.up += 1,
+            Vote::Down => entry.down += 1,
+        }
+    }
+    result
+}
+
+pub fn format_satisfaction_table(
+    weeks: &BTreeMap<chrono::NaiveDate, BTreeMap<(String, String), Satisfaction>>,
+) -> String {
+    let mut rows = vec![];
+    for (week, by_source) in weeks.iter() {
+        for ((persona, model), satisfaction) in by_source.iter() {
+            rows.push(vec![
+                week.format("%Y-%m-%d").to_string(),
+                persona.clone(),
+                model.clone(),
+                satisfaction.up.to_string(),
+                satisfaction.down.to_string(),
+                format!("{}%", satisfaction.percent()),
+            ]);
+        }
+    }
+
+    format_table(&["Week", "Persona", "Model", "Up", "Down", "Score"], &rows)
+}
+
+pub fn feedback_command() -> CreateCommand {
+    CreateCommand::new("feedback")
+        .description("Show reaction feedback on bot replies per persona and model")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "weeks",
+                "How many weeks to show",
+            )
+            .min_int_value(1)
+            .max_int_value(52),
+        )
+}
+
+pub async fn run_feedback_command(ctx: &Context, command: &CommandInteraction, conf: &Config) {
+    command.defer(&ctx.http).await.ok();
+
+    let weeks = command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == "weeks")
+        .and_then(|option| match option.value {
+            CommandDataOptionValue::Integer(weeks) => Some(weeks),
+            _ => None,
+        })
+        .unwrap_or(4);
+    let since = Utc::now() - chrono::Duration::weeks(weeks);
+    let events = read_json_lines::<FeedbackEvent>(&conf.feedback_file)
+        .into_iter()
+        .filter(|event| event.timestamp >= since)
+        .collect::<Vec<FeedbackEvent>>();
+
+    let content = match events.is_empty() {
+        true => "No feedback yet".to_string(),
+        false => {
+            let table = format_satisfaction_table(&satisfaction_by_week(&events));
+            format!("```\n{table}\n```").chars().take(2000).collect()
+        }
+    };
+    let builder = EditInteractionResponse::new().content(content);
+    if let Err(why) = command.edit_response(&ctx.http, builder).await {
+        tracing::error!("Error responding to command: {why:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(
+        message: u64,
+        user: u64,
+        vote: Vote,
+        action: VoteAction,
+        model: &str,
+    ) -> FeedbackEvent {
+        FeedbackEvent {
+            timestamp: "2025-03-05T12:00:00Z".parse().unwrap(),
+            message_id: MessageId::new(message),
+            user_id: UserId::new(user),
+            vote,
+            action,
+            persona: "default".to_string(),
+            model: model.to_string(),
+        }
+    }
+
+    #[test]
+    fn test_removed_reaction_does_not_count() {
+        let events = vec![
+            event(1, 10, Vote::Up, VoteAction::Add, "llama"),
+            event(1, 11, Vote::Up, VoteAction::Add, "llama"),
+            event(1, 11, Vote::Up, VoteAction::Remove, "llama"),
+            event(2, 10, Vote::Down, VoteAction::Add, "llama"),
+            event(3, 10, Vote::Up, VoteAction::Add, "qwen"),
+        ];
+        let weeks = satisfaction_by_week(&events);
+        let week = weeks
+            .get(&chrono::NaiveDate::from_ymd_opt(2025, 3, 3).unwrap())
+            .unwrap();
+
+        let llama = week
+            .get(&("default".to_string(), "llama".to_string()))
+            .unwrap();
+        assert_eq!(*llama, Satisfaction { up: 1, down: 1 });
+        assert_eq!(llama.percent(), 50);
+        let qwen = week
+            .get(&("default".to_string(), "qwen".to_string()))
+            .unwrap();
+        assert_eq!(*qwen, Satisfaction { up: 1, down: 0 });
+    }
+
+    #[test]
+    fn test_vote_from_reaction() {
+        let thumbs_up = ReactionType::Unicode("👍🏽".to_string());
+        assert_eq!(Vote::from_reaction(&thumbs_up), Some(Vote::Up));
+        let heart = ReactionType::Unicode("❤️".to_string());
+        assert_eq!(Vote::from_reaction(&heart), None);
+    }
+
+    #[test]
+    fn test_load_prunes_old_replies() {
+        let dir = std::env::temp_dir().join(format!("feedback-{}", uuid::Uuid::new_v4()));
+        let conf = Config {
+            replies_file: dir.join("replies.jsonl").to_string_lossy().to_string(),
+            feedback_track_days: 30,
+            ..Config::default()
+        };
+        let reply = |message: u64, days_ago: i64| TrackedReply {
+            message_id: MessageId::new(message),
+            channel_id: ChannelId::new(1),
+            created_at: Utc::now() - chrono::Duration::days(days_ago),
+            persona: "default".to_string(),
+            model: "llama".to_string(),
+            prompt: vec![],
+        };
+        append_json_line(&conf.replies_file, &reply(1, 40)).unwrap();
+        append_json_line(&conf.replies_file, &reply(2, 1)).unwrap();
+
+        let feedback = Feedback::load(&conf);
+        assert_eq!(feedback.replies.len(), 1);
+        let kept = read_json_lines::<TrackedReply>(&conf.replies_file);
+        assert_eq!(kept.len(), 1);
+        assert_eq!(kept[0].message_id, MessageId::new(2));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
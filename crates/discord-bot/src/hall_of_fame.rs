@@ -0,0 +1,454 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, CreateMessage, Http, Message, UserId};
+use serenity::prelude::*;
+
+const MAX_BROKEN: usize = 200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordKind {
+    LongestSeries,
+    PeriodMessages,
+    PeriodFiles,
+}
+
+impl RecordKind {
+    pub fn title(&self) -> &'static str {
+        match self {
+            RecordKind::LongestSeries => "Longest series",
+            RecordKind::PeriodMessages => "Most messages in a period",
+            RecordKind::PeriodFiles => "Most files in a period",
+        }
+    }
+}
+
+/// An all-time record. `at` is when the series or the period started, the same attempt going
+/// on is the record growing rather than a new one, see [`FameRecord::same_attempt`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FameRecord {
+    pub kind: RecordKind,
+    pub user_id: UserId,
+    pub value: usize,
+    pub at: DateTime<Utc>,
+    pub channel_id: Option<ChannelId>,
+    pub link: Option<String>, // first message of the series
+}
+
+impl FameRecord {
+    /// A series is its holder's, a period is one attempt whoever leads it: users trading the
+    /// lead of the running period beat the record as it stood when the period started, once.
+    fn same_attempt(&self, other: &FameRecord) -> bool {
+        self.kind == other.kind
+            && self.at == other.at
+            && (self.user_id == other.user_id || self.kind != RecordKind::LongestSeries)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BrokenRecord {
+    pub record: FameRecord,
+    pub previous: FameRecord,
+    pub broken_at: DateTime<Utc>,
+}
+
+/// All-time records of the main schedule, kept apart from the periods and saved with the
+/// statistics.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct HallOfFame {
+    pub records: Vec<FameRecord>,
+    pub seeded: bool, // the records include the archive
+    pub broken: Vec<BrokenRecord>,
+    pub announcements: Vec<BrokenRecord>, // broken, not yet announced
+}
+
+impl HallOfFame {
+    /// Takes the candidate if it beats the record. The very first record of a kind and the
+    /// same attempt growing are taken silently, a new attempt is announced.
+    pub fn offer(&mut self, candidate: FameRecord, now: DateTime<Utc>, announce: bool) {
+        let index = self.records.iter().position(|r| r.kind == candidate.kind);
+        let current = match index {
+            Some(index) => &mut self.records[index],
+            None => {
+                if candidate.value > 0 {
+                    self.records.push(candidate);
+                }
+                return;
+            }
+        };
+        if candidate.value <= current.value {
+            return;
+        }
+        if current.same_attempt(&candidate) {
+            let previous = std::mem::replace(current, candidate.clone());
+            for broken in self.broken.iter_mut().chain(self.announcements.iter_mut()) {
+                if broken.record.same_attempt(&previous) {
+                    broken.record = candidate.clone();
+                }
+            }
+            return;
+        }
+        let broken = BrokenRecord {
+            record: candidate.clone(),
+            previous: std::mem::replace(current, candidate),
+            broken_at: now,
+        };
+        tracing::info!(
+            "User {} broke the all-time record {:?} with {}",
+            broken.record.user_id,
+            broken.record.kind,
+            broken.record.value
+        );
+        if announce {
+            self.announcements.push(broken.clone());
+        }
+        self.broken.push(broken);
+        if self.broken.len() > MAX_BROKEN {
+            self.broken.remove(0);
+        }
+    }
+
+    /// Offers the records of a period: its longest series and the period counters.
+    pub fn offer_period(
+        &mut self,
+        stat: &MessageStat,
+        start: DateTime<Utc>,
+        now: DateTime<Utc>,
+        announce: bool,
+    ) {
+        for (user_id, record) in stat.personal_record.iter() {
+            self.offer(series_record(*user_id, record, start), now, announce);
+        }
+        for (kind, counts) in [
+            (RecordKind::PeriodMessages, &stat.messages_count),
+            (RecordKind::PeriodFiles, &stat.attachments_count),
+        ] {
+            let best = counts.iter().max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)));
+            if let Some((user_id, value)) = best {
+                let candidate = FameRecord {
+                    kind,
+                    user_id: *user_id,
+                    value: *value,
+                    at: start,
+                    channel_id: None,
+                    link: None,
+                };
+                self.offer(candidate, now, announce);
+            }
+        }
+    }
+
+    /// Checks the running series of the message and the counters of the main period.
+    pub fn record_message(&mut self, msg: &Message, lifetime: &MessageStat, period: &StatPeriod) {
+        let now = timestamp_to_utc(&msg.timestamp);
+        let user_id = msg.author.id;
+        let running = lifetime
+            .current_by_channel
+            .get(&msg.channel_id)
+            .filter(|streak| streak.user_id == user_id);
+        if let Some(streak) = running {
+            let mut record = MessageStreakPersonalRecord::default();
+            record.update_record(streak, msg.channel_id);
+            self.offer(series_record(user_id, &record, now), now, true);
+        }
+        let stat = &period.message_stat;
+        for (kind, counts) in [
+            (RecordKind::PeriodMessages, &stat.messages_count),
+            (RecordKind::PeriodFiles, &stat.attachments_count),
+        ] {
+            let candidate = FameRecord {
+                kind,
+                user_id,
+                value: counts.get(&user_id).copied().unwrap_or(0),
+                at: period.collection_start,
+                channel_id: None,
+                link: None,
+            };
+            self.offer(candidate, now, true);
+        }
+    }
+
+    pub fn broken_between(&self, start: DateTime<Utc>, end: DateTime<Utc>) -> Vec<&BrokenRecord> {
+        self.broken
+            .iter()
+            .filter(|broken| start <= broken.broken_at && broken.broken_at < end)
+            .collect()
+    }
+
+    /// Drops the records of the user, the next best one takes over with time.
+    pub fn purge_user(&mut self, user_id: UserId) -> bool {
+        self.purge(|record| record.user_id == user_id)
+    }
+
+    pub fn purge_channel(&mut self, channel_id: ChannelId) -> bool {
+        self.purge(|record| record.channel_id == Some(channel_id))
+    }
+
+    fn purge(&mut self, matches: impl Fn(&FameRecord) -> bool) -> bool {
+        let records = self.records.len();
+        self.records.retain(|record| !matches(record));
+        let keep = |broken: &BrokenRecord| !matches(&broken.record) && !matches(&broken.previous);
+        self.broken.retain(keep);
+        self.announcements.retain(keep);
+        self.records.len() != records
+    }
+}
+
+fn series_record(
+    user_id: UserId,
+    record: &MessageStreakPersonalRecord,
+    fallback_at: DateTime<Utc>,
+) -> FameRecord {
+    FameRecord {
+        kind: RecordKind::LongestSeries,
+        user_id,
+        value: record.counter,
+        at: record.start.unwrap_or(fallback_at),
+        channel_id: Some(record.channel_id),
+        link: record.jump_link(),
+    }
+}
+
+/// Fills the records from the archive and the running period of the main schedule, once.
+pub fn seed_hall_of_fame(stat: &mut Stat, conf: &Config) {
+    if stat.hall_of_fame.seeded {
+        return;
+    }
+    let main = &conf.report_schedules[0].name;
+    let now = Utc::now();
+    let archive = load_archive(&conf.archive_file, main);
+    let running = stat
+        .periods
+        .get(main)
+        .map(|period| (period.message_stat.snapshot(), period.collection_start));
+    let periods = archive
+        .into_iter()
+        .map(|period| (period.message_stat, period.start))
+        .chain(running);
+    for (message_stat, start) in periods {
+        stat.hall_of_fame
+            .offer_period(&message_stat, start, now, false);
+    }
+    // What was taken over from the archive was not broken now
+    stat.hall_of_fame.broken.clear();
+    stat.hall_of_fame.seeded = true;
+}
+
+/// `Longest series: name — 15 in #general, 2025-03-05 (was 12 by bob)`.
+pub async fn format_broken_record(
+    broken: &BrokenRecord,
+    names: &impl NameResolver,
+    tz: Tz,
+) -> String {
+    let record = &broken.record;
+    let name = names.user_name(record.user_id).await;
+    let mut line = format!("{}: {name} — {}", record.kind.title(), record.value);
+    if let Some(channel_id) = record.channel_id {
+        line.push_str(&format!(" in #{}", names.channel_name(channel_id).await));
+    }
+    line.push_str(&format!(
+        ", {}",
+        record.at.with_timezone(&tz).format("%Y-%m-%d")
+    ));
+    let previous_name = names.user_name(broken.previous.user_id).await;
+    line.push_str(&format!(
+        " (was {} by {previous_name})",
+        broken.previous.value
+    ));
+    if let Some(link) = record.link.as_ref() {
+        line.push_str(&format!(" [jump](<{link}>)"));
+    }
+    line
+}
+
+/// The report section of the records broken between `start` and `end`.
+pub async fn format_broken_records(
+    hall_of_fame: &HallOfFame,
+    start: DateTime<Utc>,
+    end: DateTime<Utc>,
+    names: &impl NameResolver,
+    tz: Tz,
+) -> Option<String> {
+    let broken = hall_of_fame.broken_between(start, end);
+    if broken.is_empty() {
+        return None;
+    }
+    let mut lines = vec!["Records broken this period:".to_string()];
+    for broken in broken {
+        lines.push(format!(
+            "- {}",
+            format_broken_record(broken, names, tz).await
+        ));
+    }
+    Some(lines.join("\n"))
+}
+
+/// Sends the records broken by the latest messages to `RECORDS_CHANNEL_ID` right away. A
+/// failed send is retried with the next message.
+pub async fn announce_records(http: &Http, stat: &Mutex<Stat>, conf: &Config) {
+    let mut stat_guard = stat.lock().await;
+    if stat_guard.hall_of_fame.announcements.is_empty() {
+        return;
+    }
+    let announcements = std::mem::take(&mut stat_guard.hall_of_fame.announcements);
+    drop(stat_guard);
+
+    let names = DiscordNames {
+        cache_http: http,
+        conf,
+    };
+    let mut lines = vec![];
+    for broken in announcements.iter() {
+        let line = format_broken_record(broken, &names, conf.report_timezone()).await;
+        lines.push(format!("🏅 New all-time record! {line}"));
+    }
+    let message = CreateMessage::new().content(lines.join("\n"));
+    if let Err(e) = conf.records_channel_id.send_message(http, message).await {
+        tracing::error!("Error announcing records: {:?}", e);
+        let mut stat_guard = stat.lock().await;
+        let queued = std::mem::take(&mut stat_guard.hall_of_fame.announcements);
+        stat_guard.hall_of_fame.announcements = announcements;
+        stat_guard.hall_of_fame.announcements.extend(queued);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::MessageId;
+
+    struct FakeNames;
+
+    impl NameResolver for FakeNames {
+        async fn user_name(&self, user_id: UserId) -> String {
+            format!("user{}", user_id.get())
+        }
+
+        async fn channel_name(&self, channel_id: ChannelId) -> String {
+            format!("channel-{}", channel_id.get())
+        }
+    }
+
+    fn record(hall_of_fame: &HallOfFame, kind: RecordKind) -> Option<&FameRecord> {
+        hall_of_fame
+            .records
+            .iter()
+            .find(|record| record.kind == kind)
+    }
+
+    fn message(id: u64, author: u64, time: &str) -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(id);
+        msg.author.id = UserId::new(author);
+        msg.channel_id = ChannelId::new(5);
+        msg.timestamp = serenity::all::Timestamp::parse(time).unwrap();
+        msg
+    }
+
+    #[tokio::test]
+    async fn test_records_broken_once() {
+        let conf = Config {
+            report_schedules: vec![Schedule::parse("weekly=weekly sun 00:00").unwrap()],
+            ..Config::default()
+        };
+        let mut stat = Stat::default();
+        let week_start = "2025-03-03T00:00:00Z".parse().unwrap();
+        stat.periods.insert(
+            "weekly".to_string(),
+            StatPeriod {
+                collection_start: week_start,
+                ..StatPeriod::default()
+            },
+        );
+        // Seeded from an earlier week: a series of 2 and 2 messages by user 1
+        let mut earlier = MessageStat::default();
+        for id in [1, 2] {
+            earlier.record_message(
+                &message(id, 1, "2025-02-25T10:00:00Z"),
+                chrono_tz::UTC,
+                &StreakRules::default(),
+            );
+        }
+        let earlier_start = "2025-02-24T00:00:00Z".parse().unwrap();
+        let hall_of_fame = &mut stat.hall_of_fame;
+        hall_of_fame.offer_period(&earlier.snapshot(), earlier_start, week_start, false);
+        assert!(hall_of_fame.broken.is_empty());
+        assert_eq!(
+            record(hall_of_fame, RecordKind::PeriodMessages)
+                .unwrap()
+                .value,
+            2
+        );
+
+        for id in 10..14 {
+            stat.record_message(&message(id, 2, "2025-03-05T10:00:00Z"), &conf);
+        }
+        let hall_of_fame = &stat.hall_of_fame;
+        let series = record(hall_of_fame, RecordKind::LongestSeries).unwrap();
+        assert_eq!((series.user_id, series.value), (UserId::new(2), 4));
+        assert_eq!(
+            series.link.as_deref(),
+            Some("https://discord.com/channels/@me/5/10")
+        );
+        // Both records are announced once, growing to 4 only updates them
+        assert_eq!(hall_of_fame.announcements.len(), 2);
+        assert_eq!(hall_of_fame.broken.len(), 2);
+        assert!(record(hall_of_fame, RecordKind::PeriodFiles).is_none());
+
+        let end = "2025-03-10T00:00:00Z".parse().unwrap();
+        let section =
+            format_broken_records(hall_of_fame, week_start, end, &FakeNames, chrono_tz::UTC)
+                .await
+                .unwrap();
+        assert_eq!(
+            section,
+            "Records broken this period:\n\
+             - Longest series: user2 — 4 in #channel-5, 2025-03-05 (was 2 by user1) \
+             [jump](<https://discord.com/channels/@me/5/10>)\n\
+             - Most messages in a period: user2 — 4, 2025-03-03 (was 2 by user1)"
+        );
+        assert_eq!(
+            format_broken_records(hall_of_fame, end, end, &FakeNames, chrono_tz::UTC).await,
+            None
+        );
+
+        assert!(stat.purge_user(UserId::new(2)));
+        assert!(stat.hall_of_fame.broken.is_empty());
+    }
+
+    #[test]
+    fn test_period_lead_changes_announced_once() {
+        let at = |time: &str| time.parse::<DateTime<Utc>>().unwrap();
+        let candidate = |user: u64, value: usize, start: &str| FameRecord {
+            kind: RecordKind::PeriodMessages,
+            user_id: UserId::new(user),
+            value,
+            at: at(start),
+            channel_id: None,
+            link: None,
+        };
+        let mut hall_of_fame = HallOfFame::default();
+        let now = at("2025-03-05T10:00:00Z");
+        hall_of_fame.offer(candidate(1, 10, "2025-02-24T00:00:00Z"), now, true);
+        // Two users trading the lead of the running period
+        for (user, value) in [(2, 11), (3, 12), (2, 13), (3, 14)] {
+            hall_of_fame.offer(candidate(user, value, "2025-03-03T00:00:00Z"), now, true);
+        }
+
+        assert_eq!(hall_of_fame.announcements.len(), 1);
+        assert_eq!(hall_of_fame.broken.len(), 1);
+        let broken = &hall_of_fame.broken[0];
+        assert_eq!(
+            (broken.record.user_id, broken.record.value),
+            (UserId::new(3), 14)
+        );
+        assert_eq!(
+            (broken.previous.user_id, broken.previous.value),
+            (UserId::new(1), 10)
+        );
+        assert_eq!(hall_of_fame.announcements[0], *broken);
+    }
+}
@@ -0,0 +1,250 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::all::{
+    CommandDataOptionValue, CommandInteraction, CommandOptionType, CreateCommand,
+    CreateCommandOption, CreateEmbed, EditInteractionResponse, Message, UserId,
+};
+use serenity::prelude::*;
+use std::collections::HashMap;
+
+const EMBED_COLOUR: u32 = 0x2ecc71;
+const LEADERBOARD_SIZE: usize = 10;
+
+/// What grants karma, from the `KARMA_*` variables.
+#[derive(Debug, Clone, Default)]
+pub struct KarmaRules {
+    pub words: Vec<String>, // lowercase, a reply containing one thanks its author
+    pub emoji: Option<String>, // reaction that thanks the message author
+    pub cooldown: chrono::Duration, // between two grants from the same giver to the same user
+}
+
+impl KarmaRules {
+    /// Custom emoji are compared by id, they may have been renamed since.
+    pub fn is_thanks_emoji(&self, emoji: &str) -> bool {
+        match (self.emoji.as_deref(), custom_emoji_id(emoji)) {
+            (Some(thanks), Some(id)) => custom_emoji_id(thanks) == Some(id),
+            (Some(thanks), None) => thanks == emoji,
+            (None, _) => false,
+        }
+    }
+}
+
+/// All-time karma. The cooldowns only matter for a short while and are not kept.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Karma {
+    pub totals: HashMap<UserId, usize>,
+    #[serde(skip)]
+    pub last_given: HashMap<UserId, HashMap<UserId, DateTime<Utc>>>, // giver, receiver
+}
+
+impl Karma {
+    /// Grants a point unless it is self-karma or the giver thanked the user too recently.
+    pub fn grant(
+        &mut self,
+        giver: UserId,
+        receiver: UserId,
+        at: DateTime<Utc>,
+        rules: &KarmaRules,
+    ) -> bool {
+        if giver == receiver {
+            return false;
+        }
+        let last = self.last_given.entry(giver).or_default();
+        if let Some(previous) = last.get(&receiver) {
+            if at - *previous < rules.cooldown {
+                return false;
+            }
+        }
+        last.insert(receiver, at);
+        *self.totals.entry(receiver).or_insert(0) += 1;
+        true
+    }
+
+    pub fn leaderboard(&self, count: usize) -> Vec<(UserId, usize)> {
+        let mut totals = self
+            .totals
+            .iter()
+            .filter(|(_, total)| **total > 0)
+            .map(|(user_id, total)| (*user_id, *total))
+            .collect::<Vec<(UserId, usize)>>();
+        totals.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        totals.truncate(count);
+        totals
+    }
+
+    pub fn purge_user(&mut self, user_id: UserId) -> bool {
+        self.last_given.remove(&user_id);
+        for receivers in self.last_given.values_mut() {
+            receivers.remove(&user_id);
+        }
+        self.totals.remove(&user_id).is_some()
+    }
+}
+
+/// The author of the replied-to message when the reply thanks them.
+pub fn thanked_user(msg: &Message, rules: &KarmaRules) -> Option<UserId> {
+    let replied = msg.referenced_message.as_ref()?;
+    if replied.author.bot || rules.words.is_empty() {
+        return None;
+    }
+    let text = normalize_words(&msg.content);
+    rules
+        .words
+        .iter()
+        .any(|word| text.contains(&normalize_words(word)))
+        .then_some(replied.author.id)
+}
+
+/// Lowercase words between single spaces, padded so a match is always on whole words.
+fn normalize_words(text: &str) -> String {
+    let words = text
+        .to_lowercase()
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .collect::<Vec<&str>>()
+        .join(" ");
+    format!(" {words} ")
+}
+
+pub fn karma_command() -> CreateCommand {
+    CreateCommand::new("karma")
+        .description("Who was thanked the most")
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::User,
+            "user",
+            "Karma of this user",
+        ))
+}
+
+pub async fn run_karma_command(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+) {
+    command.defer(&ctx.http).await.ok();
+    let user_id = command
+        .data
+        .options
+        .iter()
+        .find(|option| option.name == "user")
+        .and_then(|option| match option.value {
+            CommandDataOptionValue::User(user_id) => Some(user_id),
+            _ => None,
+        });
+
+    let stat_guard = stat.lock().await;
+    let karma = &stat_guard.karma;
+    let top = karma.leaderboard(LEADERBOARD_SIZE);
+    let user = user_id.map(|user_id| {
+        let ranking = karma.leaderboard(usize::MAX);
+        let rank = ranking.iter().position(|(id, _)| *id == user_id);
+        (
+            user_id,
+            karma.totals.get(&user_id).copied().unwrap_or(0),
+            rank,
+        )
+    });
+    drop(stat_guard);
+
+    let mut embed = CreateEmbed::new().title("Karma").colour(EMBED_COLOUR);
+    match user {
+        Some((user_id, total, rank)) => {
+            let name = get_user_name(&user_id, &ctx.http, conf).await;
+            let description = match rank {
+                Some(rank) => format!("{name} has {total} karma, #{} overall", rank + 1),
+                None => format!("{name} has no karma yet"),
+            };
+            embed = embed.description(description);
+        }
+        None if top.is_empty() => embed = embed.description("Nobody has karma yet"),
+        None => {
+            let mut lines = vec![];
+            for (index, (user_id, total)) in top.iter().enumerate() {
+                let name = get_user_name(user_id, &ctx.http, conf).await;
+                lines.push(format!("**{}.** {name} — {total}", index + 1));
+            }
+            embed = embed.description(lines.join("\n"));
+        }
+    }
+
+    let builder = EditInteractionResponse::new().embed(embed);
+    if let Err(why) = command.edit_response(&ctx.http, builder).await {
+        tracing::error!("Cannot respond to slash command: {why}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules() -> KarmaRules {
+        KarmaRules {
+            words: vec![
+                "thanks".to_string(),
+                "спасибо".to_string(),
+                "thank you".to_string(),
+            ],
+            emoji: None,
+            cooldown: chrono::Duration::minutes(10),
+        }
+    }
+
+    fn reply(content: &str, author: u64, replied_author: u64) -> Message {
+        let mut replied = Message::default();
+        replied.author.id = UserId::new(replied_author);
+        let mut msg = Message::default();
+        msg.author.id = UserId::new(author);
+        msg.content = content.to_string();
+        msg.referenced_message = Some(Box::new(replied));
+        msg
+    }
+
+    #[test]
+    fn test_thanked_user() {
+        let rules = rules();
+        assert_eq!(
+            thanked_user(&reply("Thanks, that worked!", 1, 2), &rules),
+            Some(UserId::new(2))
+        );
+        assert_eq!(
+            thanked_user(&reply("Спасибо большое", 1, 2), &rules),
+            Some(UserId::new(2))
+        );
+        assert_eq!(
+            thanked_user(&reply("thank  you!", 1, 2), &rules),
+            Some(UserId::new(2))
+        );
+        // Whole words only
+        assert_eq!(thanked_user(&reply("thanksgiving", 1, 2), &rules), None);
+        let mut not_a_reply = reply("thanks", 1, 2);
+        not_a_reply.referenced_message = None;
+        assert_eq!(thanked_user(&not_a_reply, &rules), None);
+
+        let rules = KarmaRules {
+            emoji: Some("<:plus:42>".to_string()),
+            ..rules
+        };
+        assert!(rules.is_thanks_emoji("<:plus_one:42>"));
+        assert!(!rules.is_thanks_emoji("<:plus:43>"));
+        assert!(!rules.is_thanks_emoji("👍"));
+    }
+
+    #[test]
+    fn test_grant_cooldown() {
+        let rules = rules();
+        let (giver, receiver) = (UserId::new(1), UserId::new(2));
+        let at = |minute: i64| DateTime::<Utc>::default() + chrono::Duration::minutes(minute);
+        let mut karma = Karma::default();
+        assert!(!karma.grant(giver, giver, at(0), &rules));
+        assert!(karma.grant(giver, receiver, at(0), &rules));
+        assert!(!karma.grant(giver, receiver, at(5), &rules));
+        assert!(karma.grant(UserId::new(3), receiver, at(5), &rules));
+        assert!(karma.grant(giver, receiver, at(10), &rules));
+        assert_eq!(karma.leaderboard(10), vec![(receiver, 3)]);
+        assert!(karma.purge_user(receiver));
+        assert!(karma.leaderboard(10).is_empty());
+    }
+}
@@ -0,0 +1,431 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use serenity::all::{
+    ChannelId, CommandDataOption, CommandDataOptionValue, CommandInteraction, CommandOptionType,
+    CreateCommand, CreateCommandOption, CreateEmbed, CreateEmbedFooter, EditInteractionResponse,
+    UserId,
+};
+use serenity::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+
+const EMBED_COLOUR: u32 = 0x5865f2;
+const DEFAULT_TOP: usize = 10;
+
+pub fn stats_command() -> CreateCommand {
+    let schedule =
+        || CreateCommandOption::new(CommandOptionType::String, "schedule", "Report schedule");
+    let mut metric = CreateCommandOption::new(CommandOptionType::String, "metric", "Sort by");
+    for column in ReportColumn::ALL {
+        metric = metric.add_string_choice(column.header(), column.name());
+    }
+
+    CreateCommand::new("stats")
+        .description("Statistics of the current period")
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "me", "Your statistics")
+                .add_sub_option(schedule()),
+        )
+        .add_option(
+            CreateCommandOption::new(CommandOptionType::SubCommand, "user", "A user's statistics")
+                .add_sub_option(
+                    CreateCommandOption::new(CommandOptionType::User, "user", "The user")
+                        .required(true),
+                )
+                .add_sub_option(schedule()),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "top",
+                "The most active users",
+            )
+            .add_sub_option(metric)
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "n", "How many users")
+                    .min_int_value(1)
+                    .max_int_value(25),
+            )
+            .add_sub_option(schedule()),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "channel",
+                "Statistics of a channel",
+            )
+            .add_sub_option(CreateCommandOption::new(
+                CommandOptionType::Channel,
+                "channel",
+                "The channel, this one by default",
+            ))
+            .add_sub_option(schedule()),
+        )
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::SubCommand,
+                "channels",
+                "The busiest channels",
+            )
+            .add_sub_option(
+                CreateCommandOption::new(CommandOptionType::Integer, "n", "How many channels")
+                    .min_int_value(1)
+                    .max_int_value(25),
+            )
+            .add_sub_option(schedule()),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "optout",
+            "Delete your statistics and stop collecting them",
+        ))
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::SubCommand,
+            "optin",
+            "Collect your statistics again",
+        ))
+}
+
+/// Reads a snapshot of the running period, nothing is reset.
+pub async fn run_stats_command(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+) {
+    let (subcommand, options) = match command.data.options.first() {
+        Some(CommandDataOption {
+            name,
+            value: CommandDataOptionValue::SubCommand(options),
+            ..
+        }) => (name.as_str(), options.as_slice()),
+        _ => return respond_ephemeral(ctx, command, "Unknown subcommand").await,
+    };
+    match subcommand {
+        "optout" => return run_opt_out(ctx, command, conf, stat, true).await,
+        "optin" => return run_opt_out(ctx, command, conf, stat, false).await,
+        _ => {}
+    }
+    command.defer(&ctx.http).await.ok();
+
+    let option = |name: &str| {
+        options
+            .iter()
+            .find(|option| option.name == name)
+            .map(|option| &option.value)
+    };
+    let schedule = match option("schedule") {
+        Some(CommandDataOptionValue::String(schedule)) => schedule.clone(),
+        _ => conf.report_schedules[0].name.clone(),
+    };
+
+    let stat_guard = stat.lock().await;
+    let live = stat_guard.periods.get(&schedule).map(|period| {
+        (
+            period.collection_start,
+            period.collect_until,
+            period.message_stat.snapshot(),
+            period.message_stat.current_by_channel.clone(),
+        )
+    });
+    drop(stat_guard);
+    let (start, until, snapshot, running) = match live {
+        Some(live) => live,
+        None => {
+            edit_stats_response(ctx, command, None, &format!("No statistics for {schedule}")).await;
+            return;
+        }
+    };
+
+    let embed = match subcommand {
+        "me" => user_embed(ctx, conf, &snapshot, command.user.id).await,
+        "user" => match option("user") {
+            Some(CommandDataOptionValue::User(user_id)) => {
+                user_embed(ctx, conf, &snapshot, *user_id).await
+            }
+            _ => return edit_stats_response(ctx, command, None, "Pick a user").await,
+        },
+        "top" => {
+            let column = match option("metric") {
+                Some(CommandDataOptionValue::String(metric)) => {
+                    metric.parse().unwrap_or(ReportColumn::Messages)
+                }
+                _ => ReportColumn::Messages,
+            };
+            let count = match option("n") {
+                Some(CommandDataOptionValue::Integer(n)) => *n as usize,
+                _ => DEFAULT_TOP,
+            };
+            top_embed(ctx, conf, &snapshot, column, count).await
+        }
+        "channel" => {
+            let channel_id = match option("channel") {
+                Some(CommandDataOptionValue::Channel(channel_id)) => *channel_id,
+                _ => command.channel_id,
+            };
+            channel_embed(ctx, conf, &snapshot, &running, channel_id).await
+        }
+        "channels" => {
+            let count = match option("n") {
+                Some(CommandDataOptionValue::Integer(n)) => *n as usize,
+                _ => DEFAULT_TOP,
+            };
+            channels_embed(ctx, conf, &snapshot, count).await
+        }
+        _ => return edit_stats_response(ctx, command, None, "Unknown subcommand").await,
+    };
+
+    let embed = embed
+        .colour(EMBED_COLOUR)
+        .field("Period", format_live_period(start, until), false)
+        .footer(CreateEmbedFooter::new(format!("Schedule: {schedule}")));
+    edit_stats_response(ctx, command, Some(embed), "").await;
+}
+
+/// Opting out deletes the user's statistics, the archive included, and stops collecting them.
+async fn run_opt_out(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+    opt_out: bool,
+) {
+    let user_id = command.user.id;
+    let mut stat_guard = stat.lock().await;
+    let text = match opt_out {
+        true => {
+            stat_guard.opted_out.insert(user_id);
+            stat_guard.purge_user(user_id);
+            tracing::info!("User {} opted out of the statistics", user_id);
+            "Your statistics are deleted and won't be collected anymore"
+        }
+        false => {
+            stat_guard.opted_out.remove(&user_id);
+            tracing::info!("User {} opted in to the statistics", user_id);
+            "Your statistics are collected again from now on"
+        }
+    };
+    save_stat(stat_guard, conf).await;
+
+    respond_ephemeral(ctx, command, text).await;
+    if opt_out {
+        purge_archive(conf, &BTreeSet::from([user_id]), &BTreeSet::new()).await;
+    }
+}
+
+async fn user_embed(
+    ctx: &Context,
+    conf: &Config,
+    stat: &MessageStat,
+    user_id: UserId,
+) -> CreateEmbed {
+    let name = get_user_name(&user_id, &ctx.http, conf).await;
+    let embed = CreateEmbed::new().title(format!("Statistics of {name}"));
+    if !stat.users().contains(&user_id) {
+        return embed.description("No activity in this period yet");
+    }
+
+    let ranking = top_users(stat, ReportColumn::Messages, usize::MAX);
+    let description = match ranking.iter().position(|(id, _)| *id == user_id) {
+        Some(index) => format!("#{} of {} by messages", index + 1, ranking.len()),
+        None => "No messages in this period yet".to_string(),
+    };
+    let mut embed = embed.description(description);
+    for column in ReportColumn::ALL {
+        let value = stat.value(column, &user_id);
+        if value == 0 {
+            continue;
+        }
+        let value = match (column, stat.personal_record.get(&user_id)) {
+            (ReportColumn::Series, Some(record)) => format!("{value} in <#{}>", record.channel_id),
+            _ => value.to_string(),
+        };
+        embed = embed.field(column.header(), value, true);
+    }
+    embed
+}
+
+async fn top_embed(
+    ctx: &Context,
+    conf: &Config,
+    stat: &MessageStat,
+    column: ReportColumn,
+    count: usize,
+) -> CreateEmbed {
+    let embed = CreateEmbed::new().title(format!("Top by {}", column.header().to_lowercase()));
+    let top = top_users(stat, column, count);
+    if top.is_empty() {
+        return embed.description("No activity in this period yet");
+    }
+    let mut lines = vec![];
+    for (index, (user_id, value)) in top.iter().enumerate() {
+        let name = get_user_name(user_id, &ctx.http, conf).await;
+        lines.push(format!("**{}.** {name} — {value}", index + 1));
+    }
+    embed.description(lines.join("\n"))
+}
+
+async fn channel_embed(
+    ctx: &Context,
+    conf: &Config,
+    stat: &MessageStat,
+    running: &HashMap<ChannelId, MessageStreakUser>,
+    channel_id: ChannelId,
+) -> CreateEmbed {
+    let name = get_channel_name(&channel_id, &ctx.http).await;
+    let embed = CreateEmbed::new().title(format!("Statistics of #{name}"));
+    let top = channel_top(stat, channel_id, DEFAULT_TOP);
+    if top.is_empty() {
+        return embed.description("No messages in this period yet");
+    }
+
+    let total = stat
+        .channel_messages
+        .get(&channel_id)
+        .map(|users| users.values().sum::<usize>())
+        .unwrap_or(0);
+    let mut lines = vec![format!("{total} messages"), String::new()];
+    for (index, (user_id, value)) in top.iter().enumerate() {
+        let name = get_user_name(user_id, &ctx.http, conf).await;
+        lines.push(format!("**{}.** {name} — {value}", index + 1));
+    }
+    let mut embed = embed.description(lines.join("\n"));
+    if let Some(streak) = running.get(&channel_id) {
+        let name = get_user_name(&streak.user_id, &ctx.http, conf).await;
+        embed = embed.field(
+            "Current series",
+            format!("{name}: {}", streak.counter),
+            false,
+        );
+    }
+    embed
+}
+
+async fn channels_embed(
+    ctx: &Context,
+    conf: &Config,
+    stat: &MessageStat,
+    count: usize,
+) -> CreateEmbed {
+    let embed = CreateEmbed::new().title("The busiest channels");
+    let names = DiscordNames {
+        cache_http: &ctx.http,
+        conf,
+    };
+    match format_channel_leaderboard(stat, &names, count).await {
+        Some(table) => embed.description(format!("```\n{table}\n```")),
+        None => embed.description("No messages in this period yet"),
+    }
+}
+
+/// Users with a non-zero value, the largest first, ties by id.
+pub fn top_users(stat: &MessageStat, column: ReportColumn, count: usize) -> Vec<(UserId, usize)> {
+    let values = stat
+        .users()
+        .into_iter()
+        .map(|user_id| (user_id, stat.value(column, &user_id)));
+    sorted_top(values, count)
+}
+
+/// The most active users of the channel.
+pub fn channel_top(
+    stat: &MessageStat,
+    channel_id: ChannelId,
+    count: usize,
+) -> Vec<(UserId, usize)> {
+    let values = stat
+        .channel_messages
+        .get(&channel_id)
+        .map(|users| {
+            users
+                .iter()
+                .map(|(user_id, count)| (*user_id, *count))
+                .collect()
+        })
+        .unwrap_or(vec![]);
+    sorted_top(values, count)
+}
+
+fn sorted_top(
+    values: impl IntoIterator<Item = (UserId, usize)>,
+    count: usize,
+) -> Vec<(UserId, usize)> {
+    let mut values = values
+        .into_iter()
+        .filter(|(_, value)| *value > 0)
+        .collect::<Vec<(UserId, usize)>>();
+    values.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    values.truncate(count);
+    values
+}
+
+/// Discord timestamps, shown in the reader's own timezone.
+fn format_live_period(start: DateTime<Utc>, until: DateTime<Utc>) -> String {
+    format!(
+        "<t:{}:f> — <t:{}:f> (ends <t:{}:R>)",
+        start.timestamp(),
+        until.timestamp(),
+        until.timestamp()
+    )
+}
+
+async fn edit_stats_response(
+    ctx: &Context,
+    command: &CommandInteraction,
+    embed: Option<CreateEmbed>,
+    content: &str,
+) {
+    let builder = match embed {
+        Some(embed) => EditInteractionResponse::new().embed(embed),
+        None => EditInteractionResponse::new().content(content),
+    };
+    if let Err(why) = command.edit_response(&ctx.http, builder).await {
+        tracing::error!("Error responding to command: {why:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::Message;
+
+    #[test]
+    fn test_top_users_live_series() {
+        let mut stat = MessageStat::default();
+        let channel = ChannelId::new(10);
+        for author in [1, 1, 1, 2, 3, 3, 3] {
+            let mut msg = Message::default();
+            msg.channel_id = channel;
+            msg.author.id = UserId::new(author);
+            stat.record_message(&msg, chrono_tz::UTC, &StreakRules::default());
+        }
+
+        let messages = top_users(&stat, ReportColumn::Messages, 10);
+        assert_eq!(
+            messages,
+            [
+                (UserId::new(1), 3),
+                (UserId::new(3), 3),
+                (UserId::new(2), 1)
+            ]
+        );
+        assert_eq!(top_users(&stat, ReportColumn::Messages, 1).len(), 1);
+        assert_eq!(channel_top(&stat, channel, 10), messages);
+        assert!(channel_top(&stat, ChannelId::new(11), 10).is_empty());
+
+        // The running series of user 3 only shows up in the snapshot
+        let series = top_users(&stat.snapshot(), ReportColumn::Series, 10);
+        assert_eq!(
+            series,
+            [
+                (UserId::new(1), 3),
+                (UserId::new(3), 3),
+                (UserId::new(2), 1)
+            ]
+        );
+        assert!(top_users(&stat, ReportColumn::Series, 10)
+            .iter()
+            .all(|(user_id, _)| *user_id != UserId::new(3)));
+        assert_eq!(stat.current_by_channel[&channel].counter, 3);
+    }
+}
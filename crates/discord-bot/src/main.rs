@@ -1,22 +1,71 @@
+mod achievements;
+mod archive;
+mod backfill;
+mod backend;
+mod chart;
+mod cli;
+mod commands;
 mod config;
+mod emoji;
+mod export;
+mod feedback;
+mod hall_of_fame;
+mod karma;
+mod live_stats;
 mod messages;
+mod persist;
+mod replay;
+mod report_layout;
 mod reporter;
+mod schedule;
 mod send_images;
 mod stat;
+mod stat_filter;
 mod storage;
+mod summarize;
+mod threads;
+mod transcript;
 mod util;
+mod voice;
 
+use achievements::*;
+use archive::*;
+use backfill::*;
+use backend::*;
+use chart::*;
+use cli::*;
+use commands::*;
 use config::*;
+use emoji::*;
+use export::*;
+use feedback::*;
+use hall_of_fame::*;
+use karma::*;
+use live_stats::*;
 use messages::*;
+use persist::*;
+use replay::*;
+use report_layout::*;
 use reporter::*;
+use schedule::*;
 use send_images::*;
 use stat::*;
+use stat_filter::*;
 use storage::*;
+use summarize::*;
+use threads::*;
+use transcript::*;
 use util::*;
+use voice::*;
 
 use serenity::async_trait;
-use serenity::model::channel::Message;
+use serenity::model::application::Interaction;
+use serenity::model::channel::{GuildChannel, Message, Reaction};
+use serenity::model::event::MessageUpdateEvent;
+use serenity::model::id::{ChannelId, GuildId, MessageId, UserId};
 use serenity::model::gateway::Ready;
+use serenity::model::guild::{Guild, PartialGuild};
+use serenity::model::voice::VoiceState;
 use serenity::prelude::*;
 use shutdown_utils::ShutdownCoordinator;
 use std::env;
@@ -33,34 +82,61 @@ use uuid::Uuid;
 
 struct Handler {
     stat: Arc<Mutex<Stat>>,
+    channels: Arc<Mutex<ChannelCache>>,
     storage: Arc<Mutex<Storage>>,
     config: Arc<Mutex<Config>>,
+    threads: Arc<Mutex<BotThreads>>,
+    llm: Arc<Llm>,
 }
 
 #[async_trait]
 impl EventHandler for Handler {
     async fn message(&self, ctx: Context, msg: Message) {
-        let mut stat_guard = self.stat.lock().await;
-        stat_guard.message_stat.update_streak(&msg);
-        let storage_guard = self.storage.lock().await;
         let config_guard = self.config.lock().await;
         let conf = config_guard.clone();
         drop(config_guard);
+        match stat_message_allowed(&ctx.http, &conf, &self.channels, &msg).await {
+            true => {
+                let parent_id = match msg.guild_id {
+                    Some(_) => {
+                        ChannelCache::thread_parent(&self.channels, &ctx.http, msg.channel_id)
+                            .await
+                    }
+                    None => None,
+                };
+                let mut stat_guard = self.stat.lock().await;
+                if let Some(parent_id) = parent_id {
+                    stat_guard.record_thread(msg.channel_id, parent_id);
+                }
+                stat_guard.record_message(&msg, &conf);
+                drop(stat_guard);
+                announce_records(&ctx.http, &self.stat, &conf).await;
+            }
+            false if conf.stat_filter.excludes_author(&msg) => {
+                purge_excluded_author(&self.stat, &conf, msg.author.id).await
+            }
+            false => {}
+        }
+        // Do not hold the locks while the LLM is answering, reactions need them meanwhile
+        let self_id = self.storage.lock().await.self_id;
 
         // Check for kemono URLs and save to file
         if let Err(e) = check_and_save_kemono_url(&msg).await {
             tracing::error!("Error saving kemono URL: {}", e);
         }
 
-        if react_to_mention(&ctx, &msg, storage_guard.self_id, &conf).await {
+        if react_in_bot_thread(&ctx, &msg, self_id, &conf, &self.threads, &self.llm).await {
+            return;
+        };
+        if react_to_mention(&ctx, &msg, self_id, &conf, &self.threads, &self.llm).await {
             return;
         };
         // TODO - enable later
-        // if react_to_trigger_word(&ctx, &msg, storage_guard.self_id, &conf).await {
+        // if react_to_trigger_word(&ctx, &msg, self_id, &conf, &self.llm).await {
         //     return;
         // };
         // TODO - enable later
-        // agr_to_someone(ctx.clone(), &msg, storage_guard.self_id, &conf).await;
+        // agr_to_someone(ctx.clone(), &msg, self_id, &conf).await;
     }
 
     // Set a handler to be called on the `ready` event. This is called when a shard is booted, and
@@ -69,6 +145,7 @@ impl EventHandler for Handler {
     //
     // In this case, just print what the current user's username is.
     async fn ready(&self, ctx: Context, ready: Ready) {
+        self.stat.lock().await.coverage.start_session(Utc::now());
         let mut storage_guard = self.storage.lock().await;
         storage_guard.self_id = ready.user.id;
         let config_guard = self.config.lock().await;
@@ -76,6 +153,144 @@ impl EventHandler for Handler {
         drop(config_guard);
         let bot_name = get_user_name(&storage_guard.self_id, &ctx.http, &conf).await;
         tracing::info!("{} is connected!", bot_name);
+        register_commands(&ctx, &conf).await;
+    }
+
+    async fn message_update(
+        &self,
+        ctx: Context,
+        _old_if_available: Option<Message>,
+        _new: Option<Message>,
+        event: MessageUpdateEvent,
+    ) {
+        let conf = self.config.lock().await.clone();
+        let (channel_id, guild_id) = (event.channel_id, event.guild_id);
+        if !conf.stat_filter.excludes_edit(&event)
+            && stat_channel_allowed(&ctx.http, &conf, &self.channels, channel_id, guild_id).await
+        {
+            self.stat.lock().await.record_edit(&event, &conf);
+        }
+    }
+
+    async fn message_delete(
+        &self,
+        _ctx: Context,
+        channel_id: ChannelId,
+        deleted_message_id: MessageId,
+        _guild_id: Option<GuildId>,
+    ) {
+        // Only messages that were counted are remembered
+        let conf = self.config.lock().await.clone();
+        self.stat
+            .lock()
+            .await
+            .record_deletion(channel_id, deleted_message_id, &conf);
+    }
+
+    async fn reaction_add(&self, ctx: Context, reaction: Reaction) {
+        let conf = self.config.lock().await.clone();
+        let self_id = self.storage.lock().await.self_id;
+        if self.stat_reaction_allowed(&ctx, &reaction).await {
+            let karma = karma_author_allowed(&ctx, &reaction, &conf, self_id).await;
+            let mut stat = self.stat.lock().await;
+            stat.record_reaction(&reaction, true, karma, &conf);
+        }
+        let feedback = self.llm.feedback.lock().await;
+        feedback.record_reaction(&conf, &reaction, VoteAction::Add, self_id);
+    }
+
+    async fn reaction_remove(&self, ctx: Context, reaction: Reaction) {
+        let conf = self.config.lock().await.clone();
+        if self.stat_reaction_allowed(&ctx, &reaction).await {
+            let mut stat = self.stat.lock().await;
+            stat.record_reaction(&reaction, false, false, &conf);
+        }
+        let self_id = self.storage.lock().await.self_id;
+        let feedback = self.llm.feedback.lock().await;
+        feedback.record_reaction(&conf, &reaction, VoteAction::Remove, self_id);
+    }
+
+    async fn interaction_create(&self, ctx: Context, interaction: Interaction) {
+        if let Interaction::Command(command) = interaction {
+            handle_command(&ctx, &command, self).await;
+        }
+    }
+
+    async fn channel_update(&self, _ctx: Context, _old: Option<GuildChannel>, new: GuildChannel) {
+        self.channels.lock().await.update(&new);
+    }
+
+    async fn channel_delete(
+        &self,
+        _ctx: Context,
+        channel: GuildChannel,
+        _messages: Option<Vec<Message>>,
+    ) {
+        self.channels.lock().await.forget(channel.id);
+    }
+
+    async fn thread_update(&self, _ctx: Context, _old: Option<GuildChannel>, new: GuildChannel) {
+        self.channels.lock().await.update(&new);
+    }
+
+    async fn guild_create(&self, ctx: Context, guild: Guild, _is_new: Option<bool>) {
+        let conf = self.config.lock().await.clone();
+        sync_voice_states(&ctx.http, &conf, &self.stat, &self.channels, &guild).await;
+    }
+
+    async fn guild_update(&self, _ctx: Context, _old: Option<Guild>, guild: PartialGuild) {
+        let afk_channel = guild.afk_metadata.map(|afk| afk.afk_channel_id);
+        self.channels.lock().await.set_afk_channel(afk_channel);
+    }
+
+    async fn voice_state_update(&self, ctx: Context, _old: Option<VoiceState>, new: VoiceState) {
+        let conf = self.config.lock().await.clone();
+        record_voice_state(&ctx.http, &conf, &self.stat, &self.channels, &new).await;
+    }
+}
+
+impl Handler {
+    async fn stat_reaction_allowed(&self, ctx: &Context, reaction: &Reaction) -> bool {
+        let conf = self.config.lock().await.clone();
+        let by_bot = reaction
+            .member
+            .as_ref()
+            .is_some_and(|member| member.user.bot);
+        !(conf.stat_filter.ignore_bots && by_bot)
+            && stat_channel_allowed(
+                &ctx.http,
+                &conf,
+                &self.channels,
+                reaction.channel_id,
+                reaction.guild_id,
+            )
+            .await
+    }
+}
+
+/// Whether a thanks reaction may grant karma to the message author: never to this bot or
+/// another one. The author is only looked up for the thanks emoji.
+async fn karma_author_allowed(
+    ctx: &Context,
+    reaction: &Reaction,
+    conf: &Config,
+    self_id: UserId,
+) -> bool {
+    let author_id = match reaction.message_author_id {
+        Some(author_id) => author_id,
+        None => return false,
+    };
+    if !conf.karma_rules.is_thanks_emoji(&reaction_emoji(&reaction.emoji))
+        || author_id == self_id
+    {
+        return false;
+    }
+    match author_id.to_user(&ctx.http).await {
+        Ok(author) => !author.bot,
+        Err(e) => {
+            tracing::warn!("Error getting user {}: {:?}", author_id, e);
+            false
+        }
     }
 }
 
@@ -86,19 +301,44 @@ async fn main() {
         .with_env_filter(tracing_subscriber::EnvFilter::from_default_env())
         .init();
 
-    tracing::info!("Starting Discord bot...");
+    let args = env::args().skip(1).collect::<Vec<String>>();
+    if let Some(code) = run_cli(&args).await {
+        std::process::exit(code);
+    }
 
-    let stat_save_file = "stat/stat.json"; // TODO - move to config
+    tracing::info!("Starting Discord bot...");
 
     // Set gateway intents, which decides what events the bot will be notified about
     let intents = GatewayIntents::GUILD_MESSAGES
         | GatewayIntents::DIRECT_MESSAGES
-        | GatewayIntents::MESSAGE_CONTENT;
+        | GatewayIntents::MESSAGE_CONTENT
+        | GatewayIntents::GUILD_MESSAGE_REACTIONS
+        | GatewayIntents::DIRECT_MESSAGE_REACTIONS
+        | GatewayIntents::GUILD_VOICE_STATES
+        | GatewayIntents::GUILDS;
 
-    let stat = Stat::load_from_file(stat_save_file).unwrap_or_default();
-    let arc_stat = Arc::new(Mutex::new(stat));
     let config = init_config();
+    let stat_save_file = config.stat_file.clone();
+    let stat_backups = config.stat_backups;
+    let mut stat = Stat::load_from_file(
+        &stat_save_file,
+        stat_backups,
+        &config.report_schedules[0].name,
+    )
+    .unwrap_or_else(|e| {
+        tracing::error!("Error loading statistics, starting fresh: {}", e);
+        Stat::default()
+    });
+    // Before the client starts, events may arrive right away
+    stat.init_collection(&config.report_schedules, config.report_timezone());
+    seed_achievements(&mut stat, &config);
+    seed_hall_of_fame(&mut stat, &config);
+    stat.coverage.init(&stat.periods, Utc::now());
+    stat.voice.close_stale(&mut stat.periods);
+    let arc_stat = Arc::new(Mutex::new(stat));
+    let arc_channels = Arc::new(Mutex::new(ChannelCache::default()));
     let arc_config = Arc::new(Mutex::new(config.clone()));
+    let arc_llm = Arc::new(Llm::new(&config));
     
     // Create shutdown coordinator
     let mut shutdown_coordinator = ShutdownCoordinator::new();
@@ -109,8 +349,11 @@ async fn main() {
     let mut client = Client::builder(&token, intents)
         .event_handler(Handler {
             stat: arc_stat.clone(),
+            channels: arc_channels.clone(),
             storage: Arc::new(Mutex::new(Storage::default())),
             config: arc_config.clone(),
+            threads: Arc::new(Mutex::new(BotThreads::default())),
+            llm: arc_llm.clone(),
         })
         .await
         .expect("Failed to create Discord client");
@@ -124,8 +367,19 @@ async fn main() {
         .parse()
         .expect("DISCORD_CHANNEL_ID must be a valid u64");
 
-    // Start the stat reporter
-    stat_reporter(client.http.clone(), arc_stat.clone(), config);
+    // Start the LLM backend health probe
+    backend_monitor(config.clone(), arc_llm.clone());
+
+    // Start the stat reporter and the periodic save
+    purge_excluded_at_start(
+        client.http.clone(),
+        arc_stat.clone(),
+        arc_channels.clone(),
+        config.clone(),
+    );
+    stat_autosave(arc_stat.clone(), config.clone());
+    achievement_announcer(client.http.clone(), arc_stat.clone(), config.clone());
+    stat_reporter(client.http.clone(), arc_stat.clone(), config.clone());
 
     // Spawn file watcher task with shutdown handling
     let shutdown_rx_clone = shutdown_rx.clone();
@@ -144,6 +398,7 @@ async fn main() {
     // Spawn Discord client task with shutdown handling
     let shutdown_rx_clone = shutdown_rx.clone();
     let arc_stat_clone = arc_stat.clone();
+    let stat_conf = config;
     let client_task = tokio::spawn(async move {
         let mut shutdown_rx = shutdown_rx_clone;
         
@@ -157,12 +412,9 @@ async fn main() {
                 tracing::info!("Discord client shutting down gracefully");
                 
                 // Save statistics before shutdown
-                let stat_guard = arc_stat_clone.lock().await;
-                match stat_guard.save_to_file(stat_save_file) {
-                    Ok(_) => tracing::info!("Statistics saved to {}", stat_save_file),
-                    Err(e) => tracing::error!("Error saving statistics: {}", e),
-                }
-                drop(stat_guard);
+                let mut stat_guard = arc_stat_clone.lock().await;
+                stat_guard.voice.touch(Utc::now());
+                save_stat(stat_guard, &stat_conf).await;
                 
                 // Shutdown Discord client
                 tracing::info!("Shutting down Discord shards...");
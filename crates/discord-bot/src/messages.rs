@@ -2,11 +2,9 @@ use super::*;
 
 use eyre::WrapErr;
 use ollama_rs::generation::chat::{ChatMessage, MessageRole};
-use ollama_rs::generation::chat::request::ChatMessageRequest;
-use ollama_rs::Ollama;
 use rand::Rng;
 use regex::Regex;
-use serenity::all::{CreateMessage, GetMessages, UserId};
+use serenity::all::{ChannelId, CreateMessage, GetMessages, UserId};
 use serenity::model::channel::Message;
 use serenity::prelude::*;
 
@@ -49,13 +47,30 @@ pub struct MyChatMessage {
     pub timestamp: serenity::all::Timestamp,
 }
 
-pub async fn react(ctx: &Context, msg: &Message, self_id: UserId, conf: &Config) {
-    let channel = msg
-        .channel(ctx)
-        .await
-        .wrap_err_with(|| "Error getting channel")
-        .unwrap()
-        .id(); // TODO - handle error
+/// Where the answer goes: next to the triggering message or into a bot thread.
+#[derive(Clone, Copy, Debug)]
+pub enum ReplyTarget {
+    Channel,
+    Thread(ChannelId),
+}
+
+pub async fn react(
+    ctx: &Context,
+    msg: &Message,
+    target: ReplyTarget,
+    self_id: UserId,
+    conf: &Config,
+    llm: &Llm,
+) {
+    let channel = match target {
+        ReplyTarget::Channel => msg
+            .channel(ctx)
+            .await
+            .wrap_err_with(|| "Error getting channel")
+            .unwrap()
+            .id(), // TODO - handle error
+        ReplyTarget::Thread(thread_id) => thread_id,
+    };
     _ = channel.broadcast_typing(ctx).await;
     let ctx_clone = ctx.clone();
 
@@ -79,17 +94,13 @@ pub async fn react(ctx: &Context, msg: &Message, self_id: UserId, conf: &Config)
     // Simulate some delay before stopping the task
     tokio::time::sleep(std::time::Duration::from_secs(5)).await;
 
-    let ollama = Ollama::new(
-        env::var("OLLAMA_HOST").unwrap_or_else(|_| "http://localhost".to_string()),
-        env::var("OLLAMA_PORT")
-            .unwrap_or_else(|_| "11434".to_string())
-            .parse::<u16>()
-            .unwrap(),
-    );
-
-    let messages_chain = get_messages_chain(ctx, msg, 5)
-        .await
-        .unwrap_or_else(|_| vec![]);
+    let messages_chain = match target {
+        ReplyTarget::Channel => get_messages_chain(ctx, msg, 5).await,
+        ReplyTarget::Thread(thread_id) => {
+            get_thread_messages_chain(ctx, msg, thread_id, conf.thread_context_messages).await
+        }
+    }
+    .unwrap_or_else(|_| vec![]);
     let mut messages_chain = process_messages(ctx, self_id, conf, &messages_chain)
         .await
         .unwrap_or_else(|_| vec![]);
@@ -100,22 +111,19 @@ pub async fn react(ctx: &Context, msg: &Message, self_id: UserId, conf: &Config)
         tool_calls: vec![],
     });
 
-    let response_text = match messages_chain.len() {
+    let prompt = messages_chain.clone();
+    let (response_text, model) = match messages_chain.len() {
         0 => {
             tracing::warn!("No messages in the chain");
-            env::var("ERROR_NO_MESSAGES").unwrap_or_else(|_| "ERROR_NO_MESSAGES".to_string())
+            let text =
+                env::var("ERROR_NO_MESSAGES").unwrap_or_else(|_| "ERROR_NO_MESSAGES".to_string());
+            (text, None)
         }
         _ => {
-            let request = ChatMessageRequest::new(env::var("MODEL_NAME").unwrap(), messages_chain);
-
             _ = channel.broadcast_typing(ctx).await;
-            match ollama.send_chat_messages(request).await {
-                Ok(response) => response.message.content,
-                Err(e) => {
-                    tracing::error!("Error: {}", e);
-                    env::var("ERROR_OLLAMA_ERROR")
-                        .unwrap_or_else(|_| "ERROR_OLLAMA_ERROR".to_string())
-                }
+            match chat(conf, llm, "reply", messages_chain).await {
+                Ok(reply) => (reply.content, Some(reply.model)),
+                Err(e) => (conf.backend_error_message(&e), None),
             }
         }
     };
@@ -125,11 +133,26 @@ pub async fn react(ctx: &Context, msg: &Message, self_id: UserId, conf: &Config)
     let response_text = remove_think_blocks(&response_text);
     let response_text = replace_mentions(&response_text, &ctx.http, conf).await;
     let response_text = response_text.chars().take(2000).collect::<String>();
-    let builder = CreateMessage::new()
-        .content(response_text)
-        .reference_message(msg);
-    if let Err(why) = msg.channel_id.send_message(&ctx.http, builder).await {
-        tracing::error!("Error sending message: {why:?}")
+    let mut builder = CreateMessage::new().content(response_text);
+    if channel == msg.channel_id {
+        builder = builder.reference_message(msg);
+    }
+    match channel.send_message(&ctx.http, builder).await {
+        Ok(sent) => {
+            // Only real LLM answers are worth rating
+            if let Some(model) = model {
+                let reply = TrackedReply {
+                    message_id: sent.id,
+                    channel_id: sent.channel_id,
+                    created_at: chrono::Utc::now(),
+                    persona: conf.persona_name.clone(),
+                    model,
+                    prompt,
+                };
+                llm.feedback.lock().await.track(conf, reply);
+            }
+        }
+        Err(why) => tracing::error!("Error sending message: {why:?}"),
     };
     tracing::info!("===================================================================================");
 
@@ -144,6 +167,8 @@ pub async fn react_to_mention(
     msg: &Message,
     self_id: UserId,
     conf: &Config,
+    threads: &Mutex<BotThreads>,
+    llm: &Llm,
 ) -> bool {
     if msg.author.id == self_id {
         return false;
@@ -157,7 +182,11 @@ pub async fn react_to_mention(
         return false;
     }
 
-    react(ctx, msg, self_id, conf).await;
+    if start_thread_conversation(ctx, msg, self_id, conf, threads, llm).await {
+        return true;
+    }
+
+    react(ctx, msg, ReplyTarget::Channel, self_id, conf, llm).await;
 
     true
 }
@@ -167,6 +196,7 @@ pub async fn react_to_trigger_word(
     msg: &Message,
     self_id: UserId,
     conf: &Config,
+    llm: &Llm,
 ) -> bool {
     if msg.author.id == self_id {
         return false;
@@ -180,7 +210,7 @@ pub async fn react_to_trigger_word(
         return false;
     }
 
-    react(ctx, msg, self_id, conf).await;
+    react(ctx, msg, ReplyTarget::Channel, self_id, conf, llm).await;
 
     true
 }
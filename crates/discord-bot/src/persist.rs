@@ -0,0 +1,159 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes the file through a temporary file in the same directory and a rename, so a crash
+/// leaves either the old or the new content, never a truncated file.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    fs::create_dir_all(dir)?;
+
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or("data");
+    let tmp_path = dir.join(format!(".{file_name}.tmp"));
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(bytes)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(&tmp_path, path)?;
+
+    // Persist the rename itself
+    if let Ok(dir) = File::open(dir) {
+        dir.sync_all().ok();
+    }
+    Ok(())
+}
+
+/// Keeps the previous versions as `name.1.ext` (newest) .. `name.<backups>.ext`, then writes
+/// the new content atomically.
+pub fn save_with_backups(path: &Path, bytes: &[u8], backups: usize) -> io::Result<()> {
+    if backups > 0 && path.exists() {
+        let oldest = rotated_path(path, backups);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for index in (1..backups).rev() {
+            let from = rotated_path(path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(path, index + 1))?;
+            }
+        }
+        write_atomic(&rotated_path(path, 1), &fs::read(path)?)?;
+    }
+    write_atomic(path, bytes)
+}
+
+/// Parses the file, falling back to the newest backup that parses. `None` if there is no
+/// file at all. When nothing parses the broken file is kept aside as `name.ext.corrupt`.
+pub fn load_with_fallback<T, E: std::fmt::Display>(
+    path: &Path,
+    backups: usize,
+    parse: impl Fn(&[u8]) -> Result<T, E>,
+) -> io::Result<Option<T>> {
+    let candidates = std::iter::once(path.to_path_buf())
+        .chain((1..=backups).map(|index| rotated_path(path, index)))
+        .collect::<Vec<PathBuf>>();
+
+    let mut found = false;
+    for candidate in candidates.iter() {
+        let bytes = match fs::read(candidate) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                tracing::error!("Error reading {}: {}", candidate.display(), e);
+                continue;
+            }
+        };
+        found = true;
+        match parse(&bytes) {
+            Ok(value) => {
+                if candidate != path {
+                    tracing::warn!("Recovered {} from {}", path.display(), candidate.display());
+                }
+                return Ok(Some(value));
+            }
+            Err(e) => tracing::error!("Error parsing {}: {}", candidate.display(), e),
+        }
+    }
+
+    if !found {
+        return Ok(None);
+    }
+    if path.exists() {
+        let mut corrupt = path.as_os_str().to_owned();
+        corrupt.push(".corrupt");
+        fs::rename(path, &corrupt)?;
+        tracing::error!("No valid copy of {}, kept it aside", path.display());
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("no valid copy of {}", path.display()),
+    ))
+}
+
+/// `dir/name.ext` -> `dir/name.<index>.ext`
+pub fn rotated_path(path: &Path, index: usize) -> PathBuf {
+    let stem = path.file_stem().and_then(|s| s.to_str()).unwrap_or("data");
+    let file_name = match path.extension().and_then(|e| e.to_str()) {
+        Some(extension) => format!("{stem}.{index}.{extension}"),
+        None => format!("{stem}.{index}"),
+    };
+    path.with_file_name(file_name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("persist-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn parse(bytes: &[u8]) -> Result<u32, String> {
+        std::str::from_utf8(bytes)
+            .map_err(|e| e.to_string())?
+            .parse::<u32>()
+            .map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_save_keeps_backups() {
+        let dir = temp_dir();
+        let path = dir.join("stat.json");
+        for value in ["1", "2", "3", "4"] {
+            save_with_backups(&path, value.as_bytes(), 2).unwrap();
+        }
+
+        assert_eq!(fs::read_to_string(&path).unwrap(), "4");
+        assert_eq!(fs::read_to_string(rotated_path(&path, 1)).unwrap(), "3");
+        assert_eq!(fs::read_to_string(rotated_path(&path, 2)).unwrap(), "2");
+        assert!(!rotated_path(&path, 3).exists());
+        assert!(!dir.join(".stat.json.tmp").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_load_falls_back_to_newest_valid_backup() {
+        let dir = temp_dir();
+        let path = dir.join("stat.json");
+        assert_eq!(load_with_fallback(&path, 2, parse).unwrap(), None);
+
+        for value in ["1", "2", "3"] {
+            save_with_backups(&path, value.as_bytes(), 2).unwrap();
+        }
+        fs::write(&path, "{truncated").unwrap();
+        assert_eq!(load_with_fallback(&path, 2, parse).unwrap(), Some(2));
+
+        fs::write(rotated_path(&path, 1), "").unwrap();
+        assert_eq!(load_with_fallback(&path, 2, parse).unwrap(), Some(1));
+
+        fs::write(rotated_path(&path, 2), "").unwrap();
+        assert!(load_with_fallback(&path, 2, parse).is_err());
+        assert!(dir.join("stat.json.corrupt").exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,273 @@
+use super::*;
+
+use ollama_rs::generation::chat::ChatMessage;
+use std::collections::HashMap;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+const REPLAY_USAGE: &str = "Usage: discord-bot replay [--mock] [--model NAME] [--host URL] \
+[--port PORT] [--out FILE] <transcript.jsonl>...
+
+Replays recorded LLM exchanges against the configured backend (OLLAMA_HOST, OLLAMA_PORT)
+and prints the difference with the recorded answers. With --mock the requests go to a local
+mock Ollama server that serves the recorded answers.";
+
+pub async fn run_replay(args: &[String]) -> i32 {
+    let args = CliArgs::parse(args, &["mock"]);
+    if args.positional.is_empty() {
+        eprintln!("{REPLAY_USAGE}");
+        return 2;
+    }
+
+    let records = args
+        .positional
+        .iter()
+        .flat_map(|path| read_json_lines::<TranscriptRecord>(path))
+        .filter(|record| record.response.is_some())
+        .collect::<Vec<TranscriptRecord>>();
+    println!("Loaded {} recorded exchanges", records.len());
+
+    let mut conf = init_offline_config();
+    if let Some(host) = args.value("host") {
+        conf.ollama_host = host.to_string();
+    }
+    if let Some(port) = args.value("port") {
+        conf.ollama_port = port.parse().expect("--port must be a valid port");
+    }
+    if args.flag("mock") {
+        let port = match start_mock_ollama(&records).await {
+            Ok(port) => port,
+            Err(e) => {
+                eprintln!("Error starting mock Ollama: {e}");
+                return 1;
+            }
+        };
+        conf.ollama_host = "http://127.0.0.1".to_string();
+        conf.ollama_port = port;
+    }
+    let mut writer = args
+        .value("out")
+        .map(|out| TranscriptWriter::with_limits(out, u64::MAX, 0));
+
+    let (mut same, mut changed, mut failed) = (0, 0, 0);
+    for record in records.iter() {
+        let model = args.value("model").unwrap_or(&record.model);
+        let mut replay_conf = conf.clone();
+        replay_conf.ollama_options = record.options.clone();
+
+        let started = Instant::now();
+        let result = send_chat(&replay_conf, model, record.messages.clone()).await;
+        let replayed = TranscriptRecord::new(
+            &record.purpose,
+            model,
+            &record.messages,
+            record.options.clone(),
+            &result,
+            started.elapsed(),
+        );
+        if let Some(writer) = writer.as_mut() {
+            writer.write(&replayed);
+        }
+
+        let old = record.response_content().unwrap_or_default();
+        let new = match replayed.response_content() {
+            Some(new) => new,
+            None => {
+                failed += 1;
+                println!(
+                    "=== {} ({model}): {}",
+                    record.id,
+                    replayed.error.unwrap_or_default()
+                );
+                continue;
+            }
+        };
+        if old == new {
+            same += 1;
+            continue;
+        }
+        changed += 1;
+        println!(
+            "=== {} ({} -> {model}, {} ms -> {} ms)",
+            record.id, record.model, record.latency_ms, replayed.latency_ms
+        );
+        for line in diff_lines(&old, &new) {
+            println!("{line}");
+        }
+    }
+
+    println!("Same: {same}, changed: {changed}, failed: {failed}");
+    match failed {
+        0 => 0,
+        _ => 1,
+    }
+}
+
+/// Line based diff of two texts, lines are prefixed with `-`, `+` or a space.
+pub fn diff_lines(old: &str, new: &str) -> Vec<String> {
+    let old = old.lines().collect::<Vec<&str>>();
+    let new = new.lines().collect::<Vec<&str>>();
+
+    // Longest common subsequence table, filled from the end
+    let mut lcs = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = match old[i] == new[j] {
+                true => lcs[i + 1][j + 1] + 1,
+                false => lcs[i + 1][j].max(lcs[i][j + 1]),
+            };
+        }
+    }
+
+    let mut result = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() && j < new.len() {
+        if old[i] == new[j] {
+            result.push(format!("  {}", old[i]));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.push(format!("- {}", old[i]));
+            i += 1;
+        } else {
+            result.push(format!("+ {}", new[j]));
+            j += 1;
+        }
+    }
+    result.extend(old[i..].iter().map(|line| format!("- {line}")));
+    result.extend(new[j..].iter().map(|line| format!("+ {line}")));
+    result
+}
+
+/// Serves `/api/chat` and `/api/tags` from the recorded exchanges on a random local port.
+pub async fn start_mock_ollama(records: &[TranscriptRecord]) -> std::io::Result<u16> {
+    let mut responses = HashMap::new();
+    for record in records {
+        if let Some(response) = record.response.clone() {
+            responses.insert(mock_key(&record.model, &record.messages), response);
+        }
+    }
+    let mut models = records
+        .iter()
+        .map(|record| record.model.clone())
+        .collect::<Vec<String>>();
+    models.sort();
+    models.dedup();
+
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let port = listener.local_addr()?.port();
+    let responses = Arc::new(responses);
+    let models = Arc::new(models);
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = match listener.accept().await {
+                Ok(connection) => connection,
+                Err(e) => {
+                    tracing::error!("Mock Ollama accept error: {}", e);
+                    continue;
+                }
+            };
+            let responses = responses.clone();
+            let models = models.clone();
+            tokio::spawn(async move {
+                if let Err(e) = serve_mock_request(stream, &responses, &models).await {
+                    tracing::error!("Mock Ollama error: {}", e);
+                }
+            });
+        }
+    });
+
+    tracing::info!("Mock Ollama listens on 127.0.0.1:{}", port);
+    Ok(port)
+}
+
+fn mock_key(model: &str, messages: &[ChatMessage]) -> String {
+    format!(
+        "{model}\n{}",
+        serde_json::to_string(messages).unwrap_or_default()
+    )
+}
+
+async fn serve_mock_request(
+    mut stream: TcpStream,
+    responses: &HashMap<String, serde_json::Value>,
+    models: &[String],
+) -> std::io::Result<()> {
+    let mut buffer = vec![];
+    let mut chunk = [0u8; 8192];
+    let (head, body_start) = loop {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+        if let Some(end) = buffer.windows(4).position(|w| w == b"\r\n\r\n") {
+            break (String::from_utf8_lossy(&buffer[..end]).to_string(), end + 4);
+        }
+    };
+
+    let content_length = head
+        .lines()
+        .filter_map(|line| line.split_once(':'))
+        .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+        .and_then(|(_, value)| value.trim().parse::<usize>().ok())
+        .unwrap_or(0);
+    while buffer.len() < body_start + content_length {
+        let read = stream.read(&mut chunk).await?;
+        if read == 0 {
+            break;
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+    let body = &buffer[body_start..buffer.len().min(body_start + content_length)];
+
+    let path = head.split_whitespace().nth(1).unwrap_or("/");
+    let (status, response) = match path {
+        "/api/tags" => {
+            let models = models
+                .iter()
+                .map(|name| serde_json::json!({"name": name, "modified_at": "", "size": 0}))
+                .collect::<Vec<serde_json::Value>>();
+            ("200 OK", serde_json::json!({ "models": models }))
+        }
+        "/api/chat" => {
+            let request = serde_json::from_slice::<serde_json::Value>(body).unwrap_or_default();
+            let model = request["model"].as_str().unwrap_or_default();
+            let messages = serde_json::from_value::<Vec<ChatMessage>>(request["messages"].clone())
+                .unwrap_or_default();
+            match responses.get(&mock_key(model, &messages)) {
+                Some(response) => ("200 OK", response.clone()),
+                None => (
+                    "404 Not Found",
+                    serde_json::json!({"error": format!("model \"{model}\" not found, no recorded exchange")}),
+                ),
+            }
+        }
+        _ => ("404 Not Found", serde_json::json!({"error": "not found"})),
+    };
+
+    let response = response.to_string();
+    let reply = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+        response.len()
+    );
+    stream.write_all(reply.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let diff = diff_lines("a\nb\nc", "a\nc\nd");
+        assert_eq!(diff, ["  a", "- b", "  c", "+ d"]);
+    }
+
+    #[test]
+    fn test_diff_identical() {
+        assert_eq!(diff_lines("same", "same"), ["  same"]);
+    }
+}
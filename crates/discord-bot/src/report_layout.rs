@@ -0,0 +1,585 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use serenity::all::{CacheHttp, ChannelId, UserId};
+use std::collections::HashMap;
+
+/// Which users and columns the report table shows and in which order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReportLayout {
+    pub columns: Vec<ReportColumn>,
+    pub sort_by: ReportColumn,
+    pub descending: bool,
+    pub top: usize,          // rows before the "others" row, 0 for everyone
+    pub min_messages: usize, // users with fewer messages are left out
+}
+
+impl Default for ReportLayout {
+    fn default() -> Self {
+        ReportLayout {
+            columns: vec![
+                ReportColumn::Messages,
+                ReportColumn::Series,
+                ReportColumn::Files,
+            ],
+            sort_by: ReportColumn::Messages,
+            descending: true,
+            top: 0,
+            min_messages: 1,
+        }
+    }
+}
+
+impl ReportLayout {
+    /// Parses `REPORT_SORT`: a column name with an optional `asc` or `desc`.
+    pub fn parse_sort(spec: &str) -> Result<(ReportColumn, bool), String> {
+        let mut words = spec.split_whitespace();
+        let column = words.next().unwrap_or("messages").parse()?;
+        let descending = match words.next() {
+            None | Some("desc") => true,
+            Some("asc") => false,
+            Some(other) => return Err(format!("unknown sort direction `{other}`")),
+        };
+        match words.next() {
+            Some(extra) => Err(format!("unexpected `{extra}` in the sort order")),
+            None => Ok((column, descending)),
+        }
+    }
+
+    /// Splits the users into the shown rows, in order, and the ones folded into "others".
+    /// Ties are broken by messages, then by id, so the same data always gives the same table.
+    pub fn select_rows(&self, stat: &MessageStat) -> (Vec<UserId>, Vec<UserId>) {
+        let mut users = stat
+            .users()
+            .into_iter()
+            .filter(|user_id| stat.value(ReportColumn::Messages, user_id) >= self.min_messages)
+            .collect::<Vec<UserId>>();
+        users.sort_by(|a, b| {
+            let by_column = stat
+                .value(self.sort_by, a)
+                .cmp(&stat.value(self.sort_by, b));
+            let by_column = match self.descending {
+                true => by_column.reverse(),
+                false => by_column,
+            };
+            by_column
+                .then_with(|| {
+                    stat.value(ReportColumn::Messages, b)
+                        .cmp(&stat.value(ReportColumn::Messages, a))
+                })
+                .then_with(|| a.cmp(b))
+        });
+
+        // An "others" row of one user is no shorter than the user's own row
+        if self.top == 0 || users.len() <= self.top + 1 {
+            return (users, vec![]);
+        }
+        let others = users.split_off(self.top);
+        (users, others)
+    }
+}
+
+/// Looks up the names shown in reports, Discord in the bot and a fixed map in tests.
+pub trait NameResolver {
+    async fn user_name(&self, user_id: UserId) -> String;
+    async fn channel_name(&self, channel_id: ChannelId) -> String;
+}
+
+pub struct DiscordNames<'a, C: CacheHttp> {
+    pub cache_http: C,
+    pub conf: &'a Config,
+}
+
+impl<C: CacheHttp> NameResolver for DiscordNames<'_, C> {
+    async fn user_name(&self, user_id: UserId) -> String {
+        get_user_name(&user_id, &self.cache_http, self.conf).await
+    }
+
+    async fn channel_name(&self, channel_id: ChannelId) -> String {
+        get_channel_name(&channel_id, &self.cache_http).await
+    }
+}
+
+/// Renders the report table of the period, `None` if nobody passes the layout filters.
+/// With the previous period the rows also get the change of the sort column and the rank
+/// movement.
+pub async fn format_report_table(
+    stat: &MessageStat,
+    previous: Option<&MessageStat>,
+    names: &impl NameResolver,
+    layout: &ReportLayout,
+) -> Option<String> {
+    let (shown, others) = layout.select_rows(stat);
+    if shown.is_empty() {
+        return None;
+    }
+    let previous_ranks = previous.map(|previous| {
+        let everyone = ReportLayout {
+            top: 0,
+            ..layout.clone()
+        };
+        let (ranking, _) = everyone.select_rows(previous);
+        ranking
+            .into_iter()
+            .enumerate()
+            .map(|(rank, user_id)| (user_id, rank))
+            .collect::<HashMap<UserId, usize>>()
+    });
+
+    let mut rows = vec![];
+    for (rank, user_id) in shown.iter().enumerate() {
+        let mut row = vec![names.user_name(*user_id).await];
+        for column in layout.columns.iter() {
+            let cell = match (column, stat.personal_record.get(user_id)) {
+                (ReportColumn::Series, Some(record)) => {
+                    let channel_name = names.channel_name(record.channel_id).await;
+                    format!("{:>4} in {channel_name}", record.counter)
+                }
+                (ReportColumn::Series, None) => format!("{:>4}", "-"),
+                (column, _) => stat.value(*column, user_id).to_string(),
+            };
+            row.push(cell);
+        }
+        if let (Some(previous), Some(previous_ranks)) = (previous, previous_ranks.as_ref()) {
+            let column = layout.sort_by;
+            row.push(format_delta(
+                stat.value(column, user_id),
+                previous.value(column, user_id),
+            ));
+            row.push(match previous_ranks.get(user_id) {
+                Some(previous_rank) => format_delta(*previous_rank, rank),
+                None => "new".to_string(),
+            });
+        }
+        rows.push(row);
+    }
+    if !others.is_empty() {
+        let mut row = vec![format!("Others ({})", others.len())];
+        for column in layout.columns.iter() {
+            let cell = match column {
+                ReportColumn::Series => String::new(),
+                column => others
+                    .iter()
+                    .map(|user_id| stat.value(*column, user_id))
+                    .sum::<usize>()
+                    .to_string(),
+            };
+            row.push(cell);
+        }
+        if previous.is_some() {
+            row.extend([String::new(), String::new()]);
+        }
+        rows.push(row);
+    }
+
+    let mut header = vec!["User"];
+    header.extend(layout.columns.iter().map(|column| column.header()));
+    let mut right_aligned = vec![false];
+    right_aligned.extend(
+        layout
+            .columns
+            .iter()
+            .map(|column| *column != ReportColumn::Series),
+    );
+    if previous.is_some() {
+        // The change is of the sort column
+        header.extend(["Change", "Rank"]);
+        right_aligned.extend([true, true]);
+    }
+    Some(format_aligned_table(&header, &rows, &right_aligned))
+}
+
+/// Totals of the period above the table, with the change against the previous period.
+pub async fn format_report_summary(
+    stat: &MessageStat,
+    previous: Option<&MessageStat>,
+    names: &impl NameResolver,
+) -> String {
+    let messages = |stat: &MessageStat| stat.messages_count.values().sum::<usize>();
+    let active = |stat: &MessageStat| stat.messages_count.values().filter(|c| **c > 0).count();
+    let change = |value: fn(&MessageStat) -> usize| match previous {
+        Some(previous) => format_percent_change(value(stat), value(previous)),
+        None => String::new(),
+    };
+
+    let mut lines = vec![
+        format!("Messages: {}{}", messages(stat), change(messages)),
+        format!("Active users: {}{}", active(stat), change(active)),
+    ];
+    let busiest = stat
+        .channel_messages
+        .iter()
+        .map(|(channel_id, users)| (*channel_id, users.values().sum::<usize>()))
+        .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)));
+    if let Some((channel_id, count)) = busiest {
+        let name = names.channel_name(channel_id).await;
+        lines.push(format!("Busiest channel: #{name}, {count} messages"));
+    }
+    let voice = stat.voice_seconds.values().sum::<usize>();
+    if voice > 0 {
+        lines.push(format!(
+            "Voice: {}, up to {} at once",
+            format_voice_time(voice),
+            stat.voice_peak
+        ));
+    }
+    let longest = stat
+        .voice_longest
+        .iter()
+        .max_by(|a, b| a.1.cmp(b.1).then(b.0.cmp(a.0)));
+    if let Some((user_id, seconds)) = longest {
+        let name = names.user_name(*user_id).await;
+        lines.push(format!(
+            "Longest call: {name}, {}",
+            format_voice_time(*seconds)
+        ));
+    }
+    lines.join("\n")
+}
+
+/// The longest series of the period with their time span and a link to where they started,
+/// `None` when there are none or `top` is 0.
+pub async fn format_streak_records(
+    stat: &MessageStat,
+    names: &impl NameResolver,
+    tz: Tz,
+    top: usize,
+) -> Option<String> {
+    let mut records = stat
+        .personal_record
+        .iter()
+        .filter(|(_, record)| record.counter > 1)
+        .collect::<Vec<(&UserId, &MessageStreakPersonalRecord)>>();
+    records.sort_by(|a, b| b.1.counter.cmp(&a.1.counter).then(a.0.cmp(b.0)));
+    records.truncate(top);
+    if records.is_empty() {
+        return None;
+    }
+
+    let mut lines = vec!["Longest series:".to_string()];
+    for (index, (user_id, record)) in records.into_iter().enumerate() {
+        let name = names.user_name(*user_id).await;
+        let channel_name = names.channel_name(record.channel_id).await;
+        let mut line = format!(
+            "{}. {name} — {} in #{channel_name}",
+            index + 1,
+            record.counter
+        );
+        if let (Some(start), Some(end)) = (record.start, record.end) {
+            line.push_str(&format!(", {}", format_span(start, end, tz)));
+        }
+        if let Some(link) = record.jump_link() {
+            line.push_str(&format!(" [jump](<{link}>)"));
+        }
+        lines.push(line);
+    }
+    Some(lines.join("\n"))
+}
+
+/// The busiest channels of the period with threads and forum posts counted in their
+/// channel, `None` when there are none or `top` is 0.
+pub async fn format_channel_leaderboard(
+    stat: &MessageStat,
+    names: &impl NameResolver,
+    top: usize,
+) -> Option<String> {
+    let mut channels = stat.channel_activity();
+    channels.truncate(top);
+    if channels.is_empty() {
+        return None;
+    }
+
+    let header = ["Channel", "Messages", "Posters", "Files", "Peak hour"];
+    let mut rows = vec![];
+    for channel in channels {
+        let peak_hour = match channel.peak_hour() {
+            Some(hour) => format!("{hour:02}:00"),
+            None => "-".to_string(),
+        };
+        rows.push(vec![
+            format!("#{}", names.channel_name(channel.channel_id).await),
+            channel.messages.to_string(),
+            channel.posters.to_string(),
+            channel.files.to_string(),
+            peak_hour,
+        ]);
+    }
+    Some(format_aligned_table(
+        &header,
+        &rows,
+        &[false, true, true, true, false],
+    ))
+}
+
+/// `2025-03-05 21:30-22:10`, the end date only when it differs.
+fn format_span(start: DateTime<Utc>, end: DateTime<Utc>, tz: Tz) -> String {
+    let (start, end) = (start.with_timezone(&tz), end.with_timezone(&tz));
+    let end_format = match start.date_naive() == end.date_naive() {
+        true => "%H:%M",
+        false => "%Y-%m-%d %H:%M",
+    };
+    format!(
+        "{}-{}",
+        start.format("%Y-%m-%d %H:%M"),
+        end.format(end_format)
+    )
+}
+
+/// `▲12`, `▼3` or `=`.
+pub fn format_delta(current: usize, previous: usize) -> String {
+    match current.cmp(&previous) {
+        std::cmp::Ordering::Greater => format!("▲{}", current - previous),
+        std::cmp::Ordering::Less => format!("▼{}", previous - current),
+        std::cmp::Ordering::Equal => "=".to_string(),
+    }
+}
+
+/// ` (+12%)`, nothing when the previous value is zero.
+fn format_percent_change(current: usize, previous: usize) -> String {
+    if previous == 0 {
+        return String::new();
+    }
+    let percent = (current as f64 - previous as f64) * 100.0 / previous as f64;
+    format!(" ({:+.0}%)", percent)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serenity::all::{GuildId, MessageId};
+    use std::collections::HashMap;
+    use std::fs;
+    use std::path::Path;
+
+    struct FakeNames;
+
+    impl NameResolver for FakeNames {
+        async fn user_name(&self, user_id: UserId) -> String {
+            match user_id.get() {
+                1 => "Алиса".to_string(),
+                2 => "bob".to_string(),
+                3 => "Ахиллес сын Пелея".to_string(),
+                id => format!("user{id}"),
+            }
+        }
+
+        async fn channel_name(&self, channel_id: ChannelId) -> String {
+            format!("channel-{}", channel_id.get())
+        }
+    }
+
+    /// Compares with `testdata/<name>`, `UPDATE_GOLDEN=1` rewrites the file instead.
+    fn assert_golden(name: &str, actual: &str) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("testdata")
+            .join(name);
+        if env::var("UPDATE_GOLDEN").is_ok() {
+            fs::write(&path, actual).unwrap();
+        }
+        let expected = fs::read_to_string(&path).unwrap();
+        assert_eq!(actual, expected, "{} differs", path.display());
+    }
+
+    fn sample_stat() -> MessageStat {
+        let user = UserId::new;
+        let mut stat = MessageStat {
+            messages_count: HashMap::from([
+                (user(1), 40),
+                (user(2), 12),
+                (user(3), 12),
+                (user(4), 1),
+            ]),
+            attachments_count: HashMap::from([(user(1), 3), (user(3), 5)]),
+            chars_count: HashMap::from([
+                (user(1), 900),
+                (user(2), 150),
+                (user(3), 4000),
+                (user(4), 7),
+            ]),
+            // Reacts but never writes
+            reactions_given: HashMap::from([(user(5), 9), (user(1), 2)]),
+            ..MessageStat::default()
+        };
+        for (user_id, counter, channel_id) in [(1, 7, 10), (3, 2, 11)] {
+            stat.personal_record.insert(
+                user(user_id),
+                MessageStreakPersonalRecord {
+                    channel_id: ChannelId::new(channel_id),
+                    counter,
+                    ..MessageStreakPersonalRecord::default()
+                },
+            );
+        }
+        stat
+    }
+
+    #[tokio::test]
+    async fn test_default_layout_golden() {
+        let layout = ReportLayout::default();
+        let table = format_report_table(&sample_stat(), None, &FakeNames, &layout)
+            .await
+            .unwrap();
+        assert_golden("report_default.txt", &table);
+
+        // Every fresh HashMap iterates in another order, the table stays the same
+        for _ in 0..5 {
+            let again = format_report_table(&sample_stat(), None, &FakeNames, &layout).await;
+            assert_eq!(again.as_ref(), Some(&table));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_custom_layout_golden() {
+        let layout = ReportLayout {
+            columns: vec![
+                ReportColumn::Chars,
+                ReportColumn::Series,
+                ReportColumn::ReactionsGiven,
+            ],
+            sort_by: ReportColumn::Chars,
+            descending: false,
+            top: 2,
+            min_messages: 0,
+        };
+        let table = format_report_table(&sample_stat(), None, &FakeNames, &layout).await;
+        assert_golden("report_custom.txt", &table.unwrap());
+
+        let nobody = ReportLayout {
+            min_messages: 100,
+            ..ReportLayout::default()
+        };
+        assert_eq!(
+            format_report_table(&sample_stat(), None, &FakeNames, &nobody).await,
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn test_deltas_golden() {
+        let user = UserId::new;
+        let previous = MessageStat {
+            messages_count: HashMap::from([(user(1), 28), (user(2), 15), (user(3), 20)]),
+            channel_messages: HashMap::from([(
+                ChannelId::new(10),
+                HashMap::from([(user(1), 28), (user(2), 15), (user(3), 20)]),
+            )]),
+            ..MessageStat::default()
+        };
+        let mut stat = sample_stat();
+        stat.channel_messages = HashMap::from([
+            (
+                ChannelId::new(10),
+                HashMap::from([(user(1), 30), (user(2), 12)]),
+            ),
+            (
+                ChannelId::new(11),
+                HashMap::from([(user(1), 10), (user(3), 12), (user(4), 1)]),
+            ),
+        ]);
+
+        let layout = ReportLayout::default();
+        let table = format_report_table(&stat, Some(&previous), &FakeNames, &layout).await;
+        assert_golden("report_deltas.txt", &table.unwrap());
+
+        let summary = format_report_summary(&stat, Some(&previous), &FakeNames).await;
+        assert_eq!(
+            summary,
+            "Messages: 65 (+3%)\nActive users: 4 (+33%)\nBusiest channel: #channel-10, 42 messages"
+        );
+        let first = format_report_summary(&previous, None, &FakeNames).await;
+        assert_eq!(
+            first,
+            "Messages: 63\nActive users: 3\nBusiest channel: #channel-10, 63 messages"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_voice_summary() {
+        let user = UserId::new;
+        let stat = MessageStat {
+            voice_seconds: HashMap::from([(user(1), 3 * 3600), (user(2), 3900)]),
+            voice_longest: HashMap::from([(user(1), 2 * 3600), (user(2), 3900)]),
+            voice_peak: 2,
+            ..MessageStat::default()
+        };
+        let summary = format_report_summary(&stat, None, &FakeNames).await;
+        assert_eq!(
+            summary,
+            "Messages: 0\nActive users: 0\nVoice: 4h 05m, up to 2 at once\n\
+             Longest call: Алиса, 2h 00m"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_streak_records() {
+        let mut stat = sample_stat();
+        let record = stat.personal_record.get_mut(&UserId::new(1)).unwrap();
+        record.start = Some("2025-03-05T21:30:00Z".parse().unwrap());
+        record.end = Some("2025-03-05T22:10:00Z".parse().unwrap());
+        record.first_message = Some(MessageId::new(99));
+        record.guild_id = Some(GuildId::new(1));
+
+        let tz = "Europe/Moscow".parse().unwrap();
+        let records = format_streak_records(&stat, &FakeNames, tz, 3).await;
+        assert_eq!(
+            records.unwrap(),
+            "Longest series:\n\
+             1. Алиса — 7 in #channel-10, 2025-03-06 00:30-01:10 \
+             [jump](<https://discord.com/channels/1/10/99>)\n\
+             2. Ахиллес сын Пелея — 2 in #channel-11"
+        );
+        assert_eq!(format_streak_records(&stat, &FakeNames, tz, 0).await, None);
+    }
+
+    #[tokio::test]
+    async fn test_channel_leaderboard() {
+        let (user, channel) = (UserId::new, ChannelId::new);
+        let mut stat = sample_stat();
+        stat.channel_messages = HashMap::from([
+            (channel(10), HashMap::from([(user(1), 30), (user(2), 12)])),
+            (channel(11), HashMap::from([(user(3), 12)])),
+            // A thread of channel 11
+            (channel(12), HashMap::from([(user(1), 10), (user(4), 1)])),
+        ]);
+        stat.channel_files = HashMap::from([
+            (channel(10), HashMap::from([(user(1), 3)])),
+            (channel(12), HashMap::from([(user(4), 5)])),
+        ]);
+        let mut hours = [0; 24];
+        hours[21] = 30;
+        hours[9] = 12;
+        stat.channel_hours.insert(channel(10), hours);
+        let mut hours = [0; 24];
+        hours[8] = 12;
+        stat.channel_hours.insert(channel(11), hours);
+        let mut hours = [0; 24];
+        hours[23] = 11;
+        stat.channel_hours.insert(channel(12), hours);
+        stat.thread_parents.insert(channel(12), channel(11));
+
+        let table = format_channel_leaderboard(&stat, &FakeNames, 5).await;
+        assert_eq!(
+            table.unwrap(),
+            "Channel     | Messages | Posters | Files | Peak hour\n\
+             ------------|----------|---------|-------|----------\n\
+             #channel-10 |       42 |       2 |     3 | 21:00    \n\
+             #channel-11 |       23 |       3 |     5 | 08:00    "
+        );
+        assert_eq!(format_channel_leaderboard(&stat, &FakeNames, 0).await, None);
+    }
+
+    #[test]
+    fn test_parse_sort() {
+        assert_eq!(
+            ReportLayout::parse_sort("chars"),
+            Ok((ReportColumn::Chars, true))
+        );
+        assert_eq!(
+            ReportLayout::parse_sort(" edits asc "),
+            Ok((ReportColumn::Edits, false))
+        );
+        assert!(ReportLayout::parse_sort("chars up").is_err());
+        assert!(ReportLayout::parse_sort("likes").is_err());
+    }
+}
@@ -1,42 +1,522 @@
 use super::*;
 
-use serenity::all::Http;
+use serde::{Deserialize, Serialize};
+use serenity::all::{CreateAttachment, CreateMessage, GetMessages, Http, HttpError, Nonce};
 use serenity::prelude::*;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::MutexGuard;
 
-pub fn stat_reporter(http: Arc<Http>, stat: Arc<Mutex<Stat>>, conf: Config) {
-    tokio::spawn(async move {
-        let flood_channel_id = serenity::all::ChannelId::from(1245807370341191812); // TODO - move to config
+const LATE_AFTER: chrono::Duration = chrono::Duration::minutes(1);
+const RETRY_INTERVAL: Duration = Duration::from_secs(60);
+const MESSAGE_LIMIT: usize = 2000;
+const BACKUP_INTERVAL: chrono::Duration = chrono::Duration::days(1);
 
-        let mut stat_guard = stat.lock().await;
-        stat_guard.init_collection();
-        drop(stat_guard);
+static SAVE_GENERATION: AtomicU64 = AtomicU64::new(0);
+static SAVED_GENERATION: std::sync::Mutex<u64> = std::sync::Mutex::new(0);
+
+/// A rendered report waiting to be published. The nonce lets Discord drop the duplicate
+/// when the bot resends it after a restart.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PendingReport {
+    pub nonce: String,
+    pub schedule: String,
+    pub content: String,
+    #[serde(default)]
+    pub image_file: Option<String>, // chart to attach, removed once published
+    #[serde(default)]
+    pub left_out_table: Option<String>, // replaced by the chart, sent if the chart is gone
+    #[serde(default)]
+    pub sent_at: Option<chrono::DateTime<chrono::Utc>>, // last attempt, its outcome may be unknown
+    #[serde(skip)]
+    pub rejected: bool, // Discord refused it even as a file, retried after a restart
+}
 
+/// Starts one reporter task per configured schedule, the periods are already initialized.
+pub fn stat_reporter(http: Arc<Http>, stat: Arc<Mutex<Stat>>, conf: Config) {
+    for schedule in conf.report_schedules.iter() {
+        schedule_reporter(http.clone(), stat.clone(), conf.clone(), schedule.clone());
+    }
+}
+
+fn schedule_reporter(http: Arc<Http>, stat: Arc<Mutex<Stat>>, conf: Config, schedule: Schedule) {
+    tokio::spawn(async move {
         loop {
-            let now = chrono::Utc::now().naive_utc();
+            if !publish_pending_reports(&http, &stat, &conf, &schedule).await {
+                tokio::time::sleep(RETRY_INTERVAL).await;
+                continue;
+            }
 
+            let now = chrono::Utc::now();
             let stat_guard = stat.lock().await;
-            let diff = (stat_guard.collect_until - now).to_std().unwrap();
+            let collect_until = match stat_guard.periods.get(&schedule.name) {
+                Some(period) => period.collect_until,
+                None => schedule.next_after(now, conf.report_timezone()),
+            };
             drop(stat_guard);
 
-            tokio::time::sleep(diff).await;
+            // An overdue period is reported right away
+            if let Ok(diff) = (collect_until - now).to_std() {
+                tokio::time::sleep(diff).await;
+            }
 
+            // The archive may be large, it is read before the statistics are locked
+            let (archive_file, name) = (conf.archive_file.clone(), schedule.name.clone());
+            let periods = tokio::task::spawn_blocking(move || load_archive(&archive_file, &name));
+            let periods = match periods.await {
+                Ok(periods) => periods,
+                Err(e) => {
+                    tracing::error!("Error loading the archive: {}", e);
+                    vec![]
+                }
+            };
+            let mut stat_guard = stat.lock().await;
+            let previous = stat_guard
+                .periods
+                .get(&schedule.name)
+                .and_then(|period| previous_period(&periods, period.collection_start))
+                .map(|previous| &previous.message_stat);
+            let report = stat_guard
+                .collect_report(&schedule, previous, &http, &conf)
+                .await;
+            // The period has left the statistics, the names are looked up without the lock
+            drop(stat_guard);
+            let archived = ArchivedPeriod::new(&schedule, &report, &http, &conf).await;
+            archive_period(&conf, &archived).await;
+            let nonce = Uuid::new_v4().simple().to_string()[..25].to_string();
+            let mut image_file = match report.table.is_some() {
+                true => report_image(&http, &conf, &schedule, &report, &nonce).await,
+                false => None,
+            };
+            let with_table = image_file.is_none() || conf.report_image == ReportImage::Alongside;
             let mut stat_guard = stat.lock().await;
-            if let Some(table) = stat_guard.collect_report(&http, &conf).await {
-                let table_message = [
-                    env::var("TABLE_HEADER").unwrap_or_default().as_str(),
-                    "```",
-                    table.as_str(),
-                    "```",
-                ]
-                .join("\n");
-                if let Err(why) = flood_channel_id.say(&http, table_message).await {
-                    tracing::error!("Error sending message: {why:?}");
+            match report_message(&conf, &schedule, &report, with_table) {
+                Some(parts) => {
+                    // Each message gets its own nonce, the chart goes with the first one
+                    for (index, content) in parts.into_iter().enumerate() {
+                        let nonce = match index {
+                            0 => nonce.clone(),
+                            _ => Uuid::new_v4().simple().to_string()[..25].to_string(),
+                        };
+                        let image_file = image_file.take();
+                        let left_out_table = match (&image_file, with_table) {
+                            (Some(_), false) => report.table.clone(),
+                            _ => None,
+                        };
+                        stat_guard.pending_reports.push(PendingReport {
+                            nonce,
+                            schedule: schedule.name.clone(),
+                            content,
+                            image_file,
+                            left_out_table,
+                            sent_at: None,
+                            rejected: false,
+                        });
+                    }
                 }
-            } else {
-                tracing::warn!("No data to report");
+                None => tracing::warn!("No data to report for {}", schedule.name),
+            }
+            save_stat_with_backup(stat_guard, &conf).await;
+        }
+    });
+}
+
+/// The report in as many messages as Discord's limit needs. Sections stay whole where they
+/// fit, a long table goes on in the next message's code block.
+fn report_message(
+    conf: &Config,
+    schedule: &Schedule,
+    report: &PeriodReport,
+    with_table: bool,
+) -> Option<Vec<String>> {
+    let table = report.table.as_ref()?;
+    let mut lines = vec![report_header(conf, schedule)];
+    if chrono::Utc::now() - report.end > LATE_AFTER {
+        lines.push(conf.report_late_note.clone());
+    }
+    lines.push(format_period(
+        report.start,
+        report.end,
+        conf.report_timezone(),
+    ));
+    lines.push(report.summary.clone());
+
+    let mut sections = split_message(&lines.join("\n"), MESSAGE_LIMIT);
+    if with_table {
+        sections.extend(code_blocks(table));
+    }
+    if let Some(channels) = report.channels.as_ref() {
+        sections.extend(code_blocks(channels));
+    }
+    for text in [&report.records, &report.broken_records, &report.emoji]
+        .into_iter()
+        .flatten()
+    {
+        sections.extend(split_message(text, MESSAGE_LIMIT));
+    }
+    Some(pack_sections(sections))
+}
+
+/// Puts the sections together in as few messages as fit Discord's limit, in order.
+fn pack_sections(sections: Vec<String>) -> Vec<String> {
+    let mut messages: Vec<String> = vec![];
+    for section in sections {
+        match messages.last_mut() {
+            Some(last) if last.chars().count() + 1 + section.chars().count() <= MESSAGE_LIMIT => {
+                last.push('\n');
+                last.push_str(&section);
+            }
+            _ => messages.push(section),
+        }
+    }
+    messages
+}
+
+/// A report whose chart is gone goes out with the table instead, in as many messages as
+/// that takes. Those after the first get their own nonces.
+fn restore_table(pending: &PendingReport) -> Vec<PendingReport> {
+    let table = pending.left_out_table.as_deref().unwrap_or_default();
+    let mut sections = split_message(&pending.content, MESSAGE_LIMIT);
+    sections.extend(code_blocks(table));
+    pack_sections(sections)
+        .into_iter()
+        .enumerate()
+        .map(|(index, content)| PendingReport {
+            nonce: match index {
+                0 => pending.nonce.clone(),
+                _ => Uuid::new_v4().simple().to_string()[..25].to_string(),
+            },
+            content,
+            image_file: None,
+            left_out_table: None,
+            ..pending.clone()
+        })
+        .collect()
+}
+
+fn code_blocks(text: &str) -> Vec<String> {
+    split_message(text, MESSAGE_LIMIT - "```\n\n```".len())
+        .into_iter()
+        .map(|part| format!("```\n{part}\n```"))
+        .collect()
+}
+
+fn report_header(conf: &Config, schedule: &Schedule) -> String {
+    let header = env::var("TABLE_HEADER").unwrap_or_default();
+    match conf.report_schedules.len() > 1 {
+        true => format!("{header} ({})", schedule.name).trim().to_string(),
+        false => header,
+    }
+}
+
+/// Renders the chart of the report next to the statistics file, so a pending report
+/// survives a restart with its image. `None` if images are off or rendering failed.
+async fn report_image(
+    http: &Http,
+    conf: &Config,
+    schedule: &Schedule,
+    report: &PeriodReport,
+    nonce: &str,
+) -> Option<String> {
+    if conf.report_image == ReportImage::Off {
+        return None;
+    }
+    let data = ChartData::from_stat(
+        report_header(conf, schedule),
+        format_period(report.start, report.end, conf.report_timezone()),
+        &report.message_stat,
+        http,
+        conf,
+    )
+    .await;
+    let png = match render_report_chart(&data) {
+        Ok(png) => png,
+        Err(e) => {
+            tracing::error!("Error rendering {} chart: {}", schedule.name, e);
+            return None;
+        }
+    };
+    let path = Path::new(&conf.stat_file).with_file_name(format!("report-{nonce}.png"));
+    match write_atomic(&path, &png) {
+        Ok(()) => Some(path.to_string_lossy().to_string()),
+        Err(e) => {
+            tracing::error!("Error saving {} chart: {}", schedule.name, e);
+            None
+        }
+    }
+}
+
+/// Sends the pending reports of the schedule in order. Returns false if some report has to
+/// be retried later.
+async fn publish_pending_reports(
+    http: &Http,
+    stat: &Mutex<Stat>,
+    conf: &Config,
+    schedule: &Schedule,
+) -> bool {
+    loop {
+        let mut stat_guard = stat.lock().await;
+        let index = stat_guard
+            .pending_reports
+            .iter()
+            .position(|report| report.schedule == schedule.name && !report.rejected);
+        let index = match index {
+            Some(index) => index,
+            None => return true,
+        };
+        let pending = &mut stat_guard.pending_reports[index];
+        let chart_gone = pending
+            .image_file
+            .as_ref()
+            .is_some_and(|image_file| !Path::new(image_file).is_file());
+        if chart_gone && pending.left_out_table.is_some() {
+            tracing::warn!("{} chart is gone, sending the table instead", schedule.name);
+            let restored = restore_table(pending);
+            stat_guard.pending_reports.splice(index..=index, restored);
+            continue;
+        }
+        let interrupted = pending.sent_at.is_some();
+        pending.sent_at = Some(chrono::Utc::now());
+        let pending = pending.clone();
+        save_stat(stat_guard, conf).await;
+
+        let sent = match interrupted && already_published(http, conf, &pending).await {
+            true => Ok(()),
+            false => send_report(http, conf, &pending, false).await,
+        };
+        let sent = match sent {
+            Err(why) if is_rejected(&why) => {
+                tracing::error!(
+                    "{} report rejected, sending it as a file: {why:?}",
+                    schedule.name
+                );
+                send_report(http, conf, &pending, true).await
+            }
+            sent => sent,
+        };
+
+        let mut stat_guard = stat.lock().await;
+        match sent {
+            Ok(()) => {
+                tracing::info!("Published {} report", schedule.name);
+                stat_guard
+                    .pending_reports
+                    .retain(|report| report.nonce != pending.nonce);
+                if let Some(image_file) = &pending.image_file {
+                    std::fs::remove_file(image_file).ok();
+                }
+            }
+            Err(why) if is_rejected(&why) => {
+                tracing::error!("Keeping {} report pending: {why:?}", schedule.name);
+                stat_guard
+                    .pending_reports
+                    .iter_mut()
+                    .filter(|report| report.nonce == pending.nonce)
+                    .for_each(|report| report.rejected = true);
+            }
+            Err(why) => {
+                tracing::error!("Error sending message: {why:?}");
+                return false;
+            }
+        }
+        save_stat(stat_guard, conf).await;
+    }
+}
+
+/// `as_file` is the fallback for a message Discord rejects: the text goes in an attachment
+/// under its first line, without the chart.
+async fn send_report(
+    http: &Http,
+    conf: &Config,
+    pending: &PendingReport,
+    as_file: bool,
+) -> serenity::Result<()> {
+    let mut message = CreateMessage::new()
+        .nonce(Nonce::String(pending.nonce.clone()))
+        .enforce_nonce(true);
+    match as_file {
+        true => {
+            // The chart doesn't go in the file, its table does
+            let header = pending.content.lines().next().unwrap_or_default();
+            let text = match &pending.left_out_table {
+                Some(table) => format!("{}\n\n{table}", pending.content),
+                None => pending.content.clone(),
             };
-            stat_guard.message_stat = MessageStat::default();
+            let text = text.into_bytes();
+            message = message
+                .content(header)
+                .add_file(CreateAttachment::bytes(text, "report.txt"));
+        }
+        false => {
+            message = message.content(&pending.content);
+            if let Some(image_file) = &pending.image_file {
+                match std::fs::read(image_file) {
+                    Ok(png) => {
+                        message = message.add_file(CreateAttachment::bytes(png, "report.png"))
+                    }
+                    Err(e) => tracing::error!("Error reading {}: {}", image_file, e),
+                }
+            }
+        }
+    }
+    conf.report_channel_id
+        .send_message(http, message)
+        .await
+        .map(|_| ())
+}
+
+/// A client error other than rate limiting, sending the same message again won't help.
+fn is_rejected(why: &serenity::Error) -> bool {
+    match why {
+        serenity::Error::Http(HttpError::UnsuccessfulRequest(response)) => {
+            response.status_code.is_client_error() && response.status_code.as_u16() != 429
+        }
+        _ => false,
+    }
+}
+
+/// The nonce only deduplicates for a few minutes, so a report whose last attempt may have
+/// gone through is looked for in the channel before it is sent again.
+async fn already_published(http: &Http, conf: &Config, pending: &PendingReport) -> bool {
+    let request = GetMessages::new().limit(50);
+    match conf.report_channel_id.messages(http, request).await {
+        Ok(messages) => messages
+            .iter()
+            .any(|message| message.author.bot && message.content == pending.content),
+        Err(e) => {
+            tracing::warn!("Error reading the report channel: {:?}", e);
+            false
+        }
+    }
+}
+
+/// Saves the statistics every `STAT_AUTOSAVE_SECS`, so a crash loses at most that much.
+pub fn stat_autosave(stat: Arc<Mutex<Stat>>, conf: Config) {
+    if conf.stat_autosave_secs == 0 {
+        return;
+    }
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(conf.stat_autosave_secs));
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            let mut stat_guard = stat.lock().await;
+            // Voice sessions are open at least until now, in case the bot stops
+            stat_guard.voice.touch(chrono::Utc::now());
+            save_stat(stat_guard, &conf).await;
         }
     });
 }
+
+/// Saves the statistics and releases the lock before the file is written, off the runtime.
+/// The backups rotate at most once a day.
+pub async fn save_stat(stat: MutexGuard<'_, Stat>, conf: &Config) {
+    write_stat(stat, conf, false).await;
+}
+
+/// Saves the statistics right after a report, the backups rotate to keep the state from
+/// before it.
+async fn save_stat_with_backup(stat: MutexGuard<'_, Stat>, conf: &Config) {
+    write_stat(stat, conf, true).await;
+}
+
+async fn write_stat(mut stat: MutexGuard<'_, Stat>, conf: &Config, rotate: bool) {
+    let now = chrono::Utc::now();
+    let rotate = rotate
+        || stat
+            .backed_up_at
+            .is_none_or(|backed_up_at| now - backed_up_at >= BACKUP_INTERVAL);
+    let bytes = match serde_json::to_vec(&*stat) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            tracing::error!("Error serializing statistics: {}", e);
+            return;
+        }
+    };
+    if rotate {
+        stat.backed_up_at = Some(now);
+    }
+    // Taken under the lock, so a higher generation is always a newer snapshot
+    let generation = SAVE_GENERATION.fetch_add(1, Ordering::SeqCst) + 1;
+    drop(stat);
+
+    let path = PathBuf::from(&conf.stat_file);
+    let backups = match rotate {
+        true => conf.stat_backups,
+        false => 0,
+    };
+    let written = tokio::task::spawn_blocking(move || {
+        let mut saved = SAVED_GENERATION.lock().unwrap_or_else(|e| e.into_inner());
+        // A newer snapshot got written first
+        if *saved > generation {
+            return Ok(());
+        }
+        *saved = generation;
+        save_with_backups(&path, &bytes, backups)
+    })
+    .await;
+    match written {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => tracing::error!("Error saving statistics: {}", e),
+        Err(e) => tracing::error!("Error saving statistics: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_long_report_is_split() {
+        let row = |index: usize| format!("{index:>3} | user{index:<20} | {:>6}", index * 7);
+        let table = (0..200).map(row).collect::<Vec<String>>().join("\n");
+        let report = PeriodReport {
+            start: "2025-03-03T00:00:00Z".parse().unwrap(),
+            end: chrono::Utc::now(),
+            table: Some(table.clone()),
+            summary: "Messages: 1400".to_string(),
+            channels: Some("#general | 1400".to_string()),
+            records: Some("New record: user1".to_string()),
+            broken_records: None,
+            emoji: None,
+            message_stat: MessageStat::default(),
+        };
+        let schedule = Schedule::parse("weekly=weekly mon 00:00").unwrap();
+        let parts = report_message(&Config::default(), &schedule, &report, true).unwrap();
+
+        assert!(parts.len() > 1);
+        for part in parts.iter() {
+            assert!(part.chars().count() <= MESSAGE_LIMIT);
+            assert_eq!(part.matches("```").count() % 2, 0);
+        }
+        let text = parts.join("\n");
+        assert!(text.contains("Messages: 1400"));
+        assert!((0..200).all(|index| text.contains(&row(index))));
+        assert!(parts.last().unwrap().ends_with("New record: user1"));
+
+        // Without its chart the report gets the table back
+        let parts = report_message(&Config::default(), &schedule, &report, false).unwrap();
+        assert_eq!(parts.len(), 1);
+        let pending = PendingReport {
+            nonce: "nonce".to_string(),
+            schedule: schedule.name.clone(),
+            content: parts[0].clone(),
+            image_file: Some("gone.png".to_string()),
+            left_out_table: Some(table),
+            sent_at: None,
+            rejected: false,
+        };
+        let restored = restore_table(&pending);
+        assert!(restored.len() > 1);
+        assert_eq!(restored[0].nonce, "nonce");
+        assert!(restored.iter().all(|part| part.image_file.is_none()));
+        let text = restored
+            .iter()
+            .map(|part| part.content.as_str())
+            .collect::<Vec<&str>>()
+            .join("\n");
+        assert!((0..200).all(|index| text.contains(&row(index))));
+    }
+}
@@ -0,0 +1,332 @@
+use chrono::{DateTime, Datelike, NaiveDateTime, NaiveTime, TimeZone, Timelike, Utc};
+use chrono_tz::Tz;
+
+const WEEKDAYS: [&str; 7] = ["sun", "mon", "tue", "wed", "thu", "fri", "sat"];
+const MONTHS: [&str; 12] = [
+    "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+];
+
+/// A named report schedule from `REPORT_SCHEDULES`, e.g. `weekly=weekly sun 00:00`.
+#[derive(Debug, Clone)]
+pub struct Schedule {
+    pub name: String,
+    pub spec: String,
+    pub cron: Cron,
+}
+
+impl Schedule {
+    /// Parses a `name=spec` line.
+    pub fn parse(line: &str) -> Result<Schedule, String> {
+        let (name, spec) = line
+            .split_once('=')
+            .ok_or_else(|| format!("expected `name=spec`, got `{line}`"))?;
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(format!("empty schedule name in `{line}`"));
+        }
+        Ok(Schedule {
+            name: name.to_string(),
+            spec: spec.trim().to_string(),
+            cron: Cron::parse_spec(spec)?,
+        })
+    }
+
+    /// The first boundary strictly after `after`, the cron fields are local time of `tz`.
+    /// A boundary in a DST gap moves to the end of the gap, a repeated local time fires once,
+    /// at its first occurrence.
+    pub fn next_after(&self, after: DateTime<Utc>, tz: Tz) -> DateTime<Utc> {
+        let mut local = after.with_timezone(&tz).naive_local();
+        loop {
+            let candidate = self
+                .cron
+                .next_after(local)
+                .unwrap_or_else(|| panic!("Schedule `{}` never fires", self.name));
+            let instant = (0..=180).find_map(|shift| {
+                let shifted = candidate + chrono::Duration::minutes(shift);
+                tz.from_local_datetime(&shifted).earliest()
+            });
+            match instant {
+                Some(instant) if instant.with_timezone(&Utc) > after => {
+                    return instant.with_timezone(&Utc)
+                }
+                _ => local = candidate,
+            }
+        }
+    }
+}
+
+/// A five field cron expression: minute, hour, day of month, month, day of week.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cron {
+    minutes: Vec<bool>,
+    hours: Vec<bool>,
+    days_of_month: Vec<bool>, // index 0 is unused
+    months: Vec<bool>,        // index 0 is unused
+    days_of_week: Vec<bool>,  // 0 is Sunday
+    any_day_of_month: bool,
+    any_day_of_week: bool,
+}
+
+impl Cron {
+    /// Accepts a preset or a raw cron expression:
+    /// `daily [HH:MM]`, `weekly <weekday> [HH:MM]`, `monthly <day> [HH:MM]`,
+    /// `cron <expr>` or just `<expr>`.
+    pub fn parse_spec(spec: &str) -> Result<Cron, String> {
+        let words = spec.split_whitespace().collect::<Vec<&str>>();
+        let time = |word: Option<&&str>| -> Result<(u32, u32), String> {
+            let word = match word {
+                Some(word) => word,
+                None => return Ok((0, 0)),
+            };
+            let time = NaiveTime::parse_from_str(word, "%H:%M")
+                .map_err(|_| format!("invalid time `{word}`, expected HH:MM"))?;
+            Ok((time.hour(), time.minute()))
+        };
+
+        let expression = match words.as_slice() {
+            ["daily", rest @ ..] if rest.len() <= 1 => {
+                let (hour, minute) = time(rest.first())?;
+                format!("{minute} {hour} * * *")
+            }
+            ["weekly", weekday, rest @ ..] if rest.len() <= 1 => {
+                let (hour, minute) = time(rest.first())?;
+                format!("{minute} {hour} * * {weekday}")
+            }
+            ["monthly", day, rest @ ..] if rest.len() <= 1 => {
+                let (hour, minute) = time(rest.first())?;
+                format!("{minute} {hour} {day} * *")
+            }
+            ["cron", rest @ ..] => rest.join(" "),
+            _ => spec.to_string(),
+        };
+        Cron::parse(&expression)
+    }
+
+    pub fn parse(expression: &str) -> Result<Cron, String> {
+        let fields = expression.split_whitespace().collect::<Vec<&str>>();
+        if fields.len() != 5 {
+            return Err(format!(
+                "cron expression needs 5 fields, got `{expression}`"
+            ));
+        }
+
+        let mut days_of_week = parse_field(fields[4], 0, 7, &WEEKDAYS)?;
+        // Both 0 and 7 are Sunday
+        if days_of_week[7] {
+            days_of_week[0] = true;
+        }
+        days_of_week.truncate(7);
+
+        Ok(Cron {
+            minutes: parse_field(fields[0], 0, 59, &[])?,
+            hours: parse_field(fields[1], 0, 23, &[])?,
+            days_of_month: parse_field(fields[2], 1, 31, &[])?,
+            months: parse_field(fields[3], 1, 12, &MONTHS)?,
+            days_of_week,
+            any_day_of_month: fields[2] == "*",
+            any_day_of_week: fields[4] == "*",
+        })
+    }
+
+    fn matches_day(&self, time: NaiveDateTime) -> bool {
+        if !self.months[time.month() as usize] {
+            return false;
+        }
+        let day_of_month = self.days_of_month[time.day() as usize];
+        let day_of_week = self.days_of_week[time.weekday().num_days_from_sunday() as usize];
+        // Like in cron, when both day fields are restricted either one may match
+        match (self.any_day_of_month, self.any_day_of_week) {
+            (true, true) => true,
+            (true, false) => day_of_week,
+            (false, true) => day_of_month,
+            (false, false) => day_of_month || day_of_week,
+        }
+    }
+
+    /// The first matching minute strictly after `after`, `None` if the expression never
+    /// matches (e.g. February 31).
+    pub fn next_after(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + chrono::Duration::minutes(1);
+        let mut date = start.date();
+
+        // Leap years repeat within 8 years, anything later never matches
+        for _ in 0..366 * 8 {
+            let day_start = date.and_hms_opt(0, 0, 0)?;
+            if self.matches_day(day_start) {
+                for hour in 0..24 {
+                    if !self.hours[hour as usize] {
+                        continue;
+                    }
+                    for minute in 0..60 {
+                        if !self.minutes[minute as usize] {
+                            continue;
+                        }
+                        let time = date.and_hms_opt(hour, minute, 0)?;
+                        if time >= start {
+                            return Some(time);
+                        }
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+}
+
+/// Parses one cron field into a lookup table indexed by value. Supports `*`, numbers,
+/// names, ranges `a-b`, steps `*/n`, `a-b/n`, `a/n` and comma separated lists.
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<Vec<bool>, String> {
+    let value = |text: &str| -> Result<u32, String> {
+        let lower = text.to_lowercase();
+        let name_index = names.iter().position(|name| lower.starts_with(name));
+        let value = match name_index {
+            Some(index) => index as u32 + min,
+            None => text
+                .parse::<u32>()
+                .map_err(|_| format!("invalid value `{text}` in `{field}`"))?,
+        };
+        match (min..=max).contains(&value) {
+            true => Ok(value),
+            false => Err(format!(
+                "value {value} out of range {min}-{max} in `{field}`"
+            )),
+        }
+    };
+
+    let mut result = vec![false; max as usize + 1];
+    for part in field.split(',') {
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => {
+                let step = step
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|step| *step > 0)
+                    .ok_or_else(|| format!("invalid step `{step}` in `{field}`"))?;
+                (range, step)
+            }
+            None => (part, 1),
+        };
+        let (from, to) = match range {
+            "*" => (min, max),
+            _ => match range.split_once('-') {
+                Some((from, to)) => (value(from)?, value(to)?),
+                None if step > 1 => (value(range)?, max),
+                None => (value(range)?, value(range)?),
+            },
+        };
+        if from > to {
+            return Err(format!("empty range `{range}` in `{field}`"));
+        }
+        for value in (from..=to).step_by(step as usize) {
+            result[value as usize] = true;
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn time(text: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(text, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    fn next(spec: &str, after: &str) -> String {
+        let cron = Cron::parse_spec(spec).unwrap();
+        let next = cron.next_after(time(after)).unwrap();
+        next.format("%Y-%m-%d %H:%M").to_string()
+    }
+
+    #[test]
+    fn test_presets() {
+        // 2025-03-05 is a Wednesday
+        assert_eq!(next("daily", "2025-03-05 12:00"), "2025-03-06 00:00");
+        assert_eq!(next("daily 18:30", "2025-03-05 12:00"), "2025-03-05 18:30");
+        assert_eq!(next("weekly sun", "2025-03-05 12:00"), "2025-03-09 00:00");
+        assert_eq!(
+            next("weekly Monday 09:00", "2025-03-05 12:00"),
+            "2025-03-10 09:00"
+        );
+        assert_eq!(next("monthly 1", "2025-03-05 12:00"), "2025-04-01 00:00");
+    }
+
+    #[test]
+    fn test_boundary_is_strictly_after() {
+        assert_eq!(next("weekly sun", "2025-03-09 00:00"), "2025-03-16 00:00");
+        assert_eq!(next("daily", "2025-03-05 23:59"), "2025-03-06 00:00");
+    }
+
+    #[test]
+    fn test_cron_expressions() {
+        assert_eq!(
+            next("cron */15 * * * *", "2025-03-05 12:07"),
+            "2025-03-05 12:15"
+        );
+        assert_eq!(
+            next("0 9-17/4 * * mon-fri", "2025-03-07 18:00"),
+            "2025-03-10 09:00"
+        );
+        assert_eq!(next("0 0 29 feb *", "2025-03-05 12:00"), "2028-02-29 00:00");
+        // Day of month or day of week, like cron
+        assert_eq!(next("0 0 13 * 5", "2025-03-05 12:00"), "2025-03-07 00:00");
+        assert_eq!(next("0 0 * * 7", "2025-03-05 12:00"), "2025-03-09 00:00");
+    }
+
+    #[test]
+    fn test_invalid_specs() {
+        assert!(Cron::parse_spec("weekly someday").is_err());
+        assert!(Cron::parse_spec("daily 25:00").is_err());
+        assert!(Cron::parse_spec("* * *").is_err());
+        assert!(Cron::parse_spec("0 0 32 * *").is_err());
+        assert!(Cron::parse_spec("*/0 * * * *").is_err());
+        assert!(Cron::parse_spec("0 0 31 feb *")
+            .unwrap()
+            .next_after(time("2025-01-01 00:00"))
+            .is_none());
+    }
+
+    #[test]
+    fn test_schedule_line() {
+        let schedule = Schedule::parse("weekly = weekly sun 00:00").unwrap();
+        assert_eq!(schedule.name, "weekly");
+        assert_eq!(schedule.spec, "weekly sun 00:00");
+        assert_eq!(schedule.cron, Cron::parse("0 0 * * 0").unwrap());
+        assert!(Schedule::parse("weekly sun").is_err());
+    }
+
+    #[test]
+    fn test_schedule_in_timezone() {
+        let schedule = Schedule::parse("weekly=weekly sun 00:00").unwrap();
+        let utc = |text: &str| text.parse::<DateTime<Utc>>().unwrap();
+        let moscow = "Europe/Moscow".parse::<Tz>().unwrap();
+        let next = schedule.next_after(utc("2025-03-05T12:00:00Z"), moscow);
+        assert_eq!(next, utc("2025-03-08T21:00:00Z"));
+    }
+
+    #[test]
+    fn test_schedule_across_dst() {
+        let berlin = "Europe/Berlin".parse::<Tz>().unwrap();
+        let utc = |text: &str| text.parse::<DateTime<Utc>>().unwrap();
+
+        // Clocks go from 02:00 to 03:00 on 2025-03-30, 02:30 doesn't exist
+        let gap = Schedule::parse("gap=daily 02:30").unwrap();
+        let next = gap.next_after(utc("2025-03-29T12:00:00Z"), berlin);
+        assert_eq!(next, utc("2025-03-30T01:00:00Z"));
+
+        // Clocks go from 03:00 back to 02:00 on 2025-10-26, 02:30 happens twice
+        let repeated = Schedule::parse("repeated=daily 02:30").unwrap();
+        let first = repeated.next_after(utc("2025-10-25T12:00:00Z"), berlin);
+        assert_eq!(first, utc("2025-10-26T00:30:00Z"));
+        let second = repeated.next_after(first, berlin);
+        assert_eq!(second, utc("2025-10-27T01:30:00Z"));
+
+        // Local midnight stays midnight after the switch
+        let weekly = Schedule::parse("weekly=weekly sun 00:00").unwrap();
+        let next = weekly.next_after(utc("2025-03-29T12:00:00Z"), berlin);
+        assert_eq!(next, utc("2025-03-29T23:00:00Z"));
+        let next = weekly.next_after(next, berlin);
+        assert_eq!(next, utc("2025-04-05T22:00:00Z"));
+    }
+}
@@ -1,56 +1,614 @@
 use super::*;
 
-use chrono::{Datelike, Timelike};
-use serde::{Deserialize, Serialize};
-use serenity::all::{CacheHttp, ChannelId, Message, UserId};
-use std::{collections::HashMap, vec};
+use chrono::{DateTime, Datelike, NaiveDateTime, Timelike, Utc};
+use chrono_tz::Tz;
+use serde::{Deserialize, Deserializer, Serialize};
+use serenity::all::{
+    CacheHttp, ChannelId, GuildId, Message, MessageId, MessageUpdateEvent, Reaction, StickerId,
+    UserId,
+};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::hash::Hash;
+use std::str::FromStr;
 
-use std::fs::File;
-use std::io::{BufReader, BufWriter};
 use std::error::Error;
+use std::path::Path;
 
+/// Statistics of every report schedule, keyed by the schedule name.
 #[derive(Debug, Default, Serialize, Deserialize)]
 pub struct Stat {
-    pub collection_start: chrono::NaiveDateTime,
-    pub collect_until: chrono::NaiveDateTime,
+    pub periods: HashMap<String, StatPeriod>,
+    #[serde(default)]
+    pub pending_reports: Vec<PendingReport>, // closed, not yet published
+    #[serde(default)]
+    pub opted_out: BTreeSet<UserId>, // users who asked not to be counted
+    #[serde(default)]
+    pub achievements: Achievements,
+    #[serde(default)]
+    pub hall_of_fame: HallOfFame,
+    #[serde(default)]
+    pub coverage: Coverage,
+    #[serde(default)]
+    pub voice: VoicePresence,
+    #[serde(default)]
+    pub karma: Karma,
+    #[serde(default)]
+    pub recent_messages: RecentMessages,
+    #[serde(skip)]
+    pub purged_authors: HashSet<UserId>, // excluded authors already purged in this run
+    #[serde(skip)]
+    pub backed_up_at: Option<DateTime<Utc>>, // last backup rotation, the first save rotates
+}
+
+/// Authors of the latest messages, for the events which only carry a message ID. Kept in
+/// the statistics file, oldest first, so deletions and reactions are attributed after a
+/// restart too.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(from = "Vec<(MessageId, UserId)>", into = "Vec<(MessageId, UserId)>")]
+pub struct RecentMessages {
+    authors: HashMap<MessageId, UserId>,
+    order: VecDeque<MessageId>,
+}
+
+impl RecentMessages {
+    const CAPACITY: usize = 10_000;
+
+    pub fn remember(&mut self, message_id: MessageId, author_id: UserId) {
+        if self.authors.insert(message_id, author_id).is_none() {
+            self.order.push_back(message_id);
+        }
+        while self.order.len() > Self::CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.authors.remove(&oldest);
+            }
+        }
+    }
+
+    pub fn author(&self, message_id: MessageId) -> Option<UserId> {
+        self.authors.get(&message_id).copied()
+    }
+
+    pub fn forget(&mut self, message_id: MessageId) -> Option<UserId> {
+        self.authors.remove(&message_id)
+    }
+}
+
+impl From<Vec<(MessageId, UserId)>> for RecentMessages {
+    fn from(messages: Vec<(MessageId, UserId)>) -> RecentMessages {
+        let mut recent = RecentMessages::default();
+        for (message_id, author_id) in messages {
+            recent.remember(message_id, author_id);
+        }
+        recent
+    }
+}
+
+impl From<RecentMessages> for Vec<(MessageId, UserId)> {
+    fn from(recent: RecentMessages) -> Vec<(MessageId, UserId)> {
+        recent
+            .order
+            .iter()
+            .filter_map(|id| recent.authors.get(id).map(|author_id| (*id, *author_id)))
+            .collect()
+    }
+}
+
+/// A column of the report table, configured by `REPORT_COLUMNS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportColumn {
+    Messages,
+    Series,
+    Files,
+    Chars,
+    Words,
+    Graphemes,
+    Links,
+    RepliesGiven,
+    RepliesReceived,
+    ReactionsGiven,
+    ReactionsReceived,
+    Edits,
+    Deletions,
+    Burst,
+    Voice,
+    VoiceSession,
+    Karma,
+}
+
+impl ReportColumn {
+    pub const ALL: [ReportColumn; 17] = [
+        ReportColumn::Messages,
+        ReportColumn::Series,
+        ReportColumn::Files,
+        ReportColumn::Chars,
+        ReportColumn::Words,
+        ReportColumn::Graphemes,
+        ReportColumn::Links,
+        ReportColumn::RepliesGiven,
+        ReportColumn::RepliesReceived,
+        ReportColumn::ReactionsGiven,
+        ReportColumn::ReactionsReceived,
+        ReportColumn::Edits,
+        ReportColumn::Deletions,
+        ReportColumn::Burst,
+        ReportColumn::Voice,
+        ReportColumn::VoiceSession,
+        ReportColumn::Karma,
+    ];
+
+    /// Name in `REPORT_COLUMNS` and command options.
+    pub fn name(&self) -> &'static str {
+        match self {
+            ReportColumn::Messages => "messages",
+            ReportColumn::Series => "series",
+            ReportColumn::Files => "files",
+            ReportColumn::Chars => "chars",
+            ReportColumn::Words => "words",
+            ReportColumn::Graphemes => "graphemes",
+            ReportColumn::Links => "links",
+            ReportColumn::RepliesGiven => "replies_given",
+            ReportColumn::RepliesReceived => "replies_received",
+            ReportColumn::ReactionsGiven => "reactions_given",
+            ReportColumn::ReactionsReceived => "reactions_received",
+            ReportColumn::Edits => "edits",
+            ReportColumn::Deletions => "deletions",
+            ReportColumn::Burst => "burst",
+            ReportColumn::Voice => "voice",
+            ReportColumn::VoiceSession => "voice_session",
+            ReportColumn::Karma => "karma",
+        }
+    }
+
+    pub fn header(&self) -> &'static str {
+        match self {
+            ReportColumn::Messages => "Messages",
+            ReportColumn::Series => "Max series",
+            ReportColumn::Files => "Files",
+            ReportColumn::Chars => "Chars",
+            ReportColumn::Words => "Words",
+            ReportColumn::Graphemes => "Symbols",
+            ReportColumn::Links => "Links",
+            ReportColumn::RepliesGiven => "Replies",
+            ReportColumn::RepliesReceived => "Replied to",
+            ReportColumn::ReactionsGiven => "Reactions",
+            ReportColumn::ReactionsReceived => "Reacted to",
+            ReportColumn::Edits => "Edits",
+            ReportColumn::Deletions => "Deleted",
+            ReportColumn::Burst => "Max burst",
+            ReportColumn::Voice => "Voice min",
+            ReportColumn::VoiceSession => "Max call min",
+            ReportColumn::Karma => "Karma",
+        }
+    }
+}
+
+impl FromStr for ReportColumn {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let name = s.trim();
+        ReportColumn::ALL
+            .into_iter()
+            .find(|column| column.name() == name)
+            .ok_or_else(|| format!("unknown report column `{name}`"))
+    }
+}
+
+/// How series and bursts are counted, from the `STREAK_*` variables.
+#[derive(Debug, Clone, Default)]
+pub struct StreakRules {
+    pub gap: Option<chrono::Duration>, // silence that breaks a series, none to never break
+    pub burst_window: Option<chrono::Duration>, // window of the burst metric, none to skip it
+    pub report_top: usize,             // longest series listed in the report
+}
+
+fn add_count(map: &mut HashMap<UserId, usize>, user_id: UserId, count: usize) {
+    *map.entry(user_id).or_insert(0) += count;
+}
+
+fn sub_count(map: &mut HashMap<UserId, usize>, user_id: UserId, count: usize) {
+    if let Some(value) = map.get_mut(&user_id) {
+        *value = value.saturating_sub(count);
+    }
+}
+
+fn merge_keyed_counts<K: Clone + Eq + Hash>(
+    map: &mut HashMap<K, HashMap<UserId, usize>>,
+    other: &HashMap<K, HashMap<UserId, usize>>,
+) {
+    for (key, users) in other {
+        let entry = map.entry(key.clone()).or_default();
+        for (user_id, count) in users {
+            add_count(entry, *user_id, *count);
+        }
+    }
+}
+
+fn sub_keyed_counts<K: Eq + Hash>(
+    map: &mut HashMap<K, HashMap<UserId, usize>>,
+    other: &HashMap<K, HashMap<UserId, usize>>,
+) {
+    for (key, users) in other {
+        if let Some(entry) = map.get_mut(key) {
+            for (user_id, count) in users {
+                sub_count(entry, *user_id, *count);
+            }
+        }
+    }
+}
+
+/// One reporting period of a schedule with its own accumulator.
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+pub struct StatPeriod {
+    #[serde(default)]
+    pub spec: String, // schedule spec the boundaries were computed with
+    #[serde(deserialize_with = "deserialize_instant")]
+    pub collection_start: DateTime<Utc>,
+    #[serde(deserialize_with = "deserialize_instant")]
+    pub collect_until: DateTime<Utc>,
     pub last_collection_duration: chrono::Duration,
     pub message_stat: MessageStat,
 }
 
+/// A closed period, ready to be published.
+#[derive(Debug, Clone)]
+pub struct PeriodReport {
+    pub start: DateTime<Utc>,
+    pub end: DateTime<Utc>,
+    pub table: Option<String>,
+    pub summary: String,
+    pub channels: Option<String>,
+    pub records: Option<String>,
+    pub broken_records: Option<String>,
+    pub emoji: Option<String>,
+    pub message_stat: MessageStat,
+}
+
+/// Instants used to be stored as naive UTC date times, both forms are accepted.
+fn deserialize_instant<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<DateTime<Utc>, D::Error> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Instant {
+        Aware(DateTime<Utc>),
+        Naive(NaiveDateTime),
+    }
+
+    Ok(match Instant::deserialize(deserializer)? {
+        Instant::Aware(instant) => instant,
+        Instant::Naive(naive) => naive.and_utc(),
+    })
+}
+
+/// `stat.json` as written before the schedules, a single weekly period.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StatFile {
+    Current(Box<Stat>),
+    Legacy(Box<StatPeriod>),
+}
+
 impl Stat {
-    pub fn init_collection(&mut self) {
-        self.collection_start = chrono::Utc::now().naive_utc();
-        tracing::info!("Now time is     : UTC {:?}", self.collection_start);
-        self.collect_until = next_update_time();
+    /// Creates the periods of new schedules and drops the ones no longer configured.
+    /// A loaded period keeps its boundary, even an overdue one, unless the spec changed.
+    pub fn init_collection(&mut self, schedules: &[Schedule], tz: Tz) {
+        let now = Utc::now();
+        tracing::info!("Now time is     : {}", now.with_timezone(&tz));
+
+        self.periods.retain(|name, _| {
+            let keep = schedules.iter().any(|schedule| &schedule.name == name);
+            if !keep {
+                tracing::warn!("Dropping statistics of removed schedule {}", name);
+            }
+            keep
+        });
+        for schedule in schedules {
+            let period = self
+                .periods
+                .entry(schedule.name.clone())
+                .or_insert_with(|| StatPeriod {
+                    collection_start: now,
+                    ..StatPeriod::default()
+                });
+            if period.spec != schedule.spec {
+                if !period.spec.is_empty() || period.collect_until == DateTime::<Utc>::default() {
+                    period.collect_until = schedule.next_after(period.collection_start, tz);
+                }
+                period.spec = schedule.spec.clone();
+            }
+            tracing::info!(
+                "Next {} ({}) update time: {}",
+                schedule.name,
+                schedule.spec,
+                period.collect_until.with_timezone(&tz)
+            );
+        }
+    }
+
+    pub fn record_message(&mut self, msg: &Message, conf: &Config) {
+        if self.opted_out.contains(&msg.author.id) {
+            return;
+        }
+        let tz = conf.guild_timezone(msg.guild_id);
+        for period in self.periods.values_mut() {
+            period
+                .message_stat
+                .record_message(msg, tz, &conf.streak_rules);
+        }
+        self.achievements.record_message(msg, tz, conf);
+        if let Some(thanked) = thanked_user(msg, &conf.karma_rules) {
+            let at = timestamp_to_utc(&msg.timestamp);
+            self.grant_karma(msg.author.id, thanked, msg.channel_id, at, conf);
+        }
+        self.coverage
+            .extend_session(timestamp_to_utc(&msg.timestamp));
+        let main = conf.report_schedules.first();
+        if let Some(period) = main.and_then(|main| self.periods.get(&main.name)) {
+            self.hall_of_fame
+                .record_message(msg, &self.achievements.lifetime, period);
+        }
+        self.recent_messages.remember(msg.id, msg.author.id);
+    }
+
+    /// Remembers the channel of a thread or forum post, the channel report counts it there.
+    pub fn record_thread(&mut self, thread_id: ChannelId, parent_id: ChannelId) {
+        for period in self.periods.values_mut() {
+            period
+                .message_stat
+                .thread_parents
+                .insert(thread_id, parent_id);
+        }
+    }
+
+    /// `karma` tells whether the author may be thanked with the reaction, bots can't.
+    pub fn record_reaction(
+        &mut self,
+        reaction: &Reaction,
+        added: bool,
+        karma: bool,
+        conf: &Config,
+    ) {
+        let user_id = match reaction.user_id {
+            Some(user_id) => user_id,
+            None => return,
+        };
+        if self.opted_out.contains(&user_id) {
+            return;
+        }
+        // The emoji counts even on messages whose author is unknown
+        let emoji = reaction_emoji(&reaction.emoji);
+        for period in self.periods.values_mut() {
+            period
+                .message_stat
+                .record_emoji_reaction(user_id, reaction.channel_id, &emoji, added);
+        }
+        // Only an added reaction carries the author, it is remembered so that taking the
+        // reaction back is attributed the same way. A removal nobody can attribute is ignored.
+        let author_id = match (added, reaction.message_author_id) {
+            (true, Some(author_id)) => {
+                self.recent_messages
+                    .remember(reaction.message_id, author_id);
+                author_id
+            }
+            _ => match self.recent_messages.author(reaction.message_id) {
+                Some(author_id) => author_id,
+                None => return,
+            },
+        };
+        if self.opted_out.contains(&author_id) {
+            return;
+        }
+        // Taking the reaction back keeps the karma, the cooldown stops add and remove loops
+        if added && karma && conf.karma_rules.is_thanks_emoji(&emoji) {
+            self.grant_karma(user_id, author_id, reaction.channel_id, Utc::now(), conf);
+        }
+        for period in self.periods.values_mut() {
+            period
+                .message_stat
+                .record_reaction(user_id, author_id, reaction.channel_id, added);
+        }
+        self.achievements
+            .record_reaction(user_id, author_id, reaction.channel_id, added, conf);
+    }
+
+    pub fn record_edit(&mut self, event: &MessageUpdateEvent, conf: &Config) {
+        // Embeds being resolved also update a message, only edits have the timestamp
+        if event.edited_timestamp.is_none() {
+            return;
+        }
+        let author_id = event
+            .author
+            .as_ref()
+            .map(|author| author.id)
+            .or_else(|| self.recent_messages.author(event.id));
+        if let Some(author_id) = author_id.filter(|id| !self.opted_out.contains(id)) {
+            for period in self.periods.values_mut() {
+                period.message_stat.record_edit(author_id, event.channel_id);
+            }
+            self.achievements
+                .record_edit(author_id, event.channel_id, conf);
+        }
+    }
+
+    /// Deletions are only attributed for messages seen since the start, Discord doesn't
+    /// tell the author.
+    pub fn record_deletion(&mut self, channel_id: ChannelId, message_id: MessageId, conf: &Config) {
+        if let Some(author_id) = self.recent_messages.forget(message_id) {
+            for period in self.periods.values_mut() {
+                period.message_stat.record_deletion(author_id, channel_id);
+            }
+            self.achievements
+                .record_deletion(author_id, channel_id, conf);
+        }
+    }
+
+    fn grant_karma(
+        &mut self,
+        giver: UserId,
+        receiver: UserId,
+        channel_id: ChannelId,
+        at: DateTime<Utc>,
+        conf: &Config,
+    ) {
+        if self.opted_out.contains(&receiver)
+            || !self.karma.grant(giver, receiver, at, &conf.karma_rules)
+        {
+            return;
+        }
+        for period in self.periods.values_mut() {
+            period.message_stat.record_karma(receiver, channel_id);
+        }
+    }
+
+    /// The user joined, moved to or left (`None`) a voice channel.
+    pub fn record_voice(
+        &mut self,
+        user_id: UserId,
+        channel_id: Option<ChannelId>,
+        now: DateTime<Utc>,
+    ) {
+        let channel_id = channel_id.filter(|_| !self.opted_out.contains(&user_id));
+        self.voice
+            .update(&mut self.periods, user_id, channel_id, now);
     }
 
+    pub fn sync_voice(
+        &mut self,
+        channels: &HashSet<ChannelId>,
+        mut present: HashMap<UserId, ChannelId>,
+        now: DateTime<Utc>,
+    ) {
+        present.retain(|user_id, _| !self.opted_out.contains(user_id));
+        self.voice.sync(&mut self.periods, channels, &present, now);
+    }
+
+    /// Removes the user from every running period, the achievements and the records.
+    /// Returns whether there was anything.
+    pub fn purge_user(&mut self, user_id: UserId) -> bool {
+        let mut purged = self.achievements.purge_user(user_id);
+        purged |= self.voice.purge_user(user_id);
+        purged |= self.karma.purge_user(user_id);
+        purged |= self.hall_of_fame.purge_user(user_id);
+        for period in self.periods.values_mut() {
+            purged |= period.message_stat.purge_user(user_id);
+        }
+        purged
+    }
+
+    /// Removes what is known to come from the channel from every running period.
+    pub fn purge_channel(&mut self, channel_id: ChannelId) -> bool {
+        let mut purged = self.achievements.purge_channel(channel_id);
+        purged |= self.voice.purge_channel(channel_id);
+        purged |= self.hall_of_fame.purge_channel(channel_id);
+        for period in self.periods.values_mut() {
+            purged |= period.message_stat.purge_channel(channel_id);
+        }
+        purged
+    }
+
+    /// Closes the current period of the schedule, returns its report and starts the next one.
+    /// The report is compared with `previous`, the stat of the period before.
     pub async fn collect_report(
         &mut self,
+        schedule: &Schedule,
+        previous: Option<&MessageStat>,
         cache_http: impl CacheHttp,
         conf: &Config,
-    ) -> Option<String> {
-        let next_time = next_update_time();
-        self.last_collection_duration = self.collect_until - self.collection_start;
-        self.collection_start = self.collect_until;
-        self.collect_until = next_time;
-        self.message_stat.flush_records();
-        self.message_stat
-            .format_results_table(cache_http, conf)
-            .await
-    }
-
-    pub fn save_to_file(&self, file_path: &str) -> Result<(), Box<dyn Error>> {
-        let file = File::create(file_path)?;
-        let writer = BufWriter::new(file);
-        serde_json::to_writer(writer, self)?;
-        Ok(())
-    }
-
-    pub fn load_from_file(file_path: &str) -> Result<Stat, Box<dyn Error>> {
-        let file = File::open(file_path)?;
-        let reader = BufReader::new(file);
-        let stat = serde_json::from_reader(reader)?;
-        Ok(stat)
+    ) -> PeriodReport {
+        let tz = conf.report_timezone();
+        let now = Utc::now();
+        let period = self.periods.entry(schedule.name.clone()).or_default();
+        let (start, end) = (period.collection_start, period.collect_until);
+
+        let (next_start, next_time, skipped) = roll_forward(schedule, end, now, tz);
+        if skipped > 0 {
+            tracing::warn!(
+                "{} skipped {} periods while offline",
+                schedule.name,
+                skipped
+            );
+        }
+        tracing::info!(
+            "Next {} update time: {}",
+            schedule.name,
+            next_time.with_timezone(&tz)
+        );
+        period.last_collection_duration = end - start;
+        self.voice
+            .close_period(&mut period.message_stat, start, next_start);
+        period.collection_start = next_start;
+        period.collect_until = next_time;
+        period.message_stat.flush_records();
+        let is_main = conf.report_schedules.first().map(|main| &main.name) == Some(&schedule.name);
+        if is_main {
+            self.achievements.close_period(&period.message_stat, conf);
+        }
+        let names = DiscordNames { cache_http, conf };
+        let stat = &period.message_stat;
+        let table = format_report_table(stat, previous, &names, &conf.report_layout).await;
+        let summary = format_report_summary(stat, previous, &names).await;
+        let channels = format_channel_leaderboard(stat, &names, conf.report_channels_top).await;
+        let records = format_streak_records(
+            stat,
+            &names,
+            conf.report_timezone(),
+            conf.streak_rules.report_top,
+        )
+        .await;
+        // Records are kept for the main schedule only
+        let broken_records = match is_main {
+            true => format_broken_records(&self.hall_of_fame, start, end, &names, tz).await,
+            false => None,
+        };
+        let emoji = format_top_emoji(stat, conf.report_emoji_top, None);
+        if let Some(table) = table.as_ref() {
+            table.lines().for_each(|row| tracing::info!("{}", row));
+        }
+        let message_stat = std::mem::take(&mut period.message_stat);
+        self.voice.open_period(&mut period.message_stat, next_start);
+        PeriodReport {
+            start,
+            end,
+            table,
+            summary,
+            channels,
+            records,
+            broken_records,
+            emoji,
+            message_stat,
+        }
+    }
+
+    /// Loads the statistics, falling back to the newest valid backup. A legacy single period
+    /// file becomes the period of `legacy_schedule`. No file at all is a fresh start.
+    pub fn load_from_file(
+        file_path: &str,
+        backups: usize,
+        legacy_schedule: &str,
+    ) -> Result<Stat, Box<dyn Error>> {
+        let parse = |bytes: &[u8]| {
+            serde_json::from_slice::<StatFile>(bytes).map(|file| match file {
+                StatFile::Current(stat) => *stat,
+                StatFile::Legacy(period) => {
+                    tracing::info!(
+                        "Migrating legacy statistics to schedule {}",
+                        legacy_schedule
+                    );
+                    Stat {
+                        periods: HashMap::from([(legacy_schedule.to_string(), *period)]),
+                        ..Stat::default()
+                    }
+                }
+            })
+        };
+        match load_with_fallback(Path::new(file_path), backups, parse)? {
+            Some(stat) => Ok(stat),
+            None => {
+                tracing::info!("No statistics in {}, starting fresh", file_path);
+                Ok(Stat::default())
+            }
+        }
     }
 }
 
@@ -60,89 +618,703 @@ pub struct MessageStat {
     pub personal_record: HashMap<UserId, MessageStreakPersonalRecord>, // per user record with chanel in which achieved
     pub messages_count: HashMap<UserId, usize>,
     pub attachments_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub chars_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub words_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub graphemes_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub links_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub replies_given: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub replies_received: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub reactions_given: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub reactions_received: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub edits_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub deletions_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub activity: [[usize; 24]; 7], // messages by local weekday (from Monday) and hour
+    #[serde(default)]
+    pub channel_messages: HashMap<ChannelId, HashMap<UserId, usize>>,
+    #[serde(default)]
+    pub channel_files: HashMap<ChannelId, HashMap<UserId, usize>>,
+    #[serde(default)]
+    pub channel_hours: HashMap<ChannelId, [usize; 24]>, // messages by local hour
+    #[serde(default)]
+    pub thread_parents: HashMap<ChannelId, ChannelId>, // threads and forum posts
+    #[serde(default)]
+    pub voice_seconds: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub voice_longest: HashMap<UserId, usize>, // seconds of the longest session
+    #[serde(default)]
+    pub channel_voice: HashMap<ChannelId, HashMap<UserId, usize>>, // seconds
+    #[serde(default)]
+    pub voice_peak: usize, // most users in voice at once
+    #[serde(default)]
+    pub voice_peak_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub emoji_used: HashMap<String, HashMap<UserId, usize>>, // in messages, by emoji text
+    #[serde(default)]
+    pub emoji_reacted: HashMap<String, HashMap<UserId, usize>>, // reactions added
+    #[serde(default)]
+    pub stickers_used: HashMap<StickerId, HashMap<UserId, usize>>,
+    #[serde(default)]
+    pub sticker_names: HashMap<StickerId, String>,
+    #[serde(default)]
+    pub karma_received: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub channel_counters: HashMap<ChannelId, ChannelCounters>,
+    #[serde(default)]
+    pub max_burst: HashMap<UserId, usize>, // most messages within the burst window
+    #[serde(default)]
+    pub burst_channels: HashMap<UserId, HashSet<ChannelId>>, // where the max burst was
+    #[serde(skip)]
+    pub burst_times: HashMap<UserId, VecDeque<(DateTime<Utc>, ChannelId)>>, // inside the window
+}
+
+/// A channel's share of the counters [`MessageStat`] doesn't otherwise keep per channel, so
+/// [`MessageStat::purge_channel`] can take all of it back.
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct ChannelCounters {
+    #[serde(default)]
+    pub chars_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub words_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub graphemes_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub links_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub replies_given: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub replies_received: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub reactions_given: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub reactions_received: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub edits_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub deletions_count: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub karma_received: HashMap<UserId, usize>,
+    #[serde(default)]
+    pub emoji_used: HashMap<String, HashMap<UserId, usize>>,
+    #[serde(default)]
+    pub emoji_reacted: HashMap<String, HashMap<UserId, usize>>,
+    #[serde(default)]
+    pub stickers_used: HashMap<StickerId, HashMap<UserId, usize>>,
+    #[serde(default)]
+    pub activity: [[usize; 24]; 7],
+}
+
+impl ChannelCounters {
+    /// The per user counters, in the order of [`MessageStat::user_counters`].
+    fn user_counters(&mut self) -> [&mut HashMap<UserId, usize>; 11] {
+        [
+            &mut self.chars_count,
+            &mut self.words_count,
+            &mut self.graphemes_count,
+            &mut self.links_count,
+            &mut self.replies_given,
+            &mut self.replies_received,
+            &mut self.reactions_given,
+            &mut self.reactions_received,
+            &mut self.edits_count,
+            &mut self.deletions_count,
+            &mut self.karma_received,
+        ]
+    }
+
+    fn merge(&mut self, other: &ChannelCounters) {
+        let other_counters = [
+            &other.chars_count,
+            &other.words_count,
+            &other.graphemes_count,
+            &other.links_count,
+            &other.replies_given,
+            &other.replies_received,
+            &other.reactions_given,
+            &other.reactions_received,
+            &other.edits_count,
+            &other.deletions_count,
+            &other.karma_received,
+        ];
+        for (map, other_map) in self.user_counters().into_iter().zip(other_counters) {
+            for (user_id, count) in other_map {
+                add_count(map, *user_id, *count);
+            }
+        }
+        merge_keyed_counts(&mut self.emoji_used, &other.emoji_used);
+        merge_keyed_counts(&mut self.emoji_reacted, &other.emoji_reacted);
+        merge_keyed_counts(&mut self.stickers_used, &other.stickers_used);
+        for (day, other_day) in self.activity.iter_mut().zip(other.activity.iter()) {
+            for (hour, count) in day.iter_mut().zip(other_day.iter()) {
+                *hour += count;
+            }
+        }
+    }
+
+    fn purge_user(&mut self, user_id: UserId) {
+        for map in self.user_counters() {
+            map.remove(&user_id);
+        }
+        for users in self
+            .emoji_used
+            .values_mut()
+            .chain(self.emoji_reacted.values_mut())
+            .chain(self.stickers_used.values_mut())
+        {
+            users.remove(&user_id);
+        }
+    }
+}
+
+/// A channel's share of the period, see [`MessageStat::channel_activity`].
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ChannelActivity {
+    pub channel_id: ChannelId,
+    pub messages: usize,
+    pub posters: usize,
+    pub files: usize,
+    pub hours: [usize; 24], // messages by local hour
+}
+
+impl ChannelActivity {
+    /// The local hour with the most messages, the earliest of ties.
+    pub fn peak_hour(&self) -> Option<usize> {
+        let max = *self.hours.iter().max()?;
+        (max > 0).then(|| self.hours.iter().position(|count| *count == max))?
+    }
 }
 
 impl MessageStat {
-    pub async fn format_results_table(
-        &self,
-        cache_http: impl CacheHttp,
-        conf: &Config,
-    ) -> Option<String> {
-        let user_header = "User".to_string();
-        let messages_header = "Messages".to_string();
-        let series_header = "Max series".to_string();
-        let attachments_header = "Files".to_string();
-
-        let mut user_name_list = vec![user_header.clone()];
-        let mut channel_name_list = vec![];
-        let mut rows = vec![];
-
-        let mut max_symbols_in_user_name = 0;
-        let mut max_symbols_in_series_channel_name = 0;
-
-        let max_symbols_in_messages = match count_symbols(&messages_header) {
-            0..4 => 4,
-            _ => count_symbols(&messages_header),
-        };
-        let max_symbols_in_attachments = match count_symbols(&attachments_header) {
-            0..4 => 4,
-            _ => count_symbols(&attachments_header),
+    /// Value of a numeric column, the series column gives the record length and the voice
+    /// columns are in minutes.
+    pub fn value(&self, column: ReportColumn, user_id: &UserId) -> usize {
+        let map = match column {
+            ReportColumn::Messages => &self.messages_count,
+            ReportColumn::Series => {
+                return self
+                    .personal_record
+                    .get(user_id)
+                    .map(|record| record.counter)
+                    .unwrap_or(0)
+            }
+            ReportColumn::Files => &self.attachments_count,
+            ReportColumn::Chars => &self.chars_count,
+            ReportColumn::Words => &self.words_count,
+            ReportColumn::Graphemes => &self.graphemes_count,
+            ReportColumn::Links => &self.links_count,
+            ReportColumn::RepliesGiven => &self.replies_given,
+            ReportColumn::RepliesReceived => &self.replies_received,
+            ReportColumn::ReactionsGiven => &self.reactions_given,
+            ReportColumn::ReactionsReceived => &self.reactions_received,
+            ReportColumn::Edits => &self.edits_count,
+            ReportColumn::Deletions => &self.deletions_count,
+            ReportColumn::Burst => &self.max_burst,
+            ReportColumn::Karma => &self.karma_received,
+            ReportColumn::Voice => return self.voice_seconds.get(user_id).unwrap_or(&0) / 60,
+            ReportColumn::VoiceSession => {
+                return self.voice_longest.get(user_id).unwrap_or(&0) / 60
+            }
         };
+        map.get(user_id).copied().unwrap_or(0)
+    }
 
-        for (user_id, personal_record) in self.personal_record.iter() {
-            let user_name = get_user_name(user_id, &cache_http, conf).await;
-            user_name_list.push(user_name);
+    /// Everyone with any activity in the period.
+    pub fn users(&self) -> BTreeSet<UserId> {
+        let mut users = self
+            .personal_record
+            .keys()
+            .copied()
+            .collect::<BTreeSet<UserId>>();
+        for map in [
+            &self.messages_count,
+            &self.attachments_count,
+            &self.chars_count,
+            &self.words_count,
+            &self.graphemes_count,
+            &self.links_count,
+            &self.replies_given,
+            &self.replies_received,
+            &self.reactions_given,
+            &self.reactions_received,
+            &self.edits_count,
+            &self.deletions_count,
+            &self.max_burst,
+            &self.voice_seconds,
+            &self.karma_received,
+        ] {
+            users.extend(map.keys());
+        }
+        users
+    }
+
+    /// Channels with any recorded activity.
+    pub fn channels(&self) -> BTreeSet<ChannelId> {
+        let mut channels = self
+            .channel_messages
+            .keys()
+            .chain(self.channel_voice.keys())
+            .chain(self.current_by_channel.keys())
+            .copied()
+            .collect::<BTreeSet<ChannelId>>();
+        channels.extend(
+            self.personal_record
+                .values()
+                .map(|record| record.channel_id),
+        );
+        channels
+    }
 
-            let channel_name = get_channel_name(&personal_record.channel_id, &cache_http).await;
-            channel_name_list.push(channel_name);
+    /// Forgets the user everywhere except the activity heatmaps, which have no authors.
+    pub fn purge_user(&mut self, user_id: UserId) -> bool {
+        let mut purged = self.personal_record.remove(&user_id).is_some();
+        for map in [
+            &mut self.messages_count,
+            &mut self.attachments_count,
+            &mut self.chars_count,
+            &mut self.words_count,
+            &mut self.graphemes_count,
+            &mut self.links_count,
+            &mut self.replies_given,
+            &mut self.replies_received,
+            &mut self.reactions_given,
+            &mut self.reactions_received,
+            &mut self.edits_count,
+            &mut self.deletions_count,
+            &mut self.max_burst,
+            &mut self.voice_seconds,
+            &mut self.voice_longest,
+            &mut self.karma_received,
+        ] {
+            purged |= map.remove(&user_id).is_some();
         }
+        self.burst_times.remove(&user_id);
+        self.burst_channels.remove(&user_id);
+        for counters in self.channel_counters.values_mut() {
+            counters.purge_user(user_id);
+        }
+        for users in self
+            .channel_messages
+            .values_mut()
+            .chain(self.channel_files.values_mut())
+            .chain(self.channel_voice.values_mut())
+            .chain(self.emoji_used.values_mut())
+            .chain(self.emoji_reacted.values_mut())
+            .chain(self.stickers_used.values_mut())
+        {
+            purged |= users.remove(&user_id).is_some();
+        }
+        let streaks = self.current_by_channel.len();
+        self.current_by_channel
+            .retain(|_, streak| streak.user_id != user_id);
+        purged || self.current_by_channel.len() != streaks
+    }
 
-        for user_name in user_name_list {
-            // println!("{:>2} | {user_name}", count_symbols(&user_name));
-            let symbols = count_symbols(&user_name);
-            if symbols > max_symbols_in_user_name {
-                max_symbols_in_user_name = symbols;
+    /// Takes back everything counted in the channel and the streak records set in it. A max
+    /// burst that took place partly there can't be split, it is dropped.
+    pub fn purge_channel(&mut self, channel_id: ChannelId) -> bool {
+        let mut purged = self.current_by_channel.remove(&channel_id).is_some();
+        if let Some(users) = self.channel_messages.remove(&channel_id) {
+            for (user_id, count) in users {
+                sub_count(&mut self.messages_count, user_id, count);
+            }
+            purged = true;
+        }
+        if let Some(users) = self.channel_files.remove(&channel_id) {
+            for (user_id, count) in users {
+                sub_count(&mut self.attachments_count, user_id, count);
+            }
+            purged = true;
+        }
+        if let Some(users) = self.channel_voice.remove(&channel_id) {
+            for (user_id, seconds) in users {
+                sub_count(&mut self.voice_seconds, user_id, seconds);
+            }
+            purged = true;
+        }
+        if let Some(mut counters) = self.channel_counters.remove(&channel_id) {
+            let shares = counters.user_counters();
+            for (map, share) in self.user_counters().into_iter().zip(shares) {
+                for (user_id, count) in share.iter() {
+                    sub_count(map, *user_id, *count);
+                }
             }
+            sub_keyed_counts(&mut self.emoji_used, &counters.emoji_used);
+            sub_keyed_counts(&mut self.emoji_reacted, &counters.emoji_reacted);
+            sub_keyed_counts(&mut self.stickers_used, &counters.stickers_used);
+            for (day, channel_day) in self.activity.iter_mut().zip(counters.activity.iter()) {
+                for (hour, count) in day.iter_mut().zip(channel_day.iter()) {
+                    *hour = hour.saturating_sub(*count);
+                }
+            }
+            purged = true;
+        }
+        let bursts = self
+            .burst_channels
+            .iter()
+            .filter(|(_, channels)| channels.contains(&channel_id))
+            .map(|(user_id, _)| *user_id)
+            .collect::<Vec<UserId>>();
+        for user_id in bursts {
+            self.max_burst.remove(&user_id);
+            self.burst_channels.remove(&user_id);
+            purged = true;
+        }
+        for times in self.burst_times.values_mut() {
+            times.retain(|(_, channel)| *channel != channel_id);
         }
+        purged |= self.channel_hours.remove(&channel_id).is_some();
+        self.thread_parents.remove(&channel_id);
+        let records = self.personal_record.len();
+        self.personal_record
+            .retain(|_, record| record.channel_id != channel_id);
+        purged || self.personal_record.len() != records
+    }
 
-        for channel_name in channel_name_list {
-            let symbols = count_symbols(&channel_name);
-            if symbols > max_symbols_in_series_channel_name {
-                max_symbols_in_series_channel_name = symbols;
+    /// The per user counters a channel has a [`ChannelCounters`] share of.
+    fn user_counters(&mut self) -> [&mut HashMap<UserId, usize>; 11] {
+        [
+            &mut self.chars_count,
+            &mut self.words_count,
+            &mut self.graphemes_count,
+            &mut self.links_count,
+            &mut self.replies_given,
+            &mut self.replies_received,
+            &mut self.reactions_given,
+            &mut self.reactions_received,
+            &mut self.edits_count,
+            &mut self.deletions_count,
+            &mut self.karma_received,
+        ]
+    }
+
+    /// Adds up the counters of another stat, the longer series record wins. Running series
+    /// are not carried over.
+    pub fn merge(&mut self, other: &MessageStat) {
+        for (map, other_map) in [
+            (&mut self.messages_count, &other.messages_count),
+            (&mut self.attachments_count, &other.attachments_count),
+            (&mut self.chars_count, &other.chars_count),
+            (&mut self.words_count, &other.words_count),
+            (&mut self.graphemes_count, &other.graphemes_count),
+            (&mut self.links_count, &other.links_count),
+            (&mut self.replies_given, &other.replies_given),
+            (&mut self.replies_received, &other.replies_received),
+            (&mut self.reactions_given, &other.reactions_given),
+            (&mut self.reactions_received, &other.reactions_received),
+            (&mut self.edits_count, &other.edits_count),
+            (&mut self.deletions_count, &other.deletions_count),
+            (&mut self.voice_seconds, &other.voice_seconds),
+            (&mut self.karma_received, &other.karma_received),
+        ] {
+            for (user_id, count) in other_map {
+                add_count(map, *user_id, *count);
+            }
+        }
+        for (user_id, burst) in other.max_burst.iter() {
+            if *burst > self.max_burst.get(user_id).copied().unwrap_or(0) {
+                self.max_burst.insert(*user_id, *burst);
+                match other.burst_channels.get(user_id) {
+                    Some(channels) => self.burst_channels.insert(*user_id, channels.clone()),
+                    None => self.burst_channels.remove(user_id),
+                };
             }
         }
-        let max_symbols_in_series = max_symbols_in_series_channel_name + 4 + 4; // 4 - for " in ", 4 - for digits
+        for (user_id, value) in other.voice_longest.iter() {
+            let max = self.voice_longest.entry(*user_id).or_insert(0);
+            *max = (*max).max(*value);
+        }
+        if let Some(at) = other.voice_peak_at {
+            self.record_voice_peak(other.voice_peak, at);
+        }
+        for (user_id, record) in other.personal_record.iter() {
+            let current = self.personal_record.entry(*user_id).or_default();
+            if record.counter > current.counter {
+                *current = record.clone();
+            }
+        }
+        for (channels, other_channels) in [
+            (&mut self.channel_messages, &other.channel_messages),
+            (&mut self.channel_files, &other.channel_files),
+            (&mut self.channel_voice, &other.channel_voice),
+        ] {
+            for (channel_id, users) in other_channels {
+                let channel = channels.entry(*channel_id).or_default();
+                for (user_id, count) in users {
+                    add_count(channel, *user_id, *count);
+                }
+            }
+        }
+        for (channel_id, other_hours) in other.channel_hours.iter() {
+            let hours = self.channel_hours.entry(*channel_id).or_insert([0; 24]);
+            for (hour, count) in hours.iter_mut().zip(other_hours.iter()) {
+                *hour += count;
+            }
+        }
+        self.thread_parents.extend(other.thread_parents.iter());
+        merge_keyed_counts(&mut self.emoji_used, &other.emoji_used);
+        merge_keyed_counts(&mut self.emoji_reacted, &other.emoji_reacted);
+        merge_keyed_counts(&mut self.stickers_used, &other.stickers_used);
+        for (channel_id, counters) in other.channel_counters.iter() {
+            self.channel_counters
+                .entry(*channel_id)
+                .or_default()
+                .merge(counters);
+        }
+        self.sticker_names.extend(
+            other
+                .sticker_names
+                .iter()
+                .map(|(id, name)| (*id, name.clone())),
+        );
+        for (day, other_day) in self.activity.iter_mut().zip(other.activity.iter()) {
+            for (hour, count) in day.iter_mut().zip(other_day.iter()) {
+                *hour += count;
+            }
+        }
+    }
 
-        // TODO - refactor
-        if max_symbols_in_user_name == 0 {
-            return None;
+    /// A copy with the running streaks counted as records, the live view of the period.
+    pub fn snapshot(&self) -> MessageStat {
+        let mut snapshot = self.clone();
+        snapshot.flush_records();
+        snapshot
+    }
+
+    /// Counts a new message: the streaks, its content and when it was sent.
+    pub fn record_message(&mut self, msg: &Message, tz: Tz, rules: &StreakRules) {
+        self.update_streak(msg, rules.gap);
+        if let Some(window) = rules.burst_window {
+            self.update_burst(msg, window);
         }
 
-        rows.push(format!("{user_header:<max_symbols_in_user_name$} | {messages_header:<max_symbols_in_messages$} | {series_header:<max_symbols_in_series$} | {attachments_header:<max_symbols_in_attachments$}"));
-        rows.push(format!("{:-<max_symbols_in_user_name$}-|-{:-<max_symbols_in_messages$}-|-{:-<max_symbols_in_series$}-|-{:-<max_symbols_in_attachments$}", "-", "-", "-", "-"));
+        let user_id = msg.author.id;
+        let content = &msg.content;
+        let channel = self.channel_counters.entry(msg.channel_id).or_default();
+        let chars = content.chars().count();
+        add_count(&mut self.chars_count, user_id, chars);
+        add_count(&mut channel.chars_count, user_id, chars);
+        let words = content.split_whitespace().count();
+        add_count(&mut self.words_count, user_id, words);
+        add_count(&mut channel.words_count, user_id, words);
+        let graphemes = count_symbols(content);
+        add_count(&mut self.graphemes_count, user_id, graphemes);
+        add_count(&mut channel.graphemes_count, user_id, graphemes);
+        let links = content
+            .split_whitespace()
+            .filter(|word| word.contains("http://") || word.contains("https://"))
+            .count();
+        add_count(&mut self.links_count, user_id, links);
+        add_count(&mut channel.links_count, user_id, links);
 
-        for (user_id, personal_record) in self.personal_record.iter() {
-            let user_name = get_user_name(user_id, &cache_http, conf).await;
-            let user_name_pad = max_symbols_in_user_name - count_symbols(&user_name);
-            let user_name = format!("{user_name}{:<user_name_pad$}", "");
+        if let Some(replied) = msg.referenced_message.as_ref() {
+            add_count(&mut self.replies_given, user_id, 1);
+            add_count(&mut channel.replies_given, user_id, 1);
+            if replied.author.id != user_id {
+                add_count(&mut self.replies_received, replied.author.id, 1);
+                add_count(&mut channel.replies_received, replied.author.id, 1);
+            }
+        }
 
-            let messages_count = self.messages_count.get(user_id).unwrap_or(&0);
-            let series_record_counter = personal_record.counter;
-            let series_record_channel_name =
-                get_channel_name(&personal_record.channel_id, &cache_http).await;
-            let attachments_count = self.attachments_count.get(user_id).unwrap_or(&0);
+        add_count(
+            self.channel_messages.entry(msg.channel_id).or_default(),
+            user_id,
+            1,
+        );
 
-            rows.push(format!("{user_name} | {messages_count:>max_symbols_in_messages$} | {series_record_counter:>4} in {series_record_channel_name:<max_symbols_in_series_channel_name$} | {attachments_count:>max_symbols_in_attachments$}"));
+        for emoji in message_emoji(content) {
+            add_count(
+                channel.emoji_used.entry(emoji.clone()).or_default(),
+                user_id,
+                1,
+            );
+            add_count(self.emoji_used.entry(emoji).or_default(), user_id, 1);
+        }
+        for sticker in msg.sticker_items.iter() {
+            for stickers in [&mut self.stickers_used, &mut channel.stickers_used] {
+                add_count(stickers.entry(sticker.id).or_default(), user_id, 1);
+            }
+            self.sticker_names.insert(sticker.id, sticker.name.clone());
         }
 
-        rows.iter().for_each(|row| {
-            tracing::info!("{}", row);
+        if !msg.attachments.is_empty() {
+            add_count(
+                self.channel_files.entry(msg.channel_id).or_default(),
+                user_id,
+                msg.attachments.len(),
+            );
+        }
+
+        let local = timestamp_to_utc(&msg.timestamp).with_timezone(&tz);
+        let (day, hour) = (
+            local.weekday().num_days_from_monday() as usize,
+            local.hour() as usize,
+        );
+        self.activity[day][hour] += 1;
+        channel.activity[day][hour] += 1;
+        self.channel_hours.entry(msg.channel_id).or_insert([0; 24])[hour] += 1;
+    }
+
+    /// Per channel totals with the threads and forum posts counted in their parent channel,
+    /// the busiest first.
+    pub fn channel_activity(&self) -> Vec<ChannelActivity> {
+        let parent =
+            |channel_id: &ChannelId| *self.thread_parents.get(channel_id).unwrap_or(channel_id);
+        let mut posters = HashMap::<ChannelId, HashSet<UserId>>::new();
+        let mut activity = HashMap::<ChannelId, ChannelActivity>::new();
+        fn entry(
+            activity: &mut HashMap<ChannelId, ChannelActivity>,
+            channel_id: ChannelId,
+        ) -> &mut ChannelActivity {
+            activity.entry(channel_id).or_insert(ChannelActivity {
+                channel_id,
+                ..ChannelActivity::default()
+            })
+        }
+        for (channel_id, users) in self.channel_messages.iter() {
+            let channel_id = parent(channel_id);
+            entry(&mut activity, channel_id).messages += users.values().sum::<usize>();
+            posters.entry(channel_id).or_default().extend(
+                users
+                    .iter()
+                    .filter(|(_, count)| **count > 0)
+                    .map(|(id, _)| *id),
+            );
+        }
+        for (channel_id, users) in self.channel_files.iter() {
+            entry(&mut activity, parent(channel_id)).files += users.values().sum::<usize>();
+        }
+        for (channel_id, hours) in self.channel_hours.iter() {
+            let channel = entry(&mut activity, parent(channel_id));
+            for (hour, count) in channel.hours.iter_mut().zip(hours.iter()) {
+                *hour += count;
+            }
+        }
+
+        let mut activity = activity
+            .into_values()
+            .map(|mut channel| {
+                channel.posters = posters.get(&channel.channel_id).map_or(0, HashSet::len);
+                channel
+            })
+            .filter(|channel| channel.messages > 0)
+            .collect::<Vec<ChannelActivity>>();
+        activity.sort_by(|a, b| {
+            b.messages
+                .cmp(&a.messages)
+                .then(a.channel_id.cmp(&b.channel_id))
         });
+        activity
+    }
+
+    /// A reaction on someone else's message, a removed one takes the counts back.
+    pub fn record_reaction(
+        &mut self,
+        user_id: UserId,
+        author_id: UserId,
+        channel_id: ChannelId,
+        added: bool,
+    ) {
+        if user_id == author_id {
+            return;
+        }
+        let channel = self.channel_counters.entry(channel_id).or_default();
+        for (given, received) in [
+            (&mut self.reactions_given, &mut self.reactions_received),
+            (
+                &mut channel.reactions_given,
+                &mut channel.reactions_received,
+            ),
+        ] {
+            match added {
+                true => {
+                    add_count(given, user_id, 1);
+                    add_count(received, author_id, 1);
+                }
+                false => {
+                    sub_count(given, user_id, 1);
+                    sub_count(received, author_id, 1);
+                }
+            }
+        }
+    }
+
+    pub fn record_edit(&mut self, author_id: UserId, channel_id: ChannelId) {
+        add_count(&mut self.edits_count, author_id, 1);
+        let channel = self.channel_counters.entry(channel_id).or_default();
+        add_count(&mut channel.edits_count, author_id, 1);
+    }
+
+    pub fn record_deletion(&mut self, author_id: UserId, channel_id: ChannelId) {
+        add_count(&mut self.deletions_count, author_id, 1);
+        let channel = self.channel_counters.entry(channel_id).or_default();
+        add_count(&mut channel.deletions_count, author_id, 1);
+    }
+
+    pub fn record_karma(&mut self, user_id: UserId, channel_id: ChannelId) {
+        add_count(&mut self.karma_received, user_id, 1);
+        let channel = self.channel_counters.entry(channel_id).or_default();
+        add_count(&mut channel.karma_received, user_id, 1);
+    }
+
+    pub fn record_emoji_reaction(
+        &mut self,
+        user_id: UserId,
+        channel_id: ChannelId,
+        emoji: &str,
+        added: bool,
+    ) {
+        let channel = self.channel_counters.entry(channel_id).or_default();
+        for reacted in [&mut self.emoji_reacted, &mut channel.emoji_reacted] {
+            match added {
+                true => add_count(reacted.entry(emoji.to_string()).or_default(), user_id, 1),
+                false => {
+                    if let Some(users) = reacted.get_mut(emoji) {
+                        sub_count(users, user_id, 1);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn record_voice(&mut self, user_id: UserId, channel_id: ChannelId, seconds: usize) {
+        if seconds == 0 {
+            return;
+        }
+        add_count(&mut self.voice_seconds, user_id, seconds);
+        add_count(
+            self.channel_voice.entry(channel_id).or_default(),
+            user_id,
+            seconds,
+        );
+    }
 
-        Some(rows.join("\n"))
+    pub fn record_voice_session(&mut self, user_id: UserId, seconds: usize) {
+        if seconds == 0 {
+            return;
+        }
+        let longest = self.voice_longest.entry(user_id).or_insert(0);
+        *longest = (*longest).max(seconds);
     }
 
-    pub fn update_streak(&mut self, msg: &Message) {
+    /// `count` users are in voice at `at`, the first time of the highest count is kept.
+    pub fn record_voice_peak(&mut self, count: usize, at: DateTime<Utc>) {
+        if count > self.voice_peak {
+            self.voice_peak = count;
+            self.voice_peak_at = Some(at);
+        }
+    }
+
+    pub fn update_streak(&mut self, msg: &Message, gap: Option<chrono::Duration>) {
         let user_id = msg.author.id;
         let channel_id = msg.channel_id;
 
@@ -161,11 +1333,11 @@ impl MessageStat {
             Some(channel_streak) => channel_streak.clone(),
             None => MessageStreakUser {
                 user_id,
-                counter: 0,
+                ..MessageStreakUser::default()
             },
         };
 
-        let record_candidate = channel_streak.update_streak(user_id);
+        let record_candidate = channel_streak.update_streak(msg, gap);
         self.current_by_channel.insert(channel_id, channel_streak);
 
         let record_candidate = match record_candidate {
@@ -178,11 +1350,30 @@ impl MessageStat {
             None => MessageStreakPersonalRecord::default(),
         };
 
-        current_record.update_record(record_candidate.counter, channel_id);
+        current_record.update_record(&record_candidate, channel_id);
         self.personal_record
             .insert(record_candidate.user_id, current_record);
     }
 
+    /// Keeps the user's messages inside the window and the most there ever were.
+    fn update_burst(&mut self, msg: &Message, window: chrono::Duration) {
+        let sent_at = timestamp_to_utc(&msg.timestamp);
+        let times = self.burst_times.entry(msg.author.id).or_default();
+        times.push_back((sent_at, msg.channel_id));
+        while times
+            .front()
+            .is_some_and(|(first, _)| sent_at - *first >= window)
+        {
+            times.pop_front();
+        }
+        let burst = self.max_burst.entry(msg.author.id).or_insert(0);
+        if times.len() > *burst {
+            *burst = times.len();
+            let channels = times.iter().map(|(_, channel_id)| *channel_id).collect();
+            self.burst_channels.insert(msg.author.id, channels);
+        }
+    }
+
     pub fn flush_records(&mut self) {
         let last_list = self.current_by_channel.drain();
         last_list.for_each(|(channel_id, record_candidate)| {
@@ -191,7 +1382,7 @@ impl MessageStat {
                 None => MessageStreakPersonalRecord::default(),
             };
 
-            current_record.update_record(record_candidate.counter, channel_id);
+            current_record.update_record(&record_candidate, channel_id);
             self.personal_record
                 .insert(record_candidate.user_id, current_record);
         });
@@ -202,17 +1393,46 @@ impl MessageStat {
 pub struct MessageStreakUser {
     pub user_id: UserId,
     pub counter: usize,
+    #[serde(default)]
+    pub started_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub last_at: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub first_message: Option<MessageId>,
+    #[serde(default)]
+    pub guild_id: Option<GuildId>,
 }
 
 impl MessageStreakUser {
-    pub fn update_streak(&mut self, user_id: UserId) -> Option<MessageStreakUser> {
-        if self.user_id == user_id {
+    /// Continues the series or starts a new one, returning the finished series. Another
+    /// author or a silence longer than `gap` ends it.
+    pub fn update_streak(
+        &mut self,
+        msg: &Message,
+        gap: Option<chrono::Duration>,
+    ) -> Option<MessageStreakUser> {
+        let sent_at = timestamp_to_utc(&msg.timestamp);
+        let silent = match (gap, self.last_at) {
+            (Some(gap), Some(last_at)) => sent_at - last_at > gap,
+            _ => false,
+        };
+        if self.user_id == msg.author.id && !silent {
             self.counter += 1;
+            self.started_at.get_or_insert(sent_at);
+            self.first_message.get_or_insert(msg.id);
+            self.last_at = Some(sent_at);
+            self.guild_id = msg.guild_id;
             return None;
         }
         let old = self.clone();
-        self.user_id = user_id;
-        self.counter = 1;
+        *self = MessageStreakUser {
+            user_id: msg.author.id,
+            counter: 1,
+            started_at: Some(sent_at),
+            last_at: Some(sent_at),
+            first_message: Some(msg.id),
+            guild_id: msg.guild_id,
+        };
         Some(old)
     }
 }
@@ -221,41 +1441,298 @@ impl MessageStreakUser {
 pub struct MessageStreakPersonalRecord {
     pub channel_id: ChannelId,
     pub counter: usize,
+    #[serde(default)]
+    pub start: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub end: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub first_message: Option<MessageId>,
+    #[serde(default)]
+    pub guild_id: Option<GuildId>,
 }
 
 impl MessageStreakPersonalRecord {
-    pub fn update_record(&mut self, count: usize, channel_id: ChannelId) {
-        if count > self.counter {
-            self.counter = count;
-            self.channel_id = channel_id;
+    pub fn update_record(&mut self, streak: &MessageStreakUser, channel_id: ChannelId) {
+        if streak.counter > self.counter {
+            *self = MessageStreakPersonalRecord {
+                channel_id,
+                counter: streak.counter,
+                start: streak.started_at,
+                end: streak.last_at,
+                first_message: streak.first_message,
+                guild_id: streak.guild_id,
+            };
         }
     }
+
+    /// Link to the first message of the series, unknown for records saved before.
+    pub fn jump_link(&self) -> Option<String> {
+        self.first_message
+            .map(|message_id| message_id.link(self.channel_id, self.guild_id))
+    }
 }
 
-pub fn next_update_time() -> chrono::NaiveDateTime {
-    // let next = next_update_time_min();
-    let next = next_sunday_start_time();
-    tracing::info!("Next update time: UTC {:?}", next);
-    next
+/// Start and end of the period following the one that ended at `end`. After downtime it
+/// starts at the latest boundary that has passed, the number of skipped periods is returned too.
+pub fn roll_forward(
+    schedule: &Schedule,
+    end: DateTime<Utc>,
+    now: DateTime<Utc>,
+    tz: Tz,
+) -> (DateTime<Utc>, DateTime<Utc>, usize) {
+    let mut start = end;
+    let mut skipped = 0;
+    let mut until = schedule.next_after(start, tz);
+    while until <= now {
+        start = until;
+        until = schedule.next_after(start, tz);
+        skipped += 1;
+    }
+    (start, until, skipped)
 }
 
-fn next_sunday_start_time() -> chrono::NaiveDateTime {
-    let current_time = chrono::Utc::now().naive_utc(); // Get current local time
-    let current_weekday = current_time.weekday(); // Get current weekday
+/// `2025-03-02 00:00 - 2025-03-09 00:00 (Europe/Berlin)`
+pub fn format_period(start: DateTime<Utc>, end: DateTime<Utc>, tz: Tz) -> String {
+    let format = "%Y-%m-%d %H:%M";
+    format!(
+        "{} - {} ({})",
+        start.with_timezone(&tz).format(format),
+        end.with_timezone(&tz).format(format),
+        tz.name()
+    )
+}
 
-    // Calculate days until next Sunday
-    let days_until_sunday = 7 - current_weekday.num_days_from_sunday();
-    let next_sunday = current_time.date() + chrono::Duration::days(days_until_sunday as i64); // Calculate next Sunday
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    // Set time to start of the day (00:00:00)
-    next_sunday.and_hms_opt(0, 0, 0).expect("Invalid time") // Return NaiveDateTime for next Sunday at midnight
-}
+    #[test]
+    fn test_load_legacy_stat_file() {
+        let dir = std::env::temp_dir().join(format!("stat-{}", Uuid::new_v4()));
+        create_dir_all(&dir).unwrap();
+        let path = dir.join("stat.json").to_string_lossy().to_string();
+        let legacy = r#"{
+            "collection_start": "2025-03-02T00:00:00",
+            "collect_until": "2025-03-09T00:00:00",
+            "last_collection_duration": [604800, 0],
+            "message_stat": {
+                "current_by_channel": {},
+                "personal_record": {"10": {"channel_id": "20", "counter": 3}},
+                "messages_count": {"10": 5},
+                "attachments_count": {}
+            }
+        }"#;
+        std::fs::write(&path, legacy).unwrap();
 
-pub fn next_update_time_min() -> chrono::NaiveDateTime {
-    let now = chrono::Utc::now().naive_utc();
-    let next = now + chrono::Duration::minutes(1) + chrono::Duration::seconds(10);
-    chrono::NaiveDateTime::new(
-        next.date(),
-        chrono::NaiveTime::from_hms_opt(next.hour(), next.minute(), 0).unwrap(),
-    )
+        let stat = Stat::load_from_file(&path, 0, "weekly").unwrap();
+        let period = &stat.periods["weekly"];
+        assert_eq!(
+            period.collect_until.to_rfc3339(),
+            "2025-03-09T00:00:00+00:00"
+        );
+        assert_eq!(period.message_stat.messages_count[&UserId::new(10)], 5);
+
+        write_atomic(Path::new(&path), &serde_json::to_vec(&stat).unwrap()).unwrap();
+        let stat = Stat::load_from_file(&path, 0, "other").unwrap();
+        assert!(stat.periods.contains_key("weekly"));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    fn message(id: u64, author: u64, content: &str) -> Message {
+        let mut msg = Message::default();
+        msg.id = MessageId::new(id);
+        msg.author.id = UserId::new(author);
+        msg.content = content.to_string();
+        msg.timestamp = serenity::all::Timestamp::parse("2025-03-05T21:30:00Z").unwrap();
+        msg
+    }
+
+    #[test]
+    fn test_record_message_metrics() {
+        let mut reply = message(2, 1, "ну да https://example.com");
+        reply.referenced_message = Some(Box::new(message(1, 2, "привет")));
+        let mut stat = MessageStat::default();
+        stat.record_message(
+            &reply,
+            "Europe/Moscow".parse().unwrap(),
+            &StreakRules::default(),
+        );
+
+        let user = UserId::new(1);
+        assert_eq!(stat.value(ReportColumn::Chars, &user), 25);
+        assert_eq!(stat.value(ReportColumn::Words, &user), 3);
+        assert_eq!(stat.value(ReportColumn::Links, &user), 1);
+        assert_eq!(stat.value(ReportColumn::RepliesGiven, &user), 1);
+        assert_eq!(
+            stat.value(ReportColumn::RepliesReceived, &UserId::new(2)),
+            1
+        );
+        // Wednesday 21:30 UTC is Thursday 00:30 in Moscow
+        assert_eq!(stat.activity[3][0], 1);
+    }
+
+    #[test]
+    fn test_reactions_and_deletions() {
+        let mut stat = Stat::default();
+        stat.periods
+            .insert("weekly".to_string(), StatPeriod::default());
+        stat.record_message(&message(1, 10, "hi"), &Config::default());
+        // The authors of recent messages survive a restart
+        let mut stat = serde_json::from_slice::<Stat>(&serde_json::to_vec(&stat).unwrap()).unwrap();
+
+        let reaction = serde_json::from_value::<Reaction>(serde_json::json!({
+            "channel_id": "5",
+            "message_id": "1",
+            "user_id": "20",
+            "emoji": {"name": "👍"},
+            "burst": false,
+            "type": 0,
+        }))
+        .unwrap();
+        stat.record_reaction(&reaction, true, true, &Config::default());
+        stat.record_reaction(&reaction, true, true, &Config::default());
+        stat.record_reaction(&reaction, false, true, &Config::default());
+        stat.record_deletion(ChannelId::new(5), MessageId::new(1), &Config::default());
+        stat.record_deletion(ChannelId::new(5), MessageId::new(1), &Config::default());
+
+        // Only the added reaction carries the author of an older message
+        let mut older = reaction.clone();
+        older.message_id = MessageId::new(2);
+        older.message_author_id = Some(UserId::new(30));
+        stat.record_reaction(&older, true, true, &Config::default());
+        older.message_author_id = None;
+        stat.record_reaction(&older, false, true, &Config::default());
+
+        let period = &stat.periods["weekly"].message_stat;
+        assert_eq!(
+            period.value(ReportColumn::ReactionsGiven, &UserId::new(20)),
+            1
+        );
+        assert_eq!(
+            period.value(ReportColumn::ReactionsReceived, &UserId::new(10)),
+            1
+        );
+        assert_eq!(period.value(ReportColumn::Deletions, &UserId::new(10)), 1);
+        assert_eq!(
+            period.value(ReportColumn::ReactionsReceived, &UserId::new(30)),
+            0
+        );
+    }
+
+    #[test]
+    fn test_opt_out_and_purge() {
+        let mut stat = Stat::default();
+        stat.periods
+            .insert("weekly".to_string(), StatPeriod::default());
+        let mut in_spam = message(3, 11, "spam");
+        in_spam.channel_id = ChannelId::new(7);
+        for msg in [
+            message(1, 10, "hi"),
+            message(2, 10, "again"),
+            in_spam.clone(),
+            in_spam,
+        ] {
+            stat.record_message(&msg, &Config::default());
+        }
+        stat.opted_out.insert(UserId::new(12));
+        stat.record_message(&message(4, 12, "not counted"), &Config::default());
+
+        let period = &stat.periods["weekly"].message_stat;
+        assert_eq!(period.value(ReportColumn::Messages, &UserId::new(12)), 0);
+        assert_eq!(period.value(ReportColumn::Messages, &UserId::new(11)), 2);
+
+        assert!(stat.purge_channel(ChannelId::new(7)));
+        assert!(!stat.purge_channel(ChannelId::new(7)));
+        let period = &stat.periods["weekly"].message_stat;
+        assert_eq!(period.value(ReportColumn::Messages, &UserId::new(11)), 0);
+        assert_eq!(period.value(ReportColumn::Chars, &UserId::new(11)), 0);
+        assert_eq!(period.value(ReportColumn::Chars, &UserId::new(10)), 7);
+        assert_eq!(period.activity.iter().flatten().sum::<usize>(), 2);
+        assert!(!period.channels().contains(&ChannelId::new(7)));
+
+        assert!(stat.purge_user(UserId::new(10)));
+        let period = &stat.periods["weekly"].message_stat;
+        assert!(!period.users().contains(&UserId::new(10)));
+        assert!(period.current_by_channel.is_empty());
+    }
+
+    #[test]
+    fn test_streak_gap_and_burst() {
+        let rules = StreakRules {
+            gap: Some(chrono::Duration::minutes(60)),
+            burst_window: Some(chrono::Duration::minutes(10)),
+            report_top: 3,
+        };
+        let at = |id: u64, time: &str| {
+            let mut msg = message(id, 10, "hi");
+            msg.guild_id = Some(GuildId::new(1));
+            msg.channel_id = ChannelId::new(5);
+            msg.timestamp = serenity::all::Timestamp::parse(time).unwrap();
+            msg
+        };
+        let mut stat = MessageStat::default();
+        for msg in [
+            at(1, "2025-03-05T10:00:00Z"),
+            at(2, "2025-03-05T10:05:00Z"),
+            at(3, "2025-03-05T10:50:00Z"),
+            // Three days of silence start a new series
+            at(4, "2025-03-08T10:00:00Z"),
+            at(5, "2025-03-08T10:01:00Z"),
+        ] {
+            stat.record_message(&msg, chrono_tz::UTC, &rules);
+        }
+        stat.flush_records();
+
+        let user = UserId::new(10);
+        let record = &stat.personal_record[&user];
+        assert_eq!(record.counter, 3);
+        assert_eq!(
+            record.start.unwrap().to_rfc3339(),
+            "2025-03-05T10:00:00+00:00"
+        );
+        assert_eq!(
+            record.end.unwrap().to_rfc3339(),
+            "2025-03-05T10:50:00+00:00"
+        );
+        assert_eq!(
+            record.jump_link().as_deref(),
+            Some("https://discord.com/channels/1/5/1")
+        );
+        assert_eq!(stat.value(ReportColumn::Burst, &user), 2);
+
+        // Without a gap the series never breaks
+        let mut endless = MessageStat::default();
+        for msg in [at(1, "2025-03-05T10:00:00Z"), at(4, "2025-03-08T10:00:00Z")] {
+            endless.record_message(&msg, chrono_tz::UTC, &StreakRules::default());
+        }
+        assert_eq!(endless.snapshot().personal_record[&user].counter, 2);
+    }
+
+    #[test]
+    fn test_roll_forward_after_downtime() {
+        let schedule = Schedule::parse("weekly=weekly sun 00:00").unwrap();
+        let utc = |text: &str| text.parse::<DateTime<Utc>>().unwrap();
+        let end = utc("2025-03-09T00:00:00Z");
+
+        let on_time = roll_forward(&schedule, end, end, Tz::UTC);
+        assert_eq!(on_time, (end, utc("2025-03-16T00:00:00Z"), 0));
+
+        let late = roll_forward(&schedule, end, utc("2025-03-26T10:00:00Z"), Tz::UTC);
+        assert_eq!(
+            late,
+            (utc("2025-03-23T00:00:00Z"), utc("2025-03-30T00:00:00Z"), 2)
+        );
+    }
+
+    #[test]
+    fn test_format_period_in_timezone() {
+        let start = "2025-03-01T21:00:00Z".parse().unwrap();
+        let end = "2025-03-08T21:00:00Z".parse().unwrap();
+        let moscow = "Europe/Moscow".parse().unwrap();
+        assert_eq!(
+            format_period(start, end, moscow),
+            "2025-03-02 00:00 - 2025-03-09 00:00 (Europe/Moscow)"
+        );
+    }
 }
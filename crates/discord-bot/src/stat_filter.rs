@@ -0,0 +1,304 @@
+use super::*;
+
+use serenity::all::{
+    Channel, ChannelId, ChannelType, GuildChannel, GuildId, Http, Message, MessageUpdateEvent,
+    UserId,
+};
+use serenity::prelude::*;
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+/// Which activity counts for the statistics, from the `STAT_*` filter variables.
+#[derive(Debug, Clone, Default)]
+pub struct StatFilter {
+    pub ignore_bots: bool,
+    pub ignore_webhooks: bool,
+    pub ignore_dms: bool,
+    pub allow_channels: HashSet<ChannelId>,
+    pub deny_channels: HashSet<ChannelId>,
+    pub allow_categories: HashSet<ChannelId>,
+    pub deny_categories: HashSet<ChannelId>,
+}
+
+impl StatFilter {
+    pub fn has_channel_rules(&self) -> bool {
+        !self.allow_channels.is_empty()
+            || !self.deny_channels.is_empty()
+            || !self.allow_categories.is_empty()
+            || !self.deny_categories.is_empty()
+    }
+
+    /// `lineage` is the channel followed by its parents: a thread, its channel, the category.
+    /// A deny rule on any of them wins, with allow rules one of them has to be allowed.
+    pub fn allows_lineage(&self, lineage: &[ChannelId]) -> bool {
+        let matches = |set: &HashSet<ChannelId>| lineage.iter().any(|id| set.contains(id));
+        if matches(&self.deny_channels) || matches(&self.deny_categories) {
+            return false;
+        }
+        let has_allow_rules = !self.allow_channels.is_empty() || !self.allow_categories.is_empty();
+        !has_allow_rules || matches(&self.allow_channels) || matches(&self.allow_categories)
+    }
+
+    pub fn excludes_author(&self, msg: &Message) -> bool {
+        (self.ignore_bots && msg.author.bot) || (self.ignore_webhooks && msg.webhook_id.is_some())
+    }
+
+    /// The same rule for an edit, which only carries what changed.
+    pub fn excludes_edit(&self, event: &MessageUpdateEvent) -> bool {
+        let by_bot = event.author.as_ref().is_some_and(|author| author.bot);
+        let by_webhook = event
+            .webhook_id
+            .is_some_and(|webhook_id| webhook_id.is_some());
+        (self.ignore_bots && by_bot) || (self.ignore_webhooks && by_webhook)
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ChannelInfo {
+    pub kind: ChannelType,
+    pub parent_id: Option<ChannelId>,
+}
+
+/// Kinds and parents of the channels seen, kept up to date by the channel events so the
+/// filters don't ask Discord for every message.
+#[derive(Debug, Default)]
+pub struct ChannelCache {
+    channels: HashMap<ChannelId, ChannelInfo>,
+    afk_channel: Option<ChannelId>,
+}
+
+/// The lookups take the cache's mutex, which is only held to read and to store, never while
+/// Discord answers.
+impl ChannelCache {
+    pub async fn info(
+        cache: &Mutex<ChannelCache>,
+        http: &Http,
+        channel_id: ChannelId,
+    ) -> Option<ChannelInfo> {
+        let cached = cache.lock().await.channels.get(&channel_id).copied();
+        if cached.is_some() {
+            return cached;
+        }
+        let info = match channel_id.to_channel(http).await {
+            Ok(Channel::Guild(channel)) => ChannelInfo {
+                kind: channel.kind,
+                parent_id: channel.parent_id,
+            },
+            Ok(Channel::Private(_)) => ChannelInfo {
+                kind: ChannelType::Private,
+                parent_id: None,
+            },
+            Ok(_) => return None,
+            Err(e) => {
+                tracing::warn!("Error getting channel {}: {:?}", channel_id, e);
+                return None;
+            }
+        };
+        cache.lock().await.channels.insert(channel_id, info);
+        Some(info)
+    }
+
+    /// The channel and its parents, closest first.
+    pub async fn lineage(
+        cache: &Mutex<ChannelCache>,
+        http: &Http,
+        channel_id: ChannelId,
+    ) -> Vec<ChannelId> {
+        let mut lineage = vec![channel_id];
+        // A thread is at most two levels below a category
+        while lineage.len() < 3 {
+            let last = lineage[lineage.len() - 1];
+            let info = ChannelCache::info(cache, http, last).await;
+            match info.and_then(|info| info.parent_id) {
+                Some(parent_id) => lineage.push(parent_id),
+                None => break,
+            }
+        }
+        lineage
+    }
+
+    /// The channel a thread or forum post belongs to, `None` for anything else.
+    pub async fn thread_parent(
+        cache: &Mutex<ChannelCache>,
+        http: &Http,
+        channel_id: ChannelId,
+    ) -> Option<ChannelId> {
+        let info = ChannelCache::info(cache, http, channel_id).await?;
+        match info.kind {
+            ChannelType::PublicThread | ChannelType::PrivateThread | ChannelType::NewsThread => {
+                info.parent_id
+            }
+            _ => None,
+        }
+    }
+
+    pub fn update(&mut self, channel: &GuildChannel) {
+        let info = ChannelInfo {
+            kind: channel.kind,
+            parent_id: channel.parent_id,
+        };
+        self.channels.insert(channel.id, info);
+    }
+
+    pub fn forget(&mut self, channel_id: ChannelId) {
+        self.channels.remove(&channel_id);
+    }
+
+    /// The guild's AFK voice channel, from the guild events.
+    pub fn set_afk_channel(&mut self, channel_id: Option<ChannelId>) {
+        self.afk_channel = channel_id;
+    }
+
+    pub fn is_afk(&self, channel_id: ChannelId) -> bool {
+        self.afk_channel == Some(channel_id)
+    }
+}
+
+/// Whether activity in the channel counts. The parents are only looked up when there are
+/// channel rules.
+pub async fn stat_channel_allowed(
+    http: &Http,
+    conf: &Config,
+    channels: &Mutex<ChannelCache>,
+    channel_id: ChannelId,
+    guild_id: Option<GuildId>,
+) -> bool {
+    let filter = &conf.stat_filter;
+    if guild_id.is_none() {
+        return !filter.ignore_dms;
+    }
+    if !filter.has_channel_rules() {
+        return true;
+    }
+    let lineage = ChannelCache::lineage(channels, http, channel_id).await;
+    filter.allows_lineage(&lineage)
+}
+
+/// Whether the message counts, by its author and channel.
+pub async fn stat_message_allowed(
+    http: &Http,
+    conf: &Config,
+    channels: &Mutex<ChannelCache>,
+    msg: &Message,
+) -> bool {
+    !conf.stat_filter.excludes_author(msg)
+        && stat_channel_allowed(http, conf, channels, msg.channel_id, msg.guild_id).await
+}
+
+/// Removes the data of users who opted out and of channels the filters exclude now, from
+/// the running periods and the archive. Runs once at the start, the rules only change with
+/// a restart.
+pub fn purge_excluded_at_start(
+    http: Arc<Http>,
+    stat: Arc<Mutex<Stat>>,
+    channels: Arc<Mutex<ChannelCache>>,
+    conf: Config,
+) {
+    tokio::spawn(async move {
+        let archive = read_json_lines::<ArchivedPeriod>(&conf.archive_file);
+        let stat_guard = stat.lock().await;
+        let users = stat_guard.opted_out.clone();
+        let mut known_channels = BTreeSet::new();
+        for message_stat in stat_guard
+            .periods
+            .values()
+            .map(|period| &period.message_stat)
+            .chain(archive.iter().map(|period| &period.message_stat))
+        {
+            known_channels.extend(message_stat.channels());
+        }
+        drop(stat_guard);
+
+        let check_channels = conf.stat_filter.has_channel_rules() || conf.stat_filter.ignore_dms;
+        let mut excluded_channels = BTreeSet::new();
+        for channel_id in known_channels.into_iter().filter(|_| check_channels) {
+            let is_dm = matches!(
+                ChannelCache::info(&channels, &http, channel_id).await,
+                Some(ChannelInfo {
+                    kind: ChannelType::Private,
+                    ..
+                })
+            );
+            let lineage = ChannelCache::lineage(&channels, &http, channel_id).await;
+            let allowed = match is_dm {
+                true => !conf.stat_filter.ignore_dms,
+                false => conf.stat_filter.allows_lineage(&lineage),
+            };
+            if !allowed {
+                excluded_channels.insert(channel_id);
+            }
+        }
+        if users.is_empty() && excluded_channels.is_empty() {
+            return;
+        }
+
+        let mut stat_guard = stat.lock().await;
+        let mut changed = false;
+        for user_id in users.iter() {
+            changed |= stat_guard.purge_user(*user_id);
+        }
+        for channel_id in excluded_channels.iter() {
+            changed |= stat_guard.purge_channel(*channel_id);
+        }
+        match changed {
+            true => {
+                tracing::info!("Purged excluded users and channels from the statistics");
+                save_stat(stat_guard, &conf).await;
+            }
+            false => drop(stat_guard),
+        }
+        purge_archive(&conf, &users, &excluded_channels).await;
+    });
+}
+
+/// Drops a bot or webhook author that used to be counted, once per run.
+pub async fn purge_excluded_author(stat: &Mutex<Stat>, conf: &Config, user_id: UserId) {
+    let mut stat_guard = stat.lock().await;
+    if !stat_guard.purged_authors.insert(user_id) {
+        return;
+    }
+    match stat_guard.purge_user(user_id) {
+        true => {
+            tracing::info!("Purged excluded author {} from the statistics", user_id);
+            save_stat(stat_guard, conf).await;
+        }
+        false => drop(stat_guard),
+    }
+    purge_archive(conf, &BTreeSet::from([user_id]), &BTreeSet::new()).await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_lineage() {
+        let id = ChannelId::new;
+        let (thread, channel, category) = (id(1), id(2), id(3));
+        let lineage = [thread, channel, category];
+
+        assert!(StatFilter::default().allows_lineage(&lineage));
+
+        let deny_category = StatFilter {
+            deny_categories: HashSet::from([category]),
+            ..StatFilter::default()
+        };
+        assert!(!deny_category.allows_lineage(&lineage));
+        assert!(deny_category.allows_lineage(&[id(4), id(5)]));
+
+        let allow_channel = StatFilter {
+            allow_channels: HashSet::from([channel]),
+            ..StatFilter::default()
+        };
+        assert!(allow_channel.allows_lineage(&lineage));
+        assert!(!allow_channel.allows_lineage(&[id(4), category]));
+
+        // Deny wins over allow
+        let both = StatFilter {
+            allow_categories: HashSet::from([category]),
+            deny_channels: HashSet::from([thread]),
+            ..StatFilter::default()
+        };
+        assert!(!both.allows_lineage(&lineage));
+        assert!(both.allows_lineage(&[id(6), category]));
+    }
+}
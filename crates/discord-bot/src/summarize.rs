@@ -0,0 +1,317 @@
+use super::*;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use ollama_rs::generation::chat::ChatMessage;
+use serenity::all::{
+    ChannelId, CommandDataOptionValue, CommandInteraction, CommandOptionType, CreateCommand,
+    CreateCommandOption, CreateInteractionResponseFollowup, EditInteractionResponse, GetMessages,
+    Message, MessageId, UserId,
+};
+use serenity::prelude::*;
+use std::collections::HashMap;
+use std::time::Instant;
+
+const DISCORD_MESSAGE_LIMIT: usize = 2000;
+
+pub fn summarize_command() -> CreateCommand {
+    CreateCommand::new("summarize")
+        .description("Summarize the recent messages of this channel")
+        .add_option(
+            CreateCommandOption::new(
+                CommandOptionType::Integer,
+                "count",
+                "How many recent messages to read",
+            )
+            .min_int_value(1)
+            .max_int_value(5000),
+        )
+        .add_option(CreateCommandOption::new(
+            CommandOptionType::String,
+            "since",
+            "Read messages since: 30m, 2h, 1d, 2025-01-31 or 2025-01-31 18:00",
+        ))
+}
+
+pub async fn run_summarize_command(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    llm: &Llm,
+) {
+    let channel_id = command.channel_id;
+
+    let mut count = conf.summarize_default_count;
+    let mut since = None;
+    for option in command.data.options.iter() {
+        match (option.name.as_str(), &option.value) {
+            ("count", CommandDataOptionValue::Integer(value)) => count = *value as usize,
+            ("since", CommandDataOptionValue::String(value)) => {
+                since = match parse_since(value, Utc::now()) {
+                    Some(since) => Some(since),
+                    None => {
+                        respond_ephemeral(ctx, command, "Can't parse `since`").await;
+                        return;
+                    }
+                };
+                if !command.data.options.iter().any(|o| o.name == "count") {
+                    count = conf.summarize_max_messages;
+                }
+            }
+            _ => {}
+        }
+    }
+    let count = count.min(conf.summarize_max_messages);
+
+    let mut cooldowns = llm.summarize_cooldowns.lock().await;
+    if let Some(last) = cooldowns.get(&channel_id) {
+        let left = conf
+            .summarize_cooldown_secs
+            .saturating_sub(last.elapsed().as_secs());
+        if left > 0 {
+            drop(cooldowns);
+            let text = format!("Summary was requested recently, try again in {left} s");
+            respond_ephemeral(ctx, command, &text).await;
+            return;
+        }
+    }
+    // Stamped now so a second request can't start meanwhile, given back if this one fails
+    let previous = cooldowns.insert(channel_id, Instant::now());
+    drop(cooldowns);
+
+    command.defer(&ctx.http).await.ok();
+
+    let messages = match fetch_channel_history(ctx, channel_id, count, since).await {
+        Ok(messages) => messages,
+        Err(e) => {
+            tracing::error!("Error fetching channel history: {:?}", e);
+            restore_cooldown(llm, channel_id, previous).await;
+            edit_summary_response(ctx, command, "Error reading the channel history").await;
+            return;
+        }
+    };
+    if messages.is_empty() {
+        restore_cooldown(llm, channel_id, previous).await;
+        edit_summary_response(ctx, command, "Nothing to summarize").await;
+        return;
+    }
+
+    let progress = format!("Reading {} messages...", messages.len());
+    edit_summary_response(ctx, command, &progress).await;
+
+    let lines = format_history(ctx, conf, &messages).await;
+    let summary = match summarize_lines(ctx, command, conf, llm, lines).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            restore_cooldown(llm, channel_id, previous).await;
+            edit_summary_response(ctx, command, &conf.backend_error_message(&e)).await;
+            return;
+        }
+    };
+
+    let header = format!("Summary of the last {} messages:\n", messages.len());
+    let mut parts = split_message(&format!("{header}{summary}"), DISCORD_MESSAGE_LIMIT).into_iter();
+    if let Some(first) = parts.next() {
+        edit_summary_response(ctx, command, &first).await;
+    }
+    for part in parts {
+        let builder = CreateInteractionResponseFollowup::new().content(part);
+        if let Err(why) = command.create_followup(&ctx.http, builder).await {
+            tracing::error!("Error sending summary part: {why:?}");
+        }
+    }
+}
+
+/// A summary that failed doesn't use up the cooldown.
+async fn restore_cooldown(llm: &Llm, channel_id: ChannelId, previous: Option<Instant>) {
+    let mut cooldowns = llm.summarize_cooldowns.lock().await;
+    match previous {
+        Some(previous) => cooldowns.insert(channel_id, previous),
+        None => cooldowns.remove(&channel_id),
+    };
+}
+
+/// Pages back through the channel history, newest first, the result is in chronological order.
+pub async fn fetch_channel_history(
+    ctx: &Context,
+    channel_id: ChannelId,
+    count: usize,
+    since: Option<DateTime<Utc>>,
+) -> eyre::Result<Vec<Message>> {
+    let mut messages: Vec<Message> = vec![];
+    let mut before: Option<MessageId> = None;
+
+    'pages: while messages.len() < count {
+        let limit = (count - messages.len()).min(100) as u8;
+        let mut builder = GetMessages::new().limit(limit);
+        if let Some(before) = before {
+            builder = builder.before(before);
+        }
+        let page = channel_id.messages(ctx, builder).await?;
+        if page.is_empty() {
+            break;
+        }
+        before = page.last().map(|m| m.id);
+
+        for msg in page {
+            if let Some(since) = since {
+                if timestamp_to_utc(&msg.timestamp) < since {
+                    break 'pages;
+                }
+            }
+            messages.push(msg);
+        }
+    }
+
+    messages.reverse();
+    Ok(messages)
+}
+
+async fn format_history(ctx: &Context, conf: &Config, messages: &[Message]) -> Vec<String> {
+    let mut names: HashMap<UserId, String> = HashMap::new();
+    let mut lines = vec![];
+
+    for msg in messages {
+        if msg.content.trim().is_empty() {
+            continue;
+        }
+        let name = match names.get(&msg.author.id) {
+            Some(name) => name.clone(),
+            None => {
+                let name = get_user_name(&msg.author.id, &ctx.http, conf).await;
+                names.insert(msg.author.id, name.clone());
+                name
+            }
+        };
+        let content = replace_mentions(&msg.content, &ctx.http, conf).await;
+        lines.push(format!(
+            "[{}] {}: {}",
+            timestamp_to_utc(&msg.timestamp).format("%Y-%m-%d %H:%M"),
+            name,
+            content
+        ));
+    }
+
+    lines
+}
+
+/// Map-reduce: every chunk of the log is summarized on its own, then the partial summaries
+/// are merged until a single digest is left.
+async fn summarize_lines(
+    ctx: &Context,
+    command: &CommandInteraction,
+    conf: &Config,
+    llm: &Llm,
+    lines: Vec<String>,
+) -> Result<String, BackendError> {
+    let mut chunks = chunk_lines(&lines, conf.summarize_chunk_chars);
+    let mut prompt = &conf.summarize_prompt;
+
+    loop {
+        let total = chunks.len();
+        let mut summaries = vec![];
+        for (index, chunk) in chunks.iter().enumerate() {
+            if total > 1 {
+                let progress = format!("Summarizing part {}/{}...", index + 1, total);
+                edit_summary_response(ctx, command, &progress).await;
+            }
+            let messages = vec![
+                ChatMessage::system(prompt.clone()),
+                ChatMessage::user(chunk.clone()),
+            ];
+            let reply = chat(conf, llm, "summarize", messages).await?;
+            summaries.push(remove_think_blocks(&reply.content).trim().to_string());
+        }
+
+        if summaries.len() == 1 {
+            return Ok(summaries.remove(0));
+        }
+        chunks = chunk_lines(&summaries, conf.summarize_chunk_chars);
+        if chunks.len() == total {
+            // The summaries don't get shorter, merging them further would never end
+            return Ok(summaries.join("\n"));
+        }
+        prompt = &conf.summarize_reduce_prompt;
+    }
+}
+
+/// Groups lines into chunks of at most `max_chars` characters, a longer line gets a chunk
+/// of its own.
+pub fn chunk_lines(lines: &[String], max_chars: usize) -> Vec<String> {
+    let mut chunks = vec![];
+    let mut current = String::new();
+
+    for line in lines {
+        let fits = current.chars().count() + line.chars().count() < max_chars;
+        if !current.is_empty() && !fits {
+            chunks.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push('\n');
+        }
+        current.push_str(line);
+    }
+    if !current.is_empty() {
+        chunks.push(current);
+    }
+
+    chunks
+}
+
+/// Accepts `30m`, `2h`, `1d`, `1w` relative to `now`, or a UTC date with optional time.
+pub fn parse_since(input: &str, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    let input = input.trim();
+    let re = Regex::new(r"^(\d+)\s*([mhdw])$").unwrap();
+    if let Some(caps) = re.captures(input) {
+        let amount = caps[1].parse::<i64>().ok()?;
+        let duration = match &caps[2] {
+            "m" => chrono::Duration::try_minutes(amount)?,
+            "h" => chrono::Duration::try_hours(amount)?,
+            "d" => chrono::Duration::try_days(amount)?,
+            _ => chrono::Duration::try_weeks(amount)?,
+        };
+        return now.checked_sub_signed(duration);
+    }
+
+    if let Ok(time) = NaiveDateTime::parse_from_str(input, "%Y-%m-%d %H:%M") {
+        return Some(time.and_utc());
+    }
+    NaiveDate::parse_from_str(input, "%Y-%m-%d")
+        .ok()
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .map(|time| time.and_utc())
+}
+
+async fn edit_summary_response(ctx: &Context, command: &CommandInteraction, content: &str) {
+    let builder = EditInteractionResponse::new().content(content);
+    if let Err(why) = command.edit_response(&ctx.http, builder).await {
+        tracing::error!("Error responding to command: {why:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_since() {
+        let now = "2025-03-05T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let parse = |input| parse_since(input, now).map(|t| t.to_rfc3339());
+        assert_eq!(parse("2h").as_deref(), Some("2025-03-05T10:00:00+00:00"));
+        assert_eq!(parse("1w").as_deref(), Some("2025-02-26T12:00:00+00:00"));
+        assert_eq!(
+            parse("2025-03-01").as_deref(),
+            Some("2025-03-01T00:00:00+00:00")
+        );
+        assert_eq!(
+            parse("2025-03-01 18:30").as_deref(),
+            Some("2025-03-01T18:30:00+00:00")
+        );
+        assert_eq!(parse("yesterday"), None);
+    }
+
+    #[test]
+    fn test_chunk_lines() {
+        let lines = ["aaaa", "bbbb", "cc", "dddddddddd"].map(String::from);
+        assert_eq!(chunk_lines(&lines, 10), ["aaaa\nbbbb", "cc", "dddddddddd"]);
+    }
+}
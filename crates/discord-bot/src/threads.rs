@@ -0,0 +1,210 @@
+use super::*;
+
+use eyre::WrapErr;
+use serenity::all::{
+    AutoArchiveDuration, Channel, ChannelId, CreateThread, GetMessages, MessageId, MessageType,
+    UserId,
+};
+use serenity::model::channel::Message;
+use serenity::prelude::*;
+use std::collections::HashMap;
+
+const MAX_THREAD_NAME_SYMBOLS: usize = 100; // Discord limit
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelKind {
+    BotThread,
+    OtherThread,
+    Regular,
+}
+
+/// Remembers which channels are threads started by the bot, so the channel has to be
+/// fetched only once per process lifetime.
+#[derive(Debug, Default)]
+pub struct BotThreads {
+    kinds: HashMap<ChannelId, ChannelKind>,
+}
+
+/// The lookup takes the mutex only to read and to store, never while Discord answers.
+impl BotThreads {
+    pub fn mark_owned(&mut self, thread_id: ChannelId) {
+        self.kinds.insert(thread_id, ChannelKind::BotThread);
+    }
+
+    pub async fn classify(
+        threads: &Mutex<BotThreads>,
+        cache_http: impl CacheHttp,
+        channel_id: ChannelId,
+        self_id: UserId,
+    ) -> ChannelKind {
+        if let Some(kind) = threads.lock().await.kinds.get(&channel_id) {
+            return *kind;
+        }
+
+        let kind = match channel_id.to_channel(&cache_http).await {
+            Ok(Channel::Guild(channel)) if channel.thread_metadata.is_some() => {
+                match channel.owner_id == Some(self_id) {
+                    true => ChannelKind::BotThread,
+                    false => ChannelKind::OtherThread,
+                }
+            }
+            Ok(_) => ChannelKind::Regular,
+            Err(err) => {
+                // Do not cache the failure, the next message will try again
+                tracing::warn!("Error getting channel {channel_id}: {err:?}");
+                return ChannelKind::Regular;
+            }
+        };
+        // The thread may have been created meanwhile, what is stored already wins
+        *threads.lock().await.kinds.entry(channel_id).or_insert(kind)
+    }
+}
+
+/// Replies to every message posted in a thread owned by the bot, no mention required.
+pub async fn react_in_bot_thread(
+    ctx: &Context,
+    msg: &Message,
+    self_id: UserId,
+    conf: &Config,
+    threads: &Mutex<BotThreads>,
+    llm: &Llm,
+) -> bool {
+    if msg.author.id == self_id || msg.author.bot {
+        return false;
+    }
+
+    if msg.guild_id.is_none() {
+        return false;
+    }
+
+    let kind = BotThreads::classify(threads, ctx, msg.channel_id, self_id).await;
+    if kind != ChannelKind::BotThread {
+        return false;
+    }
+
+    react(
+        ctx,
+        msg,
+        ReplyTarget::Thread(msg.channel_id),
+        self_id,
+        conf,
+        llm,
+    )
+    .await;
+
+    true
+}
+
+/// Starts a new thread from the mentioning message and answers inside it.
+/// Returns `false` when a thread can't be used here, so the caller can reply in place.
+pub async fn start_thread_conversation(
+    ctx: &Context,
+    msg: &Message,
+    self_id: UserId,
+    conf: &Config,
+    threads: &Mutex<BotThreads>,
+    llm: &Llm,
+) -> bool {
+    if !conf.thread_mode || msg.guild_id.is_none() {
+        return false;
+    }
+
+    if BotThreads::classify(threads, ctx, msg.channel_id, self_id).await != ChannelKind::Regular {
+        // Threads can't be nested, keep talking in the current one
+        return false;
+    }
+
+    let author_name = get_user_name(&msg.author.id, &ctx.http, conf).await;
+    let builder = CreateThread::new(thread_name(&msg.content, &author_name))
+        .auto_archive_duration(AutoArchiveDuration::from(conf.thread_auto_archive_minutes));
+    let thread = match msg
+        .channel_id
+        .create_thread_from_message(&ctx.http, msg.id, builder)
+        .await
+    {
+        Ok(thread) => thread,
+        Err(why) => {
+            tracing::error!("Error creating thread: {why:?}");
+            return false;
+        }
+    };
+    threads.lock().await.mark_owned(thread.id);
+
+    tracing::info!("Started thread \"{}\" ({})", thread.name, thread.id);
+    react(ctx, msg, ReplyTarget::Thread(thread.id), self_id, conf, llm).await;
+
+    true
+}
+
+/// Collects the thread history for the context builder, including the starter message
+/// which lives in the parent channel.
+pub async fn get_thread_messages_chain(
+    ctx: &Context,
+    msg: &Message,
+    thread_id: ChannelId,
+    last_messages_number: u8,
+) -> eyre::Result<Vec<Message>> {
+    if msg.channel_id != thread_id {
+        // The thread was just created from this message, there is no history yet
+        return Ok(vec![msg.clone()]);
+    }
+
+    let mut msg_chain = thread_id
+        .messages(ctx, GetMessages::new().limit(last_messages_number))
+        .await
+        .wrap_err_with(|| "Error getting thread messages list")?;
+    msg_chain.retain(|m| m.kind != MessageType::ThreadStarterMessage);
+
+    let thread = thread_id
+        .to_channel(ctx)
+        .await
+        .wrap_err_with(|| "Error getting thread")?
+        .guild();
+    if let Some(parent_id) = thread.and_then(|t| t.parent_id) {
+        // Threads created from a message share its ID
+        let starter_id = MessageId::new(thread_id.get());
+        match parent_id.message(ctx, starter_id).await {
+            Ok(starter) => msg_chain.push(starter),
+            Err(err) => tracing::info!("No starter message for thread {thread_id}: {err:?}"),
+        }
+    }
+
+    Ok(msg_chain)
+}
+
+/// Builds the thread title from the question: mentions are dropped, whitespace collapsed
+/// and the result cut to the Discord limit.
+pub fn thread_name(content: &str, author_name: &str) -> String {
+    let re = Regex::new(r"<(@[!&]?|#)\d+>").unwrap();
+    let topic = re.replace_all(content, " ");
+    let topic = topic.split_whitespace().collect::<Vec<&str>>().join(" ");
+
+    let name = match topic.is_empty() {
+        true => format!("Conversation with {author_name}"),
+        false => topic,
+    };
+
+    name.chars().take(MAX_THREAD_NAME_SYMBOLS).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_thread_name_strips_mentions() {
+        let name = thread_name("<@123456> how   do\nthreads work? <#42>", "Bob");
+        assert_eq!(name, "how do threads work?");
+    }
+
+    #[test]
+    fn test_thread_name_fallback() {
+        assert_eq!(thread_name("<@!123456>", "Bob"), "Conversation with Bob");
+    }
+
+    #[test]
+    fn test_thread_name_is_limited() {
+        let name = thread_name(&"я".repeat(150), "Bob");
+        assert_eq!(name.chars().count(), MAX_THREAD_NAME_SYMBOLS);
+    }
+}
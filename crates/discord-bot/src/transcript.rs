@@ -0,0 +1,192 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use ollama_rs::generation::chat::{ChatMessage, ChatMessageResponse, MessageRole};
+use ollama_rs::generation::options::GenerationOptions;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// One LLM exchange as it went over the wire.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TranscriptRecord {
+    pub id: Uuid,
+    pub timestamp: DateTime<Utc>,
+    pub purpose: String,
+    pub model: String,
+    pub system_prompt: Option<String>,
+    pub messages: Vec<ChatMessage>, // exactly as sent, including the system prompt
+    pub options: Option<GenerationOptions>,
+    pub response: Option<serde_json::Value>, // raw Ollama response
+    pub error: Option<String>,
+    pub latency_ms: u64,
+}
+
+impl TranscriptRecord {
+    pub fn new(
+        purpose: &str,
+        model: &str,
+        messages: &[ChatMessage],
+        options: Option<GenerationOptions>,
+        result: &Result<ChatMessageResponse, BackendError>,
+        latency: Duration,
+    ) -> TranscriptRecord {
+        let system_prompt = messages
+            .iter()
+            .find(|m| m.role == MessageRole::System)
+            .map(|m| m.content.clone());
+        let (response, error) = match result {
+            Ok(response) => (serde_json::to_value(response).ok(), None),
+            Err(err) => (None, Some(err.to_string())),
+        };
+
+        TranscriptRecord {
+            id: Uuid::new_v4(),
+            timestamp: Utc::now(),
+            purpose: purpose.to_string(),
+            model: model.to_string(),
+            system_prompt,
+            messages: messages.to_vec(),
+            options,
+            response,
+            error,
+            latency_ms: latency.as_millis() as u64,
+        }
+    }
+
+    /// Text of the recorded answer, if the exchange succeeded.
+    pub fn response_content(&self) -> Option<String> {
+        self.response
+            .as_ref()?
+            .get("message")?
+            .get("content")?
+            .as_str()
+            .map(String::from)
+    }
+}
+
+/// Appends records to a JSONL file and rotates it once it grows over the size limit:
+/// `transcripts.jsonl` becomes `transcripts.1.jsonl`, the oldest file is dropped.
+#[derive(Debug, Clone)]
+pub struct TranscriptWriter {
+    path: PathBuf,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl TranscriptWriter {
+    pub fn new(conf: &Config) -> TranscriptWriter {
+        TranscriptWriter::with_limits(
+            &conf.transcript_file,
+            conf.transcript_max_bytes,
+            conf.transcript_max_files,
+        )
+    }
+
+    pub fn with_limits(path: &str, max_bytes: u64, max_files: usize) -> TranscriptWriter {
+        TranscriptWriter {
+            path: PathBuf::from(path),
+            max_bytes,
+            max_files,
+        }
+    }
+
+    pub fn write(&mut self, record: &TranscriptRecord) {
+        if let Err(e) = self.rotate_if_needed() {
+            tracing::error!("Error rotating transcripts: {}", e);
+        }
+        if let Err(e) = append_json_line(&self.path.to_string_lossy(), record) {
+            tracing::error!("Error writing transcript: {}", e);
+        }
+    }
+
+    fn rotate_if_needed(&self) -> std::io::Result<()> {
+        let size = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(_) => return Ok(()),
+        };
+        if size < self.max_bytes {
+            return Ok(());
+        }
+
+        let oldest = rotated_path(&self.path, self.max_files);
+        if oldest.exists() {
+            fs::remove_file(&oldest)?;
+        }
+        for index in (1..self.max_files).rev() {
+            let from = rotated_path(&self.path, index);
+            if from.exists() {
+                fs::rename(&from, rotated_path(&self.path, index + 1))?;
+            }
+        }
+        match self.max_files {
+            0 => fs::remove_file(&self.path),
+            _ => fs::rename(&self.path, rotated_path(&self.path, 1)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(content: &str) -> TranscriptRecord {
+        let messages = vec![
+            ChatMessage::user("hi".to_string()),
+            ChatMessage::system("be nice".to_string()),
+        ];
+        let response = serde_json::json!({
+            "model": "llama",
+            "created_at": "2025-01-01T00:00:00Z",
+            "message": {"role": "assistant", "content": content},
+            "done": true,
+        });
+        let response = serde_json::from_value::<ChatMessageResponse>(response).unwrap();
+        TranscriptRecord::new(
+            "reply",
+            "llama",
+            &messages,
+            None,
+            &Ok(response),
+            Duration::from_millis(1500),
+        )
+    }
+
+    #[test]
+    fn test_record_extracts_system_prompt_and_content() {
+        let record = record("hello");
+        assert_eq!(record.system_prompt.as_deref(), Some("be nice"));
+        assert_eq!(record.response_content().as_deref(), Some("hello"));
+        assert_eq!(record.latency_ms, 1500);
+    }
+
+    #[test]
+    fn test_writer_rotates_files() {
+        let dir = std::env::temp_dir().join(format!("transcripts-{}", Uuid::new_v4()));
+        let path = dir.join("transcripts.jsonl");
+        let mut writer = TranscriptWriter {
+            path: path.clone(),
+            max_bytes: 1,
+            max_files: 2,
+        };
+
+        for content in ["first", "second", "third", "fourth"] {
+            writer.write(&record(content));
+        }
+
+        let read = |p: PathBuf| read_json_lines::<TranscriptRecord>(&p.to_string_lossy());
+        assert_eq!(read(path.clone())[0].response_content().unwrap(), "fourth");
+        assert_eq!(
+            read(rotated_path(&path, 1))[0].response_content().unwrap(),
+            "third"
+        );
+        assert_eq!(
+            read(rotated_path(&path, 2))[0].response_content().unwrap(),
+            "second"
+        );
+        assert!(!rotated_path(&path, 3).exists());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
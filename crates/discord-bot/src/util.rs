@@ -1,6 +1,14 @@
 use super::*;
 
-use serenity::all::{CacheHttp, ChannelId, GuildId, User, UserId};
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serenity::all::{
+    CacheHttp, ChannelId, CommandInteraction, CreateInteractionResponse,
+    CreateInteractionResponseMessage, GuildId, Timestamp, User, UserId,
+};
+use serenity::prelude::*;
+use std::fs::OpenOptions;
+use std::io::{BufRead, BufReader};
 
 pub async fn get_channel_name(channel_id: &ChannelId, cache_http: impl CacheHttp) -> String {
     let undefined_channel = "In the middle of nowhere".to_string();
@@ -54,3 +62,161 @@ use unicode_segmentation::UnicodeSegmentation;
 pub fn count_symbols(s: &str) -> usize {
     s.graphemes(true).count()
 }
+
+pub fn timestamp_to_utc(timestamp: &Timestamp) -> DateTime<Utc> {
+    DateTime::from_timestamp(timestamp.unix_timestamp(), 0).unwrap_or_default()
+}
+
+/// Monospace table with a header and a separator line, cells padded by graphemes.
+pub fn format_table(header: &[&str], rows: &[Vec<String>]) -> String {
+    format_aligned_table(header, rows, &[])
+}
+
+/// Like [`format_table`], the columns flagged in `right_aligned` are padded on the left.
+pub fn format_aligned_table(
+    header: &[&str],
+    rows: &[Vec<String>],
+    right_aligned: &[bool],
+) -> String {
+    let mut widths = header
+        .iter()
+        .map(|h| count_symbols(h))
+        .collect::<Vec<usize>>();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(count_symbols(cell));
+        }
+    }
+
+    let format_row = |row: Vec<&str>, is_header: bool| {
+        row.iter()
+            .zip(widths.iter())
+            .enumerate()
+            .map(|(index, (cell, width))| {
+                let pad = width - count_symbols(cell);
+                match !is_header && right_aligned.get(index) == Some(&true) {
+                    true => format!("{:<pad$}{cell}", ""),
+                    false => format!("{cell}{:<pad$}", ""),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(" | ")
+    };
+    let mut lines = vec![format_row(header.to_vec(), true)];
+    lines.push(
+        widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<String>>()
+            .join("-|-"),
+    );
+    lines.extend(
+        rows.iter()
+            .map(|row| format_row(row.iter().map(String::as_str).collect(), false)),
+    );
+    lines.join("\n")
+}
+
+/// Appends a value as one line of a JSONL file, creating the file if needed.
+pub fn append_json_line<T: Serialize>(
+    file_path: &str,
+    value: &T,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+    if let Some(dir) = std::path::Path::new(file_path).parent() {
+        create_dir_all(dir)?;
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(file_path)?;
+    file.write_all(line.as_bytes())?;
+    Ok(())
+}
+
+/// Reads every valid line of a JSONL file, a missing file is an empty list.
+pub fn read_json_lines<T: serde::de::DeserializeOwned>(file_path: &str) -> Vec<T> {
+    let file = match File::open(file_path) {
+        Ok(file) => file,
+        Err(_) => return vec![],
+    };
+    BufReader::new(file)
+        .lines()
+        .map_while(Result::ok)
+        .filter_map(|line| match serde_json::from_str(&line) {
+            Ok(value) => Some(value),
+            Err(e) => {
+                tracing::warn!("Skipping invalid line in {}: {}", file_path, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Splits a text into Discord sized messages, preferring line breaks. A line longer than
+/// the limit is cut by characters.
+pub fn split_message(text: &str, max_chars: usize) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    for line in text.lines() {
+        let mut line = line.to_string();
+        while line.chars().count() > max_chars {
+            if !current.is_empty() {
+                parts.push(std::mem::take(&mut current));
+                current_len = 0;
+            }
+            let head = line.chars().take(max_chars).collect::<String>();
+            line = line.chars().skip(max_chars).collect();
+            parts.push(head);
+        }
+
+        let line_len = line.chars().count();
+        let separator = usize::from(!current.is_empty());
+        if current_len + separator + line_len > max_chars {
+            parts.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if !current.is_empty() {
+            current.push('\n');
+            current_len += 1;
+        }
+        current.push_str(&line);
+        current_len += line_len;
+    }
+    if !current.trim().is_empty() {
+        parts.push(current);
+    }
+
+    parts
+}
+
+/// Answers the command with a message only the caller sees.
+pub async fn respond_ephemeral(ctx: &Context, command: &CommandInteraction, content: &str) {
+    let message = CreateInteractionResponseMessage::new()
+        .content(content)
+        .ephemeral(true);
+    let builder = CreateInteractionResponse::Message(message);
+    if let Err(why) = command.create_response(&ctx.http, builder).await {
+        tracing::error!("Error responding to command: {why:?}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_message_by_lines() {
+        let parts = split_message("aaa\nbbb\ncc", 7);
+        assert_eq!(parts, ["aaa\nbbb", "cc"]);
+    }
+
+    #[test]
+    fn test_split_message_long_line() {
+        let parts = split_message("ab\nпривет мир", 4);
+        assert_eq!(parts, ["ab", "прив", "ет м", "ир"]);
+    }
+}
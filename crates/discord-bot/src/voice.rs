@@ -0,0 +1,360 @@
+use super::*;
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serenity::all::{ChannelId, Guild, Http, UserId, VoiceState};
+use serenity::prelude::*;
+use std::collections::{HashMap, HashSet};
+
+/// Someone in a voice channel. A move to another channel keeps the session going, only the
+/// channel time starts over.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct VoiceSession {
+    pub channel_id: ChannelId,
+    pub start: DateTime<Utc>,
+    pub channel_start: DateTime<Utc>,
+}
+
+/// Who is in voice right now. Kept in the statistics file, so after a restart the sessions
+/// are closed when the bot was last known to be running instead of being lost.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct VoicePresence {
+    pub sessions: HashMap<UserId, VoiceSession>,
+    pub alive_at: Option<DateTime<Utc>>, // the sessions were known to be open until then
+}
+
+impl VoicePresence {
+    pub fn touch(&mut self, now: DateTime<Utc>) {
+        self.alive_at = Some(now);
+    }
+
+    /// The user joined, moved to or left (`None`) a voice channel.
+    pub fn update(
+        &mut self,
+        periods: &mut HashMap<String, StatPeriod>,
+        user_id: UserId,
+        channel_id: Option<ChannelId>,
+        now: DateTime<Utc>,
+    ) {
+        self.touch(now);
+        let session = self.sessions.get(&user_id).copied();
+        match (session, channel_id) {
+            (Some(session), Some(channel_id)) if session.channel_id == channel_id => {}
+            (Some(session), Some(channel_id)) => {
+                credit(periods, user_id, &session, now, false);
+                self.sessions.insert(
+                    user_id,
+                    VoiceSession {
+                        channel_id,
+                        channel_start: now,
+                        ..session
+                    },
+                );
+            }
+            (Some(session), None) => {
+                credit(periods, user_id, &session, now, true);
+                self.sessions.remove(&user_id);
+            }
+            (None, Some(channel_id)) => {
+                let session = VoiceSession {
+                    channel_id,
+                    start: now,
+                    channel_start: now,
+                };
+                self.sessions.insert(user_id, session);
+                for period in periods.values_mut() {
+                    period
+                        .message_stat
+                        .record_voice_peak(self.sessions.len(), now);
+                }
+            }
+            (None, None) => {}
+        }
+    }
+
+    /// Matches the sessions in the guild's `channels` to everyone in voice there according to
+    /// Discord, after a (re)connect. Whoever left while the bot was away is credited until it
+    /// was last seen alive, like [`VoicePresence::close_stale`] does.
+    pub fn sync(
+        &mut self,
+        periods: &mut HashMap<String, StatPeriod>,
+        channels: &HashSet<ChannelId>,
+        present: &HashMap<UserId, ChannelId>,
+        now: DateTime<Utc>,
+    ) {
+        let alive_at = self.alive_at.unwrap_or(now);
+        let gone = self
+            .sessions
+            .iter()
+            .filter(|(user_id, session)| {
+                channels.contains(&session.channel_id) && !present.contains_key(user_id)
+            })
+            .map(|(user_id, _)| *user_id)
+            .collect::<Vec<UserId>>();
+        for user_id in gone {
+            if let Some(session) = self.sessions.remove(&user_id) {
+                credit(periods, user_id, &session, alive_at, true);
+            }
+        }
+        for (user_id, channel_id) in present {
+            self.update(periods, *user_id, Some(*channel_id), now);
+        }
+        self.touch(now);
+    }
+
+    /// Ends the sessions left open by the previous run when it was last seen alive, what
+    /// happened while the bot was down is unknown.
+    pub fn close_stale(&mut self, periods: &mut HashMap<String, StatPeriod>) {
+        let alive_at = self.alive_at;
+        for (user_id, session) in std::mem::take(&mut self.sessions) {
+            if let Some(alive_at) = alive_at {
+                credit(periods, user_id, &session, alive_at, true);
+            }
+        }
+    }
+
+    /// Counts the open sessions into the closing period up to its end, the next period
+    /// starts counting them from there.
+    pub fn close_period(&self, stat: &mut MessageStat, start: DateTime<Utc>, end: DateTime<Utc>) {
+        for (user_id, session) in self.sessions.iter() {
+            credit_period(stat, start, *user_id, session, end, true);
+        }
+    }
+
+    /// Whoever is in voice when a period starts is its first concurrent peak.
+    pub fn open_period(&self, stat: &mut MessageStat, start: DateTime<Utc>) {
+        stat.record_voice_peak(self.sessions.len(), start);
+    }
+
+    pub fn purge_user(&mut self, user_id: UserId) -> bool {
+        self.sessions.remove(&user_id).is_some()
+    }
+
+    pub fn purge_channel(&mut self, channel_id: ChannelId) -> bool {
+        let sessions = self.sessions.len();
+        self.sessions
+            .retain(|_, session| session.channel_id != channel_id);
+        self.sessions.len() != sessions
+    }
+}
+
+/// Adds the session time up to `end` to every running period, within each period's bounds.
+/// `ended` also offers it as the user's longest session.
+fn credit(
+    periods: &mut HashMap<String, StatPeriod>,
+    user_id: UserId,
+    session: &VoiceSession,
+    end: DateTime<Utc>,
+    ended: bool,
+) {
+    for period in periods.values_mut() {
+        let start = period.collection_start;
+        credit_period(
+            &mut period.message_stat,
+            start,
+            user_id,
+            session,
+            end,
+            ended,
+        );
+    }
+}
+
+fn credit_period(
+    stat: &mut MessageStat,
+    period_start: DateTime<Utc>,
+    user_id: UserId,
+    session: &VoiceSession,
+    end: DateTime<Utc>,
+    ended: bool,
+) {
+    let seconds = |from: DateTime<Utc>| (end - from.max(period_start)).num_seconds().max(0);
+    stat.record_voice(
+        user_id,
+        session.channel_id,
+        seconds(session.channel_start) as usize,
+    );
+    if ended {
+        stat.record_voice_session(user_id, seconds(session.start) as usize);
+    }
+}
+
+/// The voice channel the state counts in, `None` when the user left, is AFK or isn't counted.
+async fn counted_channel(
+    http: &Http,
+    conf: &Config,
+    channels: &Mutex<ChannelCache>,
+    state: &VoiceState,
+    bot: bool,
+) -> Option<ChannelId> {
+    let channel_id = state.channel_id?;
+    if (conf.stat_filter.ignore_bots && bot) || channels.lock().await.is_afk(channel_id) {
+        return None;
+    }
+    match stat_channel_allowed(http, conf, channels, channel_id, state.guild_id).await {
+        true => Some(channel_id),
+        false => None,
+    }
+}
+
+pub async fn record_voice_state(
+    http: &Http,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+    channels: &Mutex<ChannelCache>,
+    state: &VoiceState,
+) {
+    let bot = state.member.as_ref().is_some_and(|member| member.user.bot);
+    let channel_id = counted_channel(http, conf, channels, state, bot).await;
+    stat.lock()
+        .await
+        .record_voice(state.user_id, channel_id, Utc::now());
+}
+
+/// The voice states of the guild have no member. Without the members intent the guild
+/// doesn't list everyone in voice either, the others are asked for when bots are ignored.
+async fn is_bot(http: &Http, conf: &Config, guild: &Guild, user_id: UserId) -> bool {
+    if let Some(member) = guild.members.get(&user_id) {
+        return member.user.bot;
+    }
+    if !conf.stat_filter.ignore_bots {
+        return false;
+    }
+    match user_id.to_user(http).await {
+        Ok(user) => user.bot,
+        Err(e) => {
+            tracing::warn!("Error getting user {}: {:?}", user_id, e);
+            false
+        }
+    }
+}
+
+/// The guild arrives with everyone in voice when the bot connects.
+pub async fn sync_voice_states(
+    http: &Http,
+    conf: &Config,
+    stat: &Mutex<Stat>,
+    channels: &Mutex<ChannelCache>,
+    guild: &Guild,
+) {
+    let afk_channel = guild.afk_metadata.as_ref().map(|afk| afk.afk_channel_id);
+    channels.lock().await.set_afk_channel(afk_channel);
+    let mut present = HashMap::new();
+    for (user_id, state) in guild.voice_states.iter() {
+        let bot = is_bot(http, conf, guild, *user_id).await;
+        if let Some(channel_id) = counted_channel(http, conf, channels, state, bot).await {
+            present.insert(*user_id, channel_id);
+        }
+    }
+    let channels = guild.channels.keys().copied().collect();
+    stat.lock().await.sync_voice(&channels, present, Utc::now());
+}
+
+/// `3h 05m`, minutes only below an hour.
+pub fn format_voice_time(seconds: usize) -> String {
+    let minutes = seconds / 60;
+    match minutes >= 60 {
+        true => format!("{}h {:02}m", minutes / 60, minutes % 60),
+        false => format!("{minutes}m"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    #[test]
+    fn test_sessions_across_moves_and_periods() {
+        let (user, channel) = (UserId::new, ChannelId::new);
+        let mut periods = HashMap::from([(
+            "weekly".to_string(),
+            StatPeriod {
+                collection_start: at("2025-03-03T00:00:00Z"),
+                ..StatPeriod::default()
+            },
+        )]);
+        let mut voice = VoicePresence::default();
+        voice.update(
+            &mut periods,
+            user(1),
+            Some(channel(10)),
+            at("2025-03-03T20:00:00Z"),
+        );
+        voice.update(
+            &mut periods,
+            user(2),
+            Some(channel(10)),
+            at("2025-03-03T20:30:00Z"),
+        );
+        voice.update(
+            &mut periods,
+            user(1),
+            Some(channel(11)),
+            at("2025-03-03T21:00:00Z"),
+        );
+        voice.update(&mut periods, user(2), None, at("2025-03-03T21:00:00Z"));
+
+        let stat = &periods["weekly"].message_stat;
+        assert_eq!(stat.voice_seconds[&user(1)], 3600);
+        assert_eq!(stat.voice_seconds[&user(2)], 1800);
+        assert_eq!(stat.channel_voice[&channel(10)][&user(1)], 3600);
+        assert_eq!(stat.voice_longest.get(&user(1)), None);
+        assert_eq!(stat.voice_longest[&user(2)], 1800);
+        assert_eq!(stat.voice_peak, 2);
+
+        // The period ends with user 1 still in voice, the rest goes to the next one
+        let boundary = at("2025-03-03T22:00:00Z");
+        let period = periods.get_mut("weekly").unwrap();
+        voice.close_period(&mut period.message_stat, period.collection_start, boundary);
+        assert_eq!(period.message_stat.voice_seconds[&user(1)], 7200);
+        assert_eq!(period.message_stat.voice_longest[&user(1)], 7200);
+        period.collection_start = boundary;
+        period.message_stat = MessageStat::default();
+        voice.open_period(&mut period.message_stat, boundary);
+        assert_eq!(period.message_stat.voice_peak, 1);
+
+        // A restart closes the session when the bot was last alive
+        voice.touch(at("2025-03-03T22:30:00Z"));
+        voice.close_stale(&mut periods);
+        let stat = &periods["weekly"].message_stat;
+        assert_eq!(stat.voice_seconds[&user(1)], 1800);
+        assert_eq!(stat.channel_voice[&channel(11)][&user(1)], 1800);
+        assert!(voice.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_sync_credits_until_alive() {
+        let (user, channel) = (UserId::new, ChannelId::new);
+        let mut periods = HashMap::from([("weekly".to_string(), StatPeriod::default())]);
+        let mut voice = VoicePresence::default();
+        voice.update(
+            &mut periods,
+            user(1),
+            Some(channel(10)),
+            at("2025-03-03T20:00:00Z"),
+        );
+        voice.touch(at("2025-03-03T20:30:00Z"));
+        // Reconnected later, user 1 left while the bot was away
+        let channels = HashSet::from([channel(10)]);
+        voice.sync(
+            &mut periods,
+            &channels,
+            &HashMap::new(),
+            at("2025-03-03T22:00:00Z"),
+        );
+        let stat = &periods["weekly"].message_stat;
+        assert_eq!(stat.voice_seconds[&user(1)], 1800);
+        assert!(voice.sessions.is_empty());
+    }
+
+    #[test]
+    fn test_format_voice_time() {
+        assert_eq!(format_voice_time(59), "0m");
+        assert_eq!(format_voice_time(45 * 60), "45m");
+        assert_eq!(format_voice_time(3 * 3600 + 5 * 60), "3h 05m");
+    }
+}
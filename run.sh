@@ -13,4 +13,61 @@ ERROR_NO_MESSAGES=$(cat ./.config/error-no-messages) \
 TRIGGER_WORDS=$(cat ./.config/trigger-words) \
 TABLE_HEADER=$(cat ./.config/table-header) \
 TELOXIDE_TOKEN=$(cat ./.config/teloxide-token) \
+THREAD_MODE=$(cat ./.config/thread-mode) \
+THREAD_AUTO_ARCHIVE_MINUTES=$(cat ./.config/thread-auto-archive-minutes) \
+THREAD_CONTEXT_MESSAGES=$(cat ./.config/thread-context-messages) \
+FALLBACK_MODELS=$(cat ./.config/fallback-models) \
+OLLAMA_TIMEOUT_SECS=$(cat ./.config/ollama-timeout-secs) \
+OLLAMA_HEALTH_INTERVAL_SECS=$(cat ./.config/ollama-health-interval-secs) \
+ERROR_OLLAMA_TIMEOUT=$(cat ./.config/error-ollama-timeout) \
+ERROR_OLLAMA_MODEL_MISSING=$(cat ./.config/error-ollama-model-missing) \
+ERROR_OLLAMA_CONNECTION_REFUSED=$(cat ./.config/error-ollama-connection-refused) \
+PERSONA_NAME=$(cat ./.config/persona-name) \
+REPLIES_FILE=$(cat ./.config/replies-file) \
+FEEDBACK_FILE=$(cat ./.config/feedback-file) \
+FEEDBACK_TRACK_DAYS=$(cat ./.config/feedback-track-days) \
+OLLAMA_OPTIONS=$(cat ./.config/ollama-options) \
+TRANSCRIPT_FILE=$(cat ./.config/transcript-file) \
+TRANSCRIPT_MAX_BYTES=$(cat ./.config/transcript-max-bytes) \
+TRANSCRIPT_MAX_FILES=$(cat ./.config/transcript-max-files) \
+SUMMARIZE_DEFAULT_COUNT=$(cat ./.config/summarize-default-count) \
+SUMMARIZE_MAX_MESSAGES=$(cat ./.config/summarize-max-messages) \
+SUMMARIZE_CHUNK_CHARS=$(cat ./.config/summarize-chunk-chars) \
+SUMMARIZE_COOLDOWN_SECS=$(cat ./.config/summarize-cooldown-secs) \
+SUMMARIZE_PROMPT=$(cat ./.config/summarize-prompt) \
+SUMMARIZE_REDUCE_PROMPT=$(cat ./.config/summarize-reduce-prompt) \
+REPORT_CHANNEL_ID=$(cat ./.config/report-channel-id) \
+REPORT_SCHEDULES=$(cat ./.config/report-schedules) \
+STAT_TIMEZONE=$(cat ./.config/stat-timezone) \
+STAT_TIMEZONES=$(cat ./.config/stat-timezones) \
+STAT_FILE=$(cat ./.config/stat-file) \
+REPORT_LATE_NOTE=$(cat ./.config/report-late-note) \
+STAT_BACKUPS=$(cat ./.config/stat-backups) \
+STAT_AUTOSAVE_SECS=$(cat ./.config/stat-autosave-secs) \
+ARCHIVE_FILE=$(cat ./.config/archive-file) \
+REPORT_COLUMNS=$(cat ./.config/report-columns) \
+REPORT_IMAGE=$(cat ./.config/report-image) \
+REPORT_SORT=$(cat ./.config/report-sort) \
+REPORT_TOP=$(cat ./.config/report-top) \
+REPORT_MIN_MESSAGES=$(cat ./.config/report-min-messages) \
+STAT_IGNORE_BOTS=$(cat ./.config/stat-ignore-bots) \
+STAT_IGNORE_WEBHOOKS=$(cat ./.config/stat-ignore-webhooks) \
+STAT_IGNORE_DMS=$(cat ./.config/stat-ignore-dms) \
+STAT_ALLOW_CHANNELS=$(cat ./.config/stat-allow-channels) \
+STAT_DENY_CHANNELS=$(cat ./.config/stat-deny-channels) \
+STAT_ALLOW_CATEGORIES=$(cat ./.config/stat-allow-categories) \
+STAT_DENY_CATEGORIES=$(cat ./.config/stat-deny-categories) \
+STREAK_GAP_MINUTES=$(cat ./.config/streak-gap-minutes) \
+STREAK_BURST_MINUTES=$(cat ./.config/streak-burst-minutes) \
+STREAK_REPORT_TOP=$(cat ./.config/streak-report-top) \
+ACHIEVEMENTS=$(cat ./.config/achievements) \
+ACHIEVEMENTS_CHANNEL_ID=$(cat ./.config/achievements-channel-id) \
+ACHIEVEMENTS_COOLDOWN_SECS=$(cat ./.config/achievements-cooldown-secs) \
+RECORDS_CHANNEL_ID=$(cat ./.config/records-channel-id) \
+BACKFILL_PAGE_DELAY_MS=$(cat ./.config/backfill-page-delay-ms) \
+REPORT_CHANNELS_TOP=$(cat ./.config/report-channels-top) \
+REPORT_EMOJI_TOP=$(cat ./.config/report-emoji-top) \
+KARMA_WORDS=$(cat ./.config/karma-words) \
+KARMA_EMOJI=$(cat ./.config/karma-emoji) \
+KARMA_COOLDOWN_MINUTES=$(cat ./.config/karma-cooldown-minutes) \
 "$@"
\ No newline at end of file